@@ -1,11 +1,15 @@
 use qubx::{
     Qubx,
+    BackendType,
     StreamParameters,
     ProcessArg,
     DspProcessArg,
+    DspOptions,
     DspHybridType,
     DspPatchType,
     MasterPatchType,
+    QueueFullPolicy,
+    StreamQueueParams,
     qinterp::Interp,
     qoperations::envelope_to_signal,
     qsignals::{ QSignal, SignalMode, SignalParams, ComplexSignalParams },
@@ -31,16 +35,16 @@ pub fn sigenv_example() {
         ..StreamParameters::default()
     };
 
-    let mut q = Qubx::new(false);
+    let mut q = Qubx::new(false, BackendType::PortAudio);
     q.start_monitoring_active_processes();
 
-    let mut master_out = q.create_master_streamout(String::from("M1"), stream_params);
+    let mut master_out = q.create_master_streamout(String::from("M1"), stream_params).unwrap();
     let master_clos: MasterPatchType = Box::new(|frame| {
         frame.iter_mut().for_each(|sample| { *sample *= 0.7 })
     });
-    master_out.start(ProcessArg::PatchSpace(master_clos));
+    master_out.start(ProcessArg::PatchSpace(master_clos)).unwrap();
 
-    let mut dsp_process = q.create_parallel_dsp_process(String::from("M1"), false);
+    let mut dsp_process = q.create_parallel_dsp_process(String::from("M1"), false).unwrap();
 
     let duration = 1.1;
 
@@ -59,11 +63,11 @@ pub fn sigenv_example() {
     let env_shape = exp_env.into_envelope_object(&exponential_env_params);
 
     let enveloped_sine_signal = envelope_to_signal(&signal_sine, &env_shape).unwrap();
-    dsp_process.start(DspProcessArg::Source::<DspPatchType, DspHybridType>(enveloped_sine_signal.vector_signal));
+    dsp_process.start(DspProcessArg::Source::<DspPatchType, DspHybridType>(enveloped_sine_signal.vector_signal)).unwrap();
     std::thread::sleep(std::time::Duration::from_secs_f32(1.5));
 
     let enveloped_comp_signal = envelope_to_signal(&signal_comp, &env_shape).unwrap();
-    dsp_process.start(DspProcessArg::Source::<DspPatchType, DspHybridType>(enveloped_comp_signal.vector_signal));
+    dsp_process.start(DspProcessArg::Source::<DspPatchType, DspHybridType>(enveloped_comp_signal.vector_signal)).unwrap();
     std::thread::sleep(std::time::Duration::from_secs_f32(1.5));
 
     // let buffer = AudioBuffer::new(SR);
@@ -71,7 +75,9 @@ pub fn sigenv_example() {
     let buffer_clone = Arc::clone(&buffer_audio);
     let path: &str = "/Users/pm/AcaHub/AudioSamples/cane.wav";
     let audio = buffer_clone.lock().unwrap().to_audio_object(path).unwrap();
-    dsp_process.start(DspProcessArg::Source::<DspPatchType, DspHybridType>(audio.vector_signal.clone()));
+    // the file can have any channel numbers and sample rate: it is adapted to the master streamout
+    let options = DspOptions::from(&audio);
+    dsp_process.start_with(DspProcessArg::Source::<DspPatchType, DspHybridType>(audio.vector_signal.clone()), options).unwrap();
     std::thread::sleep(std::time::Duration::from_secs_f32(1.0));
 
     dsp_process.start(DspProcessArg::PatchSpace::<DspPatchType, DspHybridType>(Box::new(move || {
@@ -89,7 +95,7 @@ pub fn sigenv_example() {
             timer += 1.0;
         }
         signal
-    })));
+    }))).unwrap();
     std::thread::sleep(std::time::Duration::from_secs_f32(3.0));
 
     AudioBuffer::write_to_file("test", &audio).unwrap();
@@ -123,8 +129,22 @@ pub fn sigenv_example() {
             timer += 1.0;
         }
         signal
-    })));
+    }))).unwrap();
     std::thread::sleep(std::time::Duration::from_secs_f32(5.0));
 
+    // streaming mode: the sampler is read frame by frame into a bounded queue
+    let buffer_audio_stream = Arc::clone(&buffer_audio);
+    let path: &str = "/Users/pm/AcaHub/AudioSamples/cane.wav";
+    let mut audio = buffer_audio_stream.lock().unwrap().to_audio_object(path).unwrap();
+    audio.set_read_speed(0.5);
+    audio.set_read_again(true);
+    let params = StreamQueueParams { capacity: 4, policy: QueueFullPolicy::Block, ..Default::default() };
+    let stream_handle = dsp_process.start_stream(move |frame: &mut [f32]| {
+        frame.iter_mut().for_each(|sample| *sample = audio.procedural_sampler(Interp::Cubic));
+        true
+    }, params).unwrap();
+    std::thread::sleep(std::time::Duration::from_secs_f32(3.0));
+    stream_handle.stop();
+
     q.close_qubx();
 }
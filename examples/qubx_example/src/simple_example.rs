@@ -2,13 +2,36 @@
 
 use qubx::{ 
     Qubx, 
+    BackendType, 
     StreamParameters, 
     ProcessArg, 
     DspProcessArg, 
+    DspOptions, 
     DspPatchType, 
     DspHybridType, 
     DuplexPatchType, 
-    MasterPatchType 
+    InputPatchType,
+    MasterPatchType,
+    RecordTarget,
+    FrameParams,
+    FadeParams,
+    PerChannel,
+    QGraph,
+    SignalNode,
+    EnvelopeNode,
+    GainNode,
+    ProcessorNode,
+    QParam,
+    Smoothing,
+    Processor,
+    ProcessCommand,
+    QubxSession,
+    qbuffers::DelayBuffer,
+    qsignals::{ SignalParams, SignalMode },
+    qenvelopes::{ EnvParams, EnvMode },
+    qfilters::{ QFilter, FilterType, FilterParams },
+    filtertype::BiquadFilter,
+    qwindow::QWindow
 };
 use rand::Rng;
 use std::{ cmp::min, fs::File, thread, time::Duration };
@@ -44,7 +67,32 @@ const FILES: [&str; 2] = [
 
 enum TestMode {
     Input,
+    Record,
     Output,
+    Graph,
+    Commands,
+    Session,
+}
+
+// gain with a live param (id 0) and a mute event (id 0)
+struct LiveGain {
+    gain: f32,
+    muted: bool
+}
+
+impl Processor for LiveGain {
+    fn process(&mut self, input: &[f32], output: &mut [f32]) {
+        let gain = if self.muted { 0.0 } else { self.gain };
+        output.iter_mut().zip(input.iter()).for_each(|(y, x)| *y = x * gain);
+    }
+
+    fn set_param(&mut self, id: usize, value: f32) {
+        if id == 0 { self.gain = value }
+    }
+
+    fn trigger(&mut self, event: usize) {
+        if event == 0 { self.muted = !self.muted }
+    }
 }
 
 fn simple_example() {
@@ -60,7 +108,7 @@ fn simple_example() {
         ..StreamParameters::default()
     };
 
-    let mut q = Qubx::new(false);
+    let mut q = Qubx::new(false, BackendType::PortAudio);
 
     // start monitor active processes
     q.start_monitoring_active_processes();
@@ -68,25 +116,41 @@ fn simple_example() {
 
     match mode {
         TestMode::Input => {
-            let mut duplex = q.create_duplex_dsp_process(stream_params);
+            let mut duplex = q.create_duplex_dsp_process(stream_params).unwrap();
             let clos: DuplexPatchType = Box::new(|frame| frame.to_vec());
-            duplex.start(ProcessArg::PatchSpace(clos));
+            duplex.start(ProcessArg::PatchSpace(clos)).unwrap();
 
             for i in 0..(10 * SR as usize) {
                 std::thread::sleep(std::time::Duration::from_secs_f32(1.0 / SR as f32)); 
             }
         }
 
+        TestMode::Record => {
+            let mut input = q.create_input_process(stream_params).unwrap();
+            let handle = input.record(ProcessArg::NoArgs::<InputPatchType>, RecordTarget::File(String::from("input_take"))).unwrap();
+
+            thread::sleep(Duration::from_secs(10));
+            handle.stop();
+        }
+
         TestMode::Output => {
-            let mut master_out = q.create_master_streamout(String::from("M1"), stream_params);
-            let master_clos: MasterPatchType = Box::new(|frame| {
-                frame.iter_mut().for_each(|sample| { *sample *= 0.7 }) 
+            let mut master_out = q.create_master_streamout(String::from("M1"), stream_params).unwrap();
+            // master gain set from this thread, smoothed in the audio thread
+            let master_gain = QParam::new(0.7, Smoothing::Linear(0.05));
+            let mut gain_reader = master_gain.get_reader(SR as f32);
+            let master_clos: MasterPatchType = Box::new(move |frame| {
+                frame.chunks_mut(CHANNELS as usize).for_each(|f| {
+                    let gain = gain_reader.next_value();
+                    f.iter_mut().for_each(|sample| { *sample *= gain })
+                })
             });
 
-            master_out.start(ProcessArg::PatchSpace(master_clos));
+            master_out.start(ProcessArg::PatchSpace(master_clos)).unwrap();
+            // print the mix to disk while it plays
+            master_out.start_recording(RecordTarget::File(String::from("master_mix"))).unwrap();
 
-            let mut dsp_process1 = q.create_parallel_dsp_process(String::from("M1"), true);
-            let mut dsp_process2 = q.create_parallel_dsp_process(String::from("M1"), true);
+            let mut dsp_process1 = q.create_parallel_dsp_process(String::from("M1"), true).unwrap();
+            let mut dsp_process2 = q.create_parallel_dsp_process(String::from("M1"), true).unwrap();
 
             let audio1 = open_file(FILES[0]);
             let audio2 = open_file(FILES[1]);
@@ -130,8 +194,16 @@ fn simple_example() {
                  	y
                 });
 
-                dsp_process1.start(DspProcessArg::HybridSpace::<DspPatchType, DspHybridType>(audio_data1, dsp_clos));
-                dsp_process2.start(DspProcessArg::Source::<DspPatchType, DspHybridType>(audio_data2));
+                // frames of 2048 samples (independent of the chunk) with 75% overlap
+                let frames = FrameParams { frame_size: Some(2048), hop_size: Some(512), window: Some(QWindow::Hanning) };
+                // 0.5 sec fade-in, 2 sec fade-out in the mix
+                dsp_process1.start_with(
+                    DspProcessArg::HybridSpace::<DspPatchType, DspHybridType>(audio_data1, dsp_clos),
+                    DspOptions::default().with_frames(frames).with_fades(FadeParams::new(0.5, 2.0))
+                ).unwrap();
+                // stateful processor: 100 ms delay on each channel
+                let delay = PerChannel::new(|| DelayBuffer::new(4410));
+                dsp_process2.start(DspProcessArg::Processor::<DspPatchType, DspHybridType>(audio_data2, Box::new(delay))).unwrap();
                 
                 if count >= 30 {
                     run = false
@@ -145,6 +217,107 @@ fn simple_example() {
                 let delay = rng.gen_range(0.5..1.0);
                 thread::sleep(Duration::from_secs_f32(delay));
             }
+
+            // the running sources fade out in 1 sec and leave the mix
+            dsp_process1.release(None);
+            dsp_process2.release(Some(1.0));
+            thread::sleep(Duration::from_secs(2));
+
+            // fade out
+            master_gain.set(0.0);
+            thread::sleep(Duration::from_millis(100));
+            master_out.stop_recording().unwrap();
+        }
+
+        TestMode::Graph => {
+            let mut master_out = q.create_master_streamout(String::from("M1"), stream_params).unwrap();
+            master_out.start(ProcessArg::NoArgs::<MasterPatchType>).unwrap();
+            let mut dsp_process = q.create_parallel_dsp_process(String::from("M1"), true).unwrap();
+
+            // oscillator -> vca (envelope) -> lowpass -> out
+            let graph = QGraph::new(SR as f32, CHUNK as usize, 0, CHANNELS as usize);
+            let osc = graph.add_node(SignalNode::new(SignalParams::new(SignalMode::Saw, 110.0, 0.5, 0.0, SR as f32)));
+            let env = graph.add_node(EnvelopeNode::new(EnvParams::new(vec![0.0, 0.05, 1.0, 1.5, 0.0], EnvMode::Linear)));
+            let vca = graph.add_node(GainNode::new(1, 1.0));
+            let lowpass = graph.add_node(ProcessorNode::new(PerChannel::new(|| {
+                let mut filter = QFilter::new(SR as f32).get_filter(FilterType::Biquad);
+                filter.design_filter(FilterParams::BiquadParams(BiquadFilter::Lp, 800.0, 0.707, None)).unwrap();
+                filter
+            }), 1, CHANNELS as usize));
+
+            graph.connect(osc, 0, vca, 0).unwrap();
+            graph.connect(env, 0, vca, 1).unwrap();
+            graph.connect(vca, 0, lowpass, 0).unwrap();
+            graph.connect(lowpass, 0, graph.get_output(), 0).unwrap();
+
+            for freq in [110.0, 165.0, 220.0, 330.0] {
+                graph.with_node(osc, |osc: &mut SignalNode| osc.get_params_mut().freq = freq).unwrap();
+                graph.with_node(env, |env: &mut EnvelopeNode| env.trigger()).unwrap();
+                dsp_process.start(DspProcessArg::PatchSpace::<DspPatchType, DspHybridType>(graph.get_dsp_patch(SR as usize * 2))).unwrap();
+                thread::sleep(Duration::from_secs(2));
+            }
+        }
+        TestMode::Commands => {
+            let mut master_out = q.create_master_streamout(String::from("M1"), stream_params).unwrap();
+            master_out.start(ProcessArg::Processor::<MasterPatchType>(Box::new(LiveGain { gain: 1.0, muted: false }))).unwrap();
+            let mut dsp_process = q.create_parallel_dsp_process(String::from("M1"), true).unwrap();
+            let audio_data = open_file(FILES[0]);
+            dsp_process.start(DspProcessArg::Source::<DspPatchType, DspHybridType>(audio_data)).unwrap();
+
+            // the commands are sent from another thread and applied at the top of the next callback
+            let commands = master_out.get_command_sender();
+            let control = thread::spawn(move || {
+                for gain in [0.8, 0.5, 0.2] {
+                    commands.send(ProcessCommand::SetParam(0, gain)).unwrap();
+                    thread::sleep(Duration::from_secs(1));
+                }
+                commands.send(ProcessCommand::Trigger(0)).unwrap();
+                thread::sleep(Duration::from_secs(1));
+
+                let lowpass = PerChannel::new(|| {
+                    let mut filter = QFilter::new(SR as f32).get_filter(FilterType::Biquad);
+                    filter.design_filter(FilterParams::BiquadParams(BiquadFilter::Lp, 500.0, 0.707, None)).unwrap();
+                    filter
+                });
+                commands.send(ProcessCommand::SwapProcessor(Box::new(lowpass))).unwrap();
+            });
+            control.join().unwrap();
+            thread::sleep(Duration::from_secs(3));
+
+            // A/B: crossfade (2 sec) to a soft clipper, then back to the unprocessed mix
+            master_out.swap_patch(|frame: &mut [f32]| frame.iter_mut().for_each(|sample| *sample = (*sample * 4.0).tanh() * 0.5), 2.0).unwrap();
+            thread::sleep(Duration::from_secs(4));
+            master_out.swap_patch(|_frame: &mut [f32]| { }, 2.0).unwrap();
+            thread::sleep(Duration::from_secs(3));
+        }
+        TestMode::Session => {
+            // masters, buses and sources are declared in the session file
+            let mut session = QubxSession::load("./session.toml").unwrap();
+            session.get_master("M1").unwrap().start(ProcessArg::NoArgs::<MasterPatchType>).unwrap();
+
+            // feedback delay on the bus (stereo, 250 ms)
+            let mut delay = vec![0.0; 2 * SR as usize / 4];
+            let mut index = 0;
+            session.get_bus("M1", "echo").unwrap().set_effect(Box::new(move |frame| {
+                frame.iter_mut().for_each(|sample| {
+                    let y = *sample + delay[index] * 0.5;
+                    delay[index] = y;
+                    index = (index + 1) % delay.len();
+                    *sample = y;
+                })
+            }));
+
+            session.start_source("blues").unwrap();
+            thread::sleep(Duration::from_secs(4));
+            session.start_source("vox").unwrap();
+            thread::sleep(Duration::from_secs(10));
+            session.get_source("blues").unwrap().release(None);
+            session.get_source("vox").unwrap().release(None);
+            thread::sleep(Duration::from_secs(3));
+
+            session.save("./session_copy.json").unwrap();
+            session.close();
+            return
         }
     }
 
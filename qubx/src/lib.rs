@@ -1,9 +1,24 @@
 mod qubx;
 mod qlist;
+mod qring;
+mod qpool;
+mod qtransport;
+mod qstrip;
+mod qrouting;
 mod qubx_components;
 mod qubx_common;
 mod qubx_pmanage;
+mod qubx_control;
+mod qmetrics;
+mod qdevices;
+mod qrecorder;
+mod qprocessor;
+mod qgraph;
+mod qparams;
+mod qcommand;
+mod qsession;
 mod qubx_types;
+mod qubx_backend;
 mod qmod {
     pub mod qenvelopes;
     pub mod qsignals;
@@ -26,8 +41,41 @@ mod qmod {
 // --- PUB USE ---
 
 pub use qubx::Qubx;
-pub use qubx_common::{ StreamParameters, ProcessArg, DspProcessArg };
+pub use qubx_common::{
+    StreamParameters, ProcessArg, DspProcessArg, DspOptions, StartTime, OfflineParams, RenderTarget, QubxError, QueueFullPolicy,
+    StreamQueueParams, FrameParams, SourceFormat, RecordTarget, FadeParams
+};
 pub use qubx_components::*;
+pub use qpool::{ FramePool, PoolFrame };
+pub use qtransport::{ Transport, BarBeat };
+pub use qstrip::ChannelStrip;
+pub use qprocessor::{ Processor, PerChannel };
+pub use qgraph::{
+    QGraph, GraphNode, GraphError, NodeId, PortType, Ports, ProcessorNode, SignalNode, EnvelopeNode, GainNode,
+    ParamNode
+};
+pub use qparams::{ QParam, ParamReader, Smoothing };
+pub use qcommand::{ command_channel, CommandReceiver, CommandSender, ProcessCommand, ProcessCommandSender };
+pub use qsession::{
+    QubxSession, SessionConfig, SessionBackend, SessionError, MasterConfig, DuplexConfig, SourceConfig
+};
+pub use qrouting::{ QubxBus, RouteTarget, RoutingError };
+pub use qubx_control::{ ProcessControl, ProcessHandle };
+pub use qmetrics::{ ProcessMetrics, MetricsSnapshot };
+pub use qdevices::DeviceDescriptor;
+pub use qubx_backend::{
+    AudioBackend,
+    BackendType,
+    BackendError,
+    BackendFactory,
+    CallbackFlow,
+    OutputCallback,
+    DuplexCallback,
+    OfflineRender,
+    PortAudioBackend,
+    NullBackend,
+    FileSinkBackend
+};
 pub use qmod::qenvelopes;
 pub use qmod::qsignals;
 pub use qubx_types::*;
@@ -0,0 +1,409 @@
+#![allow(dead_code)]
+
+use std::sync::Arc;
+use concurrent_queue::ConcurrentQueue;
+
+use crate::qubx_common::{ ProcessArg, QubxError };
+use crate::qubx_types::ProcessorType;
+
+pub(crate) const COMMANDS_CAPACITY: usize = 256;
+
+/// Command sent to a running master or duplex stream (see `QubxMasterProcess::send_command()`). Commands are drained
+/// at the top of every callback, in order
+///
+/// `SetParam(id, value)`: call `Processor::set_param()` of a `ProcessArg::Processor`
+/// `Trigger(event)`: call `Processor::trigger()` of a `ProcessArg::Processor`
+/// `SwapProcessor(processor)`: replace the arg of the stream (closure or processor) with a new processor. The processor
+/// is prepared by the sender (not in the audio thread) and the old arg is dropped outside the audio thread
+/// `Crossfade(processor, time)`: like `SwapProcessor`, but the old and the new arg run in parallel for `time` sec with
+/// an equal-power crossfade (see `Crossfade`)
+///
+/// Closures (`ProcessArg::PatchSpace`) ignore `SetParam` and `Trigger`: they can drain their own typed channel (see
+/// `command_channel()`)
+///
+#[derive(Debug)]
+pub enum ProcessCommand {
+    SetParam(usize, f32),
+    Trigger(usize),
+    SwapProcessor(ProcessorType),
+    Crossfade(ProcessorType, f32)
+}
+
+/// Create a typed lock-free channel (bounded MPMC queue). The receiver is moved into a closure and drained at the top
+/// of each call, the senders can be cloned and used from any thread
+///
+/// # Args
+/// ------
+///
+/// `capacity`: max number of pending messages
+///
+/// # Return
+/// --------
+///
+/// `(CommandSender<T>, CommandReceiver<T>)`
+///
+/// Example:
+/// ```ignore
+/// enum Synth { Freq(f32), Mute(bool) }
+///
+/// let (sender, receiver) = command_channel::<Synth>(64);
+/// let master_clos: MasterPatchType = Box::new(move |frame| {
+///     receiver.drain(|message| match message {
+///         Synth::Freq(freq) => { ... },
+///         Synth::Mute(mute) => { ... }
+///     });
+///     ...
+/// });
+///
+/// sender.send(Synth::Freq(440.0))?;
+/// ```
+///
+pub fn command_channel<T: Send>(capacity: usize) -> (CommandSender<T>, CommandReceiver<T>) {
+    let queue = Arc::new(ConcurrentQueue::bounded(capacity.max(1)));
+    (CommandSender { queue: Arc::clone(&queue) }, CommandReceiver { queue })
+}
+
+/// # Command Sender
+///
+/// Sending side of a typed channel (see `command_channel()`). It never blocks
+///
+pub struct CommandSender<T> {
+    queue: Arc<ConcurrentQueue<T>>
+}
+
+impl<T> Clone for CommandSender<T> {
+    fn clone(&self) -> Self {
+        Self { queue: Arc::clone(&self.queue) }
+    }
+}
+
+impl<T> std::fmt::Debug for CommandSender<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CommandSender").field("pending", &self.queue.len()).finish()
+    }
+}
+
+impl<T> CommandSender<T> {
+    /// Send a message
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), QubxError>`: `QubxError::QueueFull` if the receiver is late (the message is dropped)
+    ///
+    pub fn send(&self, message: T) -> Result<(), QubxError> {
+        self.queue.push(message).map_err(|_| QubxError::QueueFull(String::from("command channel")))
+    }
+
+    /// Number of messages not yet received
+    ///
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+/// # Command Receiver
+///
+/// Receiving side of a typed channel (see `command_channel()`). Receiving is real-time safe (no locks, no
+/// allocations), but dropping a message that owns memory frees it in the receiving thread
+///
+pub struct CommandReceiver<T> {
+    queue: Arc<ConcurrentQueue<T>>
+}
+
+impl<T> std::fmt::Debug for CommandReceiver<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CommandReceiver").field("pending", &self.queue.len()).finish()
+    }
+}
+
+impl<T> CommandReceiver<T> {
+    pub fn try_recv(&self) -> Option<T> {
+        self.queue.pop().ok()
+    }
+
+    /// Receive all the pending messages
+    ///
+    pub fn drain<F: FnMut(T)>(&self, mut f: F) {
+        while let Ok(message) = self.queue.pop() {
+            f(message);
+        }
+    }
+}
+
+/// # Process Command Sender
+///
+/// Send `ProcessCommand`s to a master or duplex stream from any thread (see `QubxMasterProcess::get_command_sender()`).
+/// New processors are prepared with the stream format before sending
+///
+#[derive(Debug, Clone)]
+pub struct ProcessCommandSender {
+    queue: Arc<ConcurrentQueue<ProcessCommand>>,
+    name: String,
+    sr: f32,
+    block_size: usize,
+    n_channels: usize
+}
+
+impl ProcessCommandSender {
+    /// Send a command
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), QubxError>`: `QubxError::QueueFull` if the stream is not draining its commands (e.g. not started)
+    ///
+    pub fn send(&self, command: ProcessCommand) -> Result<(), QubxError> {
+        let mut command = command;
+        if let ProcessCommand::SwapProcessor(ref mut processor) | ProcessCommand::Crossfade(ref mut processor, _) = command {
+            processor.prepare(self.sr, self.block_size, self.n_channels);
+        }
+        self.queue.push(command).map_err(|_| QubxError::QueueFull(format!("{} commands", self.name)))
+    }
+}
+
+/// Commands of a master or duplex stream. The queue survives across restarts
+///
+#[derive(Debug)]
+pub(crate) struct ProcessCommands {
+    queue: Arc<ConcurrentQueue<ProcessCommand>>
+}
+
+impl Default for ProcessCommands {
+    fn default() -> Self {
+        Self { queue: Arc::new(ConcurrentQueue::bounded(COMMANDS_CAPACITY)) }
+    }
+}
+
+impl ProcessCommands {
+    pub(crate) fn get_sender(&self, name: &str, sr: f32, block_size: usize, n_channels: usize) -> ProcessCommandSender {
+        ProcessCommandSender { queue: Arc::clone(&self.queue), name: name.to_string(), sr, block_size, n_channels }
+    }
+
+    pub(crate) fn get_queue(&self) -> Arc<ConcurrentQueue<ProcessCommand>> {
+        Arc::clone(&self.queue)
+    }
+}
+
+/// Number of args a command can retire (a crossfade retires the arg fading out and, with no fade time, the replaced
+/// one)
+///
+const RETIRED_PER_COMMAND: usize = 2;
+
+/// Check if the retired queue can take the args replaced by one more command
+///
+#[inline]
+fn has_room<F>(retired: &ConcurrentQueue<ProcessArg<F>>) -> bool {
+    retired.len() + RETIRED_PER_COMMAND <= retired.capacity().unwrap_or(usize::MAX)
+}
+
+/// # Crossfade
+///
+/// Real-time state of a hot-swap: the old arg keeps running next to the new one for the crossfade length, then it is
+/// retired. Gains follow an equal-power law (`cos`/`sin`), so uncorrelated patches keep a constant loudness. A new
+/// swap during a crossfade retires the arg that is fading out
+///
+/// Retired args are never dropped in the audio thread: when the retired queue is full the arg is kept in `retiring`
+/// and pushed again at the top of the next callback (see `apply_commands()`)
+///
+pub(crate) struct Crossfade<F> {
+    old: Option<ProcessArg<F>>,
+    retiring: Option<ProcessArg<F>>,
+    sr: f32,
+    length: usize,
+    position: usize
+}
+
+impl<F> Crossfade<F> {
+    pub(crate) fn new(sr: f32) -> Self {
+        Self { old: None, retiring: None, sr, length: 0, position: 0 }
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        self.old.is_some()
+    }
+
+    /// Arg that is fading out
+    ///
+    pub(crate) fn get_old_mut(&mut self) -> Option<&mut ProcessArg<F>> {
+        self.old.as_mut()
+    }
+
+    /// Move a replaced arg out of the audio thread (dropped by the stream thread), or keep it until the retired queue
+    /// has room
+    ///
+    fn retire(&mut self, old: ProcessArg<F>, retired: &ConcurrentQueue<ProcessArg<F>>) {
+        if let Err(e) = retired.push(old) {
+            // commands wait while an arg is kept: only the end of a crossfade can get here with the slot taken
+            debug_assert!(self.retiring.is_none());
+            self.retiring = Some(e.into_inner());
+        }
+    }
+
+    /// Push the kept arg again (see `retire()`)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `bool`: `true` if no arg is kept anymore
+    ///
+    fn flush(&mut self, retired: &ConcurrentQueue<ProcessArg<F>>) -> bool {
+        if let Some(old) = self.retiring.take() {
+            if let Err(e) = retired.push(old) {
+                self.retiring = Some(e.into_inner());
+                return false
+            }
+        }
+        true
+    }
+
+    /// Start fading out `old` over `time` sec (`time <= 0` retires it immediately)
+    ///
+    fn start(&mut self, old: ProcessArg<F>, time: f32, retired: &ConcurrentQueue<ProcessArg<F>>) {
+        self.cancel(retired);
+        let length = (time * self.sr).round();
+        if length < 1.0 {
+            self.retire(old, retired);
+            return
+        }
+        self.old = Some(old);
+        self.length = length as usize;
+        self.position = 0;
+    }
+
+    /// Stop the crossfade and drop the old args, outside the audio thread (e.g. on restart)
+    ///
+    pub(crate) fn reset(&mut self) {
+        self.old = None;
+        self.retiring = None;
+    }
+
+    /// Stop the crossfade and retire the old arg
+    ///
+    fn cancel(&mut self, retired: &ConcurrentQueue<ProcessArg<F>>) {
+        if let Some(old) = self.old.take() {
+            self.retire(old, retired);
+        }
+    }
+
+    /// Mix the old output into the new one (interleaved blocks of the same length). The old arg is retired at the end
+    /// of the crossfade
+    ///
+    pub(crate) fn mix(&mut self, new: &mut [f32], old: &[f32], n_channels: usize, retired: &ConcurrentQueue<ProcessArg<F>>) {
+        let n_channels = n_channels.max(1);
+        for (i, (y_new, y_old)) in new.chunks_mut(n_channels).zip(old.chunks(n_channels)).enumerate() {
+            let t = ((self.position + i) as f32 / self.length as f32).min(1.0) * std::f32::consts::FRAC_PI_2;
+            let (g_old, g_new) = (t.cos(), t.sin());
+            y_new.iter_mut().zip(y_old.iter()).for_each(|(a, b)| *a = *a * g_new + *b * g_old);
+        }
+        self.position += new.len() / n_channels;
+        if self.position >= self.length {
+            self.cancel(retired);
+        }
+    }
+}
+
+/// Apply the pending commands to the arg of a running stream (real-time safe). Replaced args are moved to `retired`
+/// and dropped by the stream thread. Commands wait in the queue while `retired` is full, so that no arg is dropped in
+/// the audio thread
+///
+pub(crate) fn apply_commands<F>(
+    queue: &ConcurrentQueue<ProcessCommand>,
+    arg: &mut ProcessArg<F>,
+    fade: &mut Crossfade<F>,
+    retired: &ConcurrentQueue<ProcessArg<F>>
+) {
+    if !fade.flush(retired) {
+        return
+    }
+    while has_room(retired) {
+        let Ok(command) = queue.pop() else { break };
+        match command {
+            ProcessCommand::SetParam(id, value) => {
+                if let ProcessArg::Processor(processor) = arg {
+                    processor.set_param(id, value);
+                }
+            },
+            ProcessCommand::Trigger(event) => {
+                if let ProcessArg::Processor(processor) = arg {
+                    processor.trigger(event);
+                }
+            },
+            ProcessCommand::SwapProcessor(processor) => {
+                let old = std::mem::replace(arg, ProcessArg::Processor(processor));
+                fade.retire(old, retired);
+            },
+            ProcessCommand::Crossfade(processor, time) => {
+                let old = std::mem::replace(arg, ProcessArg::Processor(processor));
+                fade.start(old, time, retired);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qprocessor::InPlacePatch;
+
+    type Patch = fn(&mut [f32]);
+
+    fn gain(gain: f32) -> ProcessorType {
+        Box::new(InPlacePatch(move |frame: &mut [f32]| frame.iter_mut().for_each(|sample| *sample *= gain)))
+    }
+
+    fn fill(retired: &ConcurrentQueue<ProcessArg<Patch>>) {
+        while retired.push(ProcessArg::NoArgs).is_ok() { }
+    }
+
+    #[test]
+    fn commands_wait_for_retired_room() {
+        let queue = ConcurrentQueue::bounded(4);
+        let retired = ConcurrentQueue::bounded(2);
+        let mut arg: ProcessArg<Patch> = ProcessArg::NoArgs;
+        let mut fade = Crossfade::new(4.0);
+
+        fill(&retired);
+        queue.push(ProcessCommand::SwapProcessor(gain(2.0))).unwrap();
+        apply_commands(&queue, &mut arg, &mut fade, &retired);
+        assert_eq!(queue.len(), 1);
+        assert!(matches!(arg, ProcessArg::NoArgs));
+
+        while retired.pop().is_ok() { }
+        apply_commands(&queue, &mut arg, &mut fade, &retired);
+        assert!(queue.is_empty());
+        assert!(matches!(arg, ProcessArg::Processor(_)));
+        assert_eq!(retired.len(), 1);
+    }
+
+    #[test]
+    fn keep_faded_arg_until_retired() {
+        let queue = ConcurrentQueue::bounded(4);
+        let retired = ConcurrentQueue::bounded(3);
+        let mut arg: ProcessArg<Patch> = ProcessArg::NoArgs;
+        let mut fade = Crossfade::new(4.0);
+
+        queue.push(ProcessCommand::Crossfade(gain(2.0), 1.0)).unwrap();
+        apply_commands(&queue, &mut arg, &mut fade, &retired);
+        assert!(fade.is_active());
+
+        // the crossfade ends with a full retired queue: the old arg is kept and the next commands wait
+        fill(&retired);
+        let (mut new, old) = (vec![1.0; 4], vec![0.0; 4]);
+        fade.mix(&mut new, &old, 1, &retired);
+        assert!(!fade.is_active());
+        assert!(fade.retiring.is_some());
+        let expected = (0..4).map(|i| (i as f32 / 4.0 * std::f32::consts::FRAC_PI_2).sin());
+        assert!(new.iter().zip(expected).all(|(y, g)| (y - g).abs() < 1e-6));
+
+        queue.push(ProcessCommand::SwapProcessor(gain(0.5))).unwrap();
+        apply_commands(&queue, &mut arg, &mut fade, &retired);
+        assert_eq!(queue.len(), 1);
+
+        while retired.pop().is_ok() { }
+        apply_commands(&queue, &mut arg, &mut fade, &retired);
+        assert!(fade.retiring.is_none());
+        assert!(queue.is_empty());
+        assert_eq!(retired.len(), 2);
+    }
+}
@@ -0,0 +1,245 @@
+#![allow(dead_code)]
+
+use portaudio as pa;
+
+use crate::qubx_backend::BackendError;
+
+/// # Device Descriptor
+///
+/// Audio device reported by PortAudio (see `Qubx::get_devices()`)
+///
+/// `index`: device index (it can change when devices are added or removed, prefer the name in session configs)
+/// `name`: device name
+/// `host_api`: host API name (CoreAudio, ALSA, WASAPI...)
+/// `max_input_channels`: max number of input channels
+/// `max_output_channels`: max number of output channels
+/// `default_sample_rate`: default sample rate
+/// `default_low_input_latency`, `default_low_output_latency`: default latency in sec. for interactive use
+/// `default_high_input_latency`, `default_high_output_latency`: default latency in sec. for robust playback
+/// `is_default_input`, `is_default_output`: the device is the default input/output device
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceDescriptor {
+    pub index: u32,
+    pub name: String,
+    pub host_api: String,
+    pub max_input_channels: u32,
+    pub max_output_channels: u32,
+    pub default_sample_rate: f64,
+    pub default_low_input_latency: f64,
+    pub default_low_output_latency: f64,
+    pub default_high_input_latency: f64,
+    pub default_high_output_latency: f64,
+    pub is_default_input: bool,
+    pub is_default_output: bool
+}
+
+impl DeviceDescriptor {
+    /// Check if the device matches a name: exact match (case insensitive) or substring
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `pattern`: device name or part of it
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Option<bool>`: `Some(true)` exact match, `Some(false)` substring match, `None` no match
+    ///
+    pub fn matches(&self, pattern: &str) -> Option<bool> {
+        let name = self.name.to_lowercase();
+        let pattern = pattern.to_lowercase();
+        if name == pattern {
+            Some(true)
+        } else if name.contains(&pattern) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+/// Get all the devices seen by a PortAudio instance
+///
+pub(crate) fn list_devices(port_audio: &pa::PortAudio) -> Result<Vec<DeviceDescriptor>, BackendError> {
+    let default_input = port_audio.default_input_device().ok();
+    let default_output = port_audio.default_output_device().ok();
+    let devices = port_audio.devices().map_err(|e| BackendError::DeviceError(e.to_string()))?;
+
+    let mut descriptors = Vec::new();
+    for device in devices {
+        let (index, info) = device.map_err(|e| BackendError::DeviceError(e.to_string()))?;
+        let host_api = port_audio
+            .host_api_info(info.host_api)
+            .map(|host| host.name.to_string())
+            .unwrap_or_default();
+
+        descriptors.push(DeviceDescriptor {
+            index: index.0,
+            name: info.name.to_string(),
+            host_api,
+            max_input_channels: info.max_input_channels.max(0) as u32,
+            max_output_channels: info.max_output_channels.max(0) as u32,
+            default_sample_rate: info.default_sample_rate,
+            default_low_input_latency: info.default_low_input_latency,
+            default_low_output_latency: info.default_low_output_latency,
+            default_high_input_latency: info.default_high_input_latency,
+            default_high_output_latency: info.default_high_output_latency,
+            is_default_input: default_input == Some(index),
+            is_default_output: default_output == Some(index)
+        });
+    }
+
+    Ok(descriptors)
+}
+
+/// Select a device by name. Exact matches (case insensitive) come first, then substrings. Devices without enough
+/// channels in the requested direction are skipped. A substring matching devices with different names is refused
+/// (the same device listed by several host APIs is not ambiguous: the first one is selected)
+///
+/// # Args
+/// ------
+///
+/// `devices`: available devices
+/// `pattern`: device name or part of it
+/// `input`: look for an input (true) or an output (false) device
+/// `nchannels`: number of channels required
+///
+/// # Return
+/// --------
+///
+/// `Result<u32, BackendError>`: device index, `BackendError::DeviceError` if no device or more than one device matches
+///
+pub(crate) fn select_device(devices: &[DeviceDescriptor], pattern: &str, input: bool, nchannels: u32) -> Result<u32, BackendError> {
+    let candidates = devices.iter().filter(|device| {
+        let channels = if input { device.max_input_channels } else { device.max_output_channels };
+        channels >= nchannels.max(1)
+    });
+
+    let mut substrings: Vec<&DeviceDescriptor> = Vec::new();
+    for device in candidates {
+        match device.matches(pattern) {
+            Some(true) => return Ok(device.index),
+            Some(false) => substrings.push(device),
+            None => { }
+        }
+    }
+
+    let direction = if input { "input" } else { "output" };
+    let Some(first) = substrings.first() else {
+        return Err(BackendError::DeviceError(format!("no {} device matching \"{}\" with {} channels", direction, pattern, nchannels)))
+    };
+    let mut names = substrings.iter().map(|device| format!("\"{}\"", device.name)).collect::<Vec<String>>();
+    names.sort();
+    names.dedup();
+    if names.len() > 1 {
+        return Err(BackendError::DeviceError(format!("{} device name \"{}\" is ambiguous ({})", direction, pattern, names.join(", "))))
+    }
+    Ok(first.index)
+}
+
+/// Resolve the device of a stream: the name (if set) takes precedence over the index, then the default device
+///
+pub(crate) fn resolve_device(
+    port_audio: &pa::PortAudio,
+    name: Option<&str>,
+    index: Option<u32>,
+    input: bool,
+    nchannels: u32
+) -> Result<pa::DeviceIndex, BackendError> {
+    if let Some(pattern) = name {
+        let devices = list_devices(port_audio)?;
+        return select_device(&devices, pattern, input, nchannels).map(pa::DeviceIndex)
+    }
+
+    match index {
+        Some(index) => Ok(pa::DeviceIndex(index)),
+        None if input => port_audio.default_input_device().map_err(|e| BackendError::DeviceError(e.to_string())),
+        None => port_audio.default_output_device().map_err(|e| BackendError::DeviceError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(index: u32, name: &str, host_api: &str, inputs: u32, outputs: u32) -> DeviceDescriptor {
+        DeviceDescriptor {
+            index,
+            name: name.to_string(),
+            host_api: host_api.to_string(),
+            max_input_channels: inputs,
+            max_output_channels: outputs,
+            default_sample_rate: 44100.0,
+            default_low_input_latency: 0.01,
+            default_low_output_latency: 0.01,
+            default_high_input_latency: 0.1,
+            default_high_output_latency: 0.1,
+            is_default_input: false,
+            is_default_output: false
+        }
+    }
+
+    fn devices() -> Vec<DeviceDescriptor> {
+        vec![
+            device(0, "Built-in Microphone", "Core Audio", 2, 0),
+            device(1, "Built-in Output", "Core Audio", 0, 2),
+            device(2, "Scarlett 2i2 USB", "Core Audio", 2, 2),
+            device(3, "Scarlett 18i20 USB", "Core Audio", 18, 20),
+            device(4, "USB Audio", "MME", 1, 2),
+            device(5, "USB Audio", "WASAPI", 1, 2)
+        ]
+    }
+
+    fn error_message(result: Result<u32, BackendError>) -> String {
+        match result {
+            Err(BackendError::DeviceError(e)) => e,
+            other => panic!("unexpected result {:?}", other)
+        }
+    }
+
+    #[test]
+    fn match_name() {
+        let device = device(0, "Scarlett 2i2 USB", "Core Audio", 2, 2);
+        assert_eq!(device.matches("Scarlett 2i2 USB"), Some(true));
+        assert_eq!(device.matches("scarlett 2I2 usb"), Some(true));
+        assert_eq!(device.matches("2i2"), Some(false));
+        assert_eq!(device.matches("SCARLETT"), Some(false));
+        assert_eq!(device.matches("18i20"), None);
+    }
+
+    #[test]
+    fn select_by_name() {
+        let devices = devices();
+        assert_eq!(select_device(&devices, "built-in output", false, 2).unwrap(), 1);
+        assert_eq!(select_device(&devices, "2I2", false, 2).unwrap(), 2);
+        assert_eq!(select_device(&devices, "microphone", true, 1).unwrap(), 0);
+        // the exact name wins over the substrings
+        assert_eq!(select_device(&devices, "usb audio", false, 2).unwrap(), 4);
+        // devices without enough channels are skipped
+        assert_eq!(select_device(&devices, "scarlett", false, 8).unwrap(), 3);
+        // the same device listed by several host APIs
+        assert_eq!(select_device(&devices, "audio", true, 1).unwrap(), 4);
+    }
+
+    #[test]
+    fn ambiguous_name() {
+        let devices = devices();
+        let e = error_message(select_device(&devices, "scarlett", false, 2));
+        assert_eq!(e, "output device name \"scarlett\" is ambiguous (\"Scarlett 18i20 USB\", \"Scarlett 2i2 USB\")");
+        // only one of the devices matching the name is an input device
+        assert_eq!(select_device(&devices, "built-in", true, 1).unwrap(), 0);
+        assert_eq!(select_device(&devices, "built-in", false, 1).unwrap(), 1);
+    }
+
+    #[test]
+    fn device_not_found() {
+        let devices = devices();
+        let e = error_message(select_device(&devices, "focusrite", false, 2));
+        assert_eq!(e, "no output device matching \"focusrite\" with 2 channels");
+        // the name matches, but not the direction or the channels
+        assert!(select_device(&devices, "built-in output", true, 1).is_err());
+        assert!(select_device(&devices, "2i2", true, 4).is_err());
+    }
+}
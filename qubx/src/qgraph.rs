@@ -0,0 +1,953 @@
+#![allow(dead_code)]
+
+use std::any::Any;
+use std::sync::{ Arc, Mutex };
+use rayon::prelude::*;
+
+use crate::qprocessor::Processor;
+use crate::qparams::{ ParamReader, QParam };
+use crate::qenvelopes::{ AdsrParams, EnvParams, QEnvelope };
+use crate::qsignals::{ QSignal, SignalParams };
+use crate::qubx_types::{ DspPatchType, DuplexPatchType, MasterPatchType };
+
+const INPUT_NODE: usize = 0;
+const OUTPUT_NODE: usize = 1;
+
+/// Graph errors
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum GraphError {
+    NodeNotFound(usize),
+    PortNotFound { node: usize, port: usize },
+    PortMismatch { from: PortType, to: PortType },
+    EdgeNotFound,
+    CycleDetected(usize, usize),
+    IoNode(usize),
+    NodeType(usize)
+}
+
+impl std::fmt::Display for GraphError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NodeNotFound(node) => write!(f, "node {} not found", node),
+            Self::PortNotFound { node, port } => write!(f, "port {} of node {} not found", port, node),
+            Self::PortMismatch { from, to } => write!(f, "can not connect {:?} port to {:?} port", from, to),
+            Self::EdgeNotFound => write!(f, "connection not found"),
+            Self::CycleDetected(from, to) => write!(f, "connection {} -> {} creates a cycle", from, to),
+            Self::IoNode(node) => write!(f, "node {} is the input or the output of the graph", node),
+            Self::NodeType(node) => write!(f, "node {} has another type", node)
+        }
+    }
+}
+
+/// Port type of a graph node
+///
+/// `Audio(n_channels)`: interleaved audio with `n_channels` channels
+/// `Control`: mono control signal at audio rate (one value per frame)
+///
+/// Ports can be connected only to ports of the same type (and the same number of channels)
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PortType {
+    Audio(usize),
+    Control
+}
+
+impl PortType {
+    pub fn get_channels(&self) -> usize {
+        match self {
+            Self::Audio(n_channels) => *n_channels,
+            Self::Control => 1
+        }
+    }
+}
+
+/// Node id in a `QGraph` (see `QGraph::add_node()`)
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    pub fn get_index(&self) -> usize {
+        self.0
+    }
+}
+
+/// # Ports
+///
+/// Buffers of the input or output ports of a node for the current block. Each port holds `get_frames()` frames of
+/// `get_channels(port)` interleaved channels. An input port connected to more outputs receives their sum, an input
+/// port not connected is silent (see `is_connected()`)
+///
+#[derive(Debug, Default)]
+pub struct Ports {
+    buffers: Vec<Vec<f32>>,
+    channels: Vec<usize>,
+    connected: Vec<bool>,
+    frames: usize
+}
+
+impl Ports {
+    fn new(types: &[PortType]) -> Self {
+        Self {
+            buffers: vec![Vec::new(); types.len()],
+            channels: types.iter().map(|port| port.get_channels()).collect(),
+            connected: vec![false; types.len()],
+            frames: 0
+        }
+    }
+
+    fn allocate(&mut self, block_size: usize) {
+        for (buffer, channels) in self.buffers.iter_mut().zip(self.channels.iter()) {
+            *buffer = vec![0.0; block_size * channels];
+        }
+    }
+
+    fn clear(&mut self) {
+        self.buffers.iter_mut().for_each(|buffer| buffer.fill(0.0));
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty()
+    }
+
+    pub fn get_frames(&self) -> usize {
+        self.frames
+    }
+
+    pub fn get_channels(&self, port: usize) -> usize {
+        self.channels[port]
+    }
+
+    pub fn is_connected(&self, port: usize) -> bool {
+        self.connected[port]
+    }
+
+    pub fn get(&self, port: usize) -> &[f32] {
+        &self.buffers[port][..self.frames * self.channels[port]]
+    }
+
+    pub fn get_mut(&mut self, port: usize) -> &mut [f32] {
+        &mut self.buffers[port][..self.frames * self.channels[port]]
+    }
+}
+
+/// # Graph Node
+///
+/// Node of a `QGraph` with typed input and output ports (see `PortType`). Ports are read once, when the node is added
+/// to the graph
+///
+/// `prepare()`: called when the node is added and when the graph is prepared, with the sample rate and the max number
+/// of frames of a block
+/// `process()`: called for each block, in topological order. Write all the output ports
+/// `reset()`: clear the internal state
+///
+pub trait GraphNode: Any + Send {
+    fn get_inputs(&self) -> Vec<PortType>;
+
+    fn get_outputs(&self) -> Vec<PortType>;
+
+    fn prepare(&mut self, _sr: f32, _block_size: usize) { }
+
+    fn process(&mut self, inputs: &Ports, outputs: &mut Ports);
+
+    fn reset(&mut self) { }
+}
+
+/// Input and output of the graph (the graph copies the audio in and out)
+///
+struct IoNode {
+    inputs: Vec<PortType>,
+    outputs: Vec<PortType>
+}
+
+impl GraphNode for IoNode {
+    fn get_inputs(&self) -> Vec<PortType> {
+        self.inputs.clone()
+    }
+
+    fn get_outputs(&self) -> Vec<PortType> {
+        self.outputs.clone()
+    }
+
+    fn process(&mut self, _inputs: &Ports, _outputs: &mut Ports) { }
+}
+
+/// # Processor Node
+///
+/// Any `Processor` as a graph node (`Filter`, `DelayBuffer`, `SpaceProcessor`, `QModulation`, `QGranulator`, `PerChannel`...)
+///
+/// Ports: input 0 `Audio(in_channels)`, output 0 `Audio(out_channels)`
+///
+pub struct ProcessorNode<P: Processor> {
+    processor: P,
+    in_channels: usize,
+    out_channels: usize
+}
+
+impl<P: Processor> ProcessorNode<P> {
+    /// Create a processor node
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `processor`: processor (prepared with `out_channels`)
+    /// `in_channels`: channels of the input port (0 for generators)
+    /// `out_channels`: channels of the output port
+    ///
+    pub fn new(processor: P, in_channels: usize, out_channels: usize) -> Self {
+        Self { processor, in_channels, out_channels }
+    }
+
+    pub fn get_processor_mut(&mut self) -> &mut P {
+        &mut self.processor
+    }
+}
+
+impl<P: Processor + 'static> GraphNode for ProcessorNode<P> {
+    fn get_inputs(&self) -> Vec<PortType> {
+        vec![PortType::Audio(self.in_channels)]
+    }
+
+    fn get_outputs(&self) -> Vec<PortType> {
+        vec![PortType::Audio(self.out_channels)]
+    }
+
+    fn prepare(&mut self, sr: f32, block_size: usize) {
+        self.processor.prepare(sr, block_size, self.out_channels);
+    }
+
+    fn process(&mut self, inputs: &Ports, outputs: &mut Ports) {
+        self.processor.process(inputs.get(0), outputs.get_mut(0));
+    }
+
+    fn reset(&mut self) {
+        self.processor.reset();
+    }
+}
+
+/// # Signal Node
+///
+/// Procedural oscillator (see `SignalParams`)
+///
+/// Ports: input 0 `Control` (frequency in Hz), input 1 `Control` (amplitude), output 0 `Audio(1)`. If a control input
+/// is not connected, the value of the params is used
+///
+pub struct SignalNode {
+    params: SignalParams
+}
+
+impl SignalNode {
+    pub fn new(params: SignalParams) -> Self {
+        Self { params }
+    }
+
+    pub fn get_params_mut(&mut self) -> &mut SignalParams {
+        &mut self.params
+    }
+}
+
+impl GraphNode for SignalNode {
+    fn get_inputs(&self) -> Vec<PortType> {
+        vec![PortType::Control, PortType::Control]
+    }
+
+    fn get_outputs(&self) -> Vec<PortType> {
+        vec![PortType::Audio(1)]
+    }
+
+    fn prepare(&mut self, sr: f32, _block_size: usize) {
+        self.params.sr = sr;
+    }
+
+    fn process(&mut self, inputs: &Ports, outputs: &mut Ports) {
+        let (freq, amp) = (inputs.is_connected(0), inputs.is_connected(1));
+        for (i, sample) in outputs.get_mut(0).iter_mut().enumerate() {
+            if freq { self.params.freq = inputs.get(0)[i] }
+            if amp { self.params.amp = inputs.get(1)[i] }
+            *sample = QSignal::procedural_oscillator(&mut self.params);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.params.reset_signal_history();
+    }
+}
+
+/// # Envelope Node
+///
+/// Envelope generator (see `EnvParams` and `AdsrParams`). At the end of the shape it holds the last value
+///
+/// Ports: input 0 `Control` (trigger: the envelope restarts when the input rises above 0), output 0 `Control`
+///
+pub struct EnvelopeNode {
+    envelope: QEnvelope,
+    params: EnvParams,
+    gate: f32
+}
+
+impl EnvelopeNode {
+    pub fn new(params: EnvParams) -> Self {
+        Self { envelope: QEnvelope::new(44100.0), params, gate: 0.0 }
+    }
+
+    pub fn from_adsr(params: &AdsrParams) -> Self {
+        Self::new(params.get_env_params())
+    }
+
+    /// Restart the envelope from the beginning
+    ///
+    pub fn trigger(&mut self) {
+        self.envelope = QEnvelope::new(self.envelope.sr);
+    }
+}
+
+impl GraphNode for EnvelopeNode {
+    fn get_inputs(&self) -> Vec<PortType> {
+        vec![PortType::Control]
+    }
+
+    fn get_outputs(&self) -> Vec<PortType> {
+        vec![PortType::Control]
+    }
+
+    fn prepare(&mut self, sr: f32, _block_size: usize) {
+        if self.envelope.sr != sr {
+            self.envelope = QEnvelope::new(sr);
+        }
+    }
+
+    fn process(&mut self, inputs: &Ports, outputs: &mut Ports) {
+        let gated = inputs.is_connected(0);
+        for (i, sample) in outputs.get_mut(0).iter_mut().enumerate() {
+            if gated {
+                let gate = inputs.get(0)[i];
+                if gate > 0.0 && self.gate <= 0.0 {
+                    self.trigger();
+                }
+                self.gate = gate;
+            }
+            *sample = self.envelope.advance_envelope(&self.params, false).unwrap_or(0.0);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.gate = 0.0;
+        self.trigger();
+    }
+}
+
+/// # Param Node
+///
+/// Smoothed parameter as a control signal (see `QParam`). The param can be set from any thread while the graph runs
+///
+/// Ports: output 0 `Control`
+///
+pub struct ParamNode {
+    reader: ParamReader
+}
+
+impl ParamNode {
+    pub fn new(param: &QParam) -> Self {
+        Self { reader: param.get_reader(44100.0) }
+    }
+}
+
+impl GraphNode for ParamNode {
+    fn get_inputs(&self) -> Vec<PortType> {
+        Vec::new()
+    }
+
+    fn get_outputs(&self) -> Vec<PortType> {
+        vec![PortType::Control]
+    }
+
+    fn prepare(&mut self, sr: f32, _block_size: usize) {
+        self.reader.set_sr(sr);
+    }
+
+    fn process(&mut self, _inputs: &Ports, outputs: &mut Ports) {
+        self.reader.fill(outputs.get_mut(0));
+    }
+
+    fn reset(&mut self) {
+        self.reader.reset();
+    }
+}
+
+/// # Gain Node
+///
+/// Multiply audio by a control signal (VCA)
+///
+/// Ports: input 0 `Audio(n_channels)`, input 1 `Control` (gain), output 0 `Audio(n_channels)`. If the gain is not
+/// connected, the fixed gain is used
+///
+pub struct GainNode {
+    n_channels: usize,
+    gain: f32
+}
+
+impl GainNode {
+    pub fn new(n_channels: usize, gain: f32) -> Self {
+        Self { n_channels, gain }
+    }
+
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain
+    }
+}
+
+impl GraphNode for GainNode {
+    fn get_inputs(&self) -> Vec<PortType> {
+        vec![PortType::Audio(self.n_channels), PortType::Control]
+    }
+
+    fn get_outputs(&self) -> Vec<PortType> {
+        vec![PortType::Audio(self.n_channels)]
+    }
+
+    fn process(&mut self, inputs: &Ports, outputs: &mut Ports) {
+        let n_channels = self.n_channels.max(1);
+        let connected = inputs.is_connected(1);
+        let (x, gain) = (inputs.get(0), inputs.get(1));
+        for (i, sample) in outputs.get_mut(0).iter_mut().enumerate() {
+            let g = if connected { gain[i / n_channels] } else { self.gain };
+            *sample = x[i] * g;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Edge {
+    from: usize,
+    out_port: usize,
+    to: usize,
+    in_port: usize
+}
+
+/// Node in processing order with the outputs summed into each input port
+///
+#[derive(Debug, Clone)]
+struct PlanNode {
+    node: usize,
+    sources: Vec<Edge>
+}
+
+struct NodeSlot {
+    node: Box<dyn GraphNode>,
+    inputs: Ports,
+    outputs: Ports
+}
+
+impl NodeSlot {
+    fn process(&mut self, frames: usize) {
+        self.inputs.frames = frames;
+        self.outputs.frames = frames;
+        self.node.process(&self.inputs, &mut self.outputs);
+    }
+}
+
+struct GraphState {
+    nodes: Vec<Option<NodeSlot>>,
+    edges: Vec<Edge>,
+    levels: Vec<Vec<PlanNode>>,
+    running: Vec<(usize, NodeSlot)>,
+    sr: f32,
+    block_size: usize,
+    in_channels: usize,
+    out_channels: usize,
+    parallel: bool
+}
+
+impl GraphState {
+    fn get_slot(&self, node: usize) -> Result<&NodeSlot, GraphError> {
+        self.nodes.get(node).and_then(|slot| slot.as_ref()).ok_or(GraphError::NodeNotFound(node))
+    }
+
+    /// Check if `to` is reachable from `from`
+    ///
+    fn reaches(&self, from: usize, to: usize) -> bool {
+        let mut visited = vec![false; self.nodes.len()];
+        let mut stack = vec![from];
+        while let Some(node) = stack.pop() {
+            if node == to { return true }
+            if visited[node] { continue }
+            visited[node] = true;
+            stack.extend(self.edges.iter().filter(|edge| edge.from == node).map(|edge| edge.to));
+        }
+        false
+    }
+
+    /// Processing order: each node comes after the nodes it reads. Nodes of the same level do not depend on each
+    /// other and can be processed in parallel (Kahn algorithm)
+    ///
+    fn update_plan(&mut self) {
+        let n = self.nodes.len();
+        let mut indegree = vec![0usize; n];
+        for edge in self.edges.iter() {
+            indegree[edge.to] += 1;
+        }
+
+        let mut ready: Vec<usize> = (0..n).filter(|node| self.nodes[*node].is_some() && indegree[*node] == 0).collect();
+        let mut levels = Vec::new();
+        while !ready.is_empty() {
+            let mut next = Vec::new();
+            let mut level = Vec::with_capacity(ready.len());
+            for node in ready {
+                for edge in self.edges.iter().filter(|edge| edge.from == node) {
+                    indegree[edge.to] -= 1;
+                    if indegree[edge.to] == 0 { next.push(edge.to) }
+                }
+                let sources = self.edges.iter().filter(|edge| edge.to == node).copied().collect();
+                level.push(PlanNode { node, sources });
+            }
+            levels.push(level);
+            ready = next;
+        }
+
+        for (index, slot) in self.nodes.iter_mut().enumerate() {
+            if let Some(slot) = slot {
+                for (port, connected) in slot.inputs.connected.iter_mut().enumerate() {
+                    *connected = self.edges.iter().any(|edge| edge.to == index && edge.in_port == port);
+                }
+            }
+        }
+
+        let max_level = levels.iter().map(|level| level.len()).max().unwrap_or(0);
+        self.running = Vec::with_capacity(max_level);
+        self.levels = levels;
+    }
+
+    fn prepare_slot(slot: &mut NodeSlot, sr: f32, block_size: usize) {
+        slot.node.prepare(sr, block_size);
+        slot.inputs.allocate(block_size);
+        slot.outputs.allocate(block_size);
+    }
+
+    /// Render a block of `frames` frames (at most `block_size`)
+    ///
+    fn render(&mut self, input: &[f32], output: &mut [f32], frames: usize) {
+        let GraphState { nodes, levels, running, parallel, in_channels, out_channels, .. } = self;
+
+        // graph input: channels of the stream input are repeated if they are fewer
+        if let Some(slot) = nodes[INPUT_NODE].as_mut() {
+            slot.outputs.frames = frames;
+            let in_stride = input.len().checked_div(frames).unwrap_or(0);
+            let x = slot.outputs.get_mut(0);
+            for (i, sample) in x.iter_mut().enumerate() {
+                let (frame, channel) = (i / *in_channels, i % *in_channels);
+                *sample = if in_stride > 0 { input[frame * in_stride + channel % in_stride] } else { 0.0 };
+            }
+        }
+
+        for level in levels.iter() {
+            for plan in level.iter() {
+                let Some(mut slot) = nodes[plan.node].take() else { continue };
+                slot.inputs.frames = frames;
+                for port in 0..slot.inputs.len() {
+                    slot.inputs.get_mut(port).fill(0.0);
+                }
+                for edge in plan.sources.iter() {
+                    if let Some(source) = nodes[edge.from].as_ref() {
+                        let y = &source.outputs.buffers[edge.out_port][..frames * source.outputs.channels[edge.out_port]];
+                        slot.inputs.get_mut(edge.in_port).iter_mut().zip(y.iter()).for_each(|(x, y)| *x += y);
+                    }
+                }
+                running.push((plan.node, slot));
+            }
+
+            if *parallel && running.len() > 1 {
+                running.par_iter_mut().for_each(|(_, slot)| slot.process(frames));
+            } else {
+                running.iter_mut().for_each(|(_, slot)| slot.process(frames));
+            }
+
+            for (node, slot) in running.drain(..) {
+                nodes[node] = Some(slot);
+            }
+        }
+
+        // graph output
+        if let Some(slot) = nodes[OUTPUT_NODE].as_ref() {
+            let y = &slot.inputs.buffers[0][..frames * *out_channels];
+            output[..y.len()].copy_from_slice(y);
+        }
+    }
+}
+
+/// # Q Graph
+///
+/// Audio processing graph. Nodes (see `GraphNode`) have typed audio and control ports (see `PortType`) and are
+/// connected at runtime. Each block is rendered in topological order; nodes of independent branches can be processed
+/// in parallel on the rayon pool (see `set_parallel()`).
+///
+/// The graph has an input node (output port 0: the audio of the stream) and an output node (input port 0: the audio
+/// sent to the stream). It implements `Processor`, so it can run as a master, duplex, input or dsp process
+/// (`ProcessArg::Processor`, `DspProcessArg::Processor`), or as a closure (see `get_master_patch()`,
+/// `get_duplex_patch()`, `get_dsp_patch()`).
+///
+/// `QGraph` is a handle: clones share the same graph. Changes lock the graph, so the audio thread outputs silence
+/// for the block in which a change happens
+///
+/// Example:
+/// ```ignore
+/// let graph = QGraph::new(44100.0, 1024, 0, 2);
+/// let osc = graph.add_node(SignalNode::new(SignalParams::new(SignalMode::Sine, 220.0, 0.5, 0.0, 44100.0)));
+/// let env = graph.add_node(EnvelopeNode::new(EnvParams::new(vec![0.0, 0.1, 1.0, 2.0, 0.0], EnvMode::Linear)));
+/// let vca = graph.add_node(GainNode::new(1, 1.0));
+/// let echo = graph.add_node(ProcessorNode::new(PerChannel::new(|| DelayBuffer::new(11025)), 1, 2));
+///
+/// graph.connect(osc, 0, vca, 0)?;
+/// graph.connect(env, 0, vca, 1)?;
+/// graph.connect(vca, 0, echo, 0)?;
+/// graph.connect(echo, 0, graph.get_output(), 0)?;
+///
+/// dsp_process.start(DspProcessArg::PatchSpace::<DspPatchType, DspHybridType>(graph.get_dsp_patch(44100 * 3)))?;
+/// ```
+///
+#[derive(Clone)]
+pub struct QGraph {
+    state: Arc<Mutex<GraphState>>
+}
+
+impl std::fmt::Debug for QGraph {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("QGraph")
+            .field("nodes", &state.nodes.iter().filter(|slot| slot.is_some()).count())
+            .field("edges", &state.edges)
+            .field("in_channels", &state.in_channels)
+            .field("out_channels", &state.out_channels)
+            .finish()
+    }
+}
+
+impl QGraph {
+    /// Create an empty graph
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `sr`: sample rate
+    /// `block_size`: max number of frames of a block (e.g. the chunk of the stream)
+    /// `in_channels`: channels of the graph input (0 for generators)
+    /// `out_channels`: channels of the graph output
+    ///
+    pub fn new(sr: f32, block_size: usize, in_channels: usize, out_channels: usize) -> Self {
+        let input = IoNode { inputs: Vec::new(), outputs: vec![PortType::Audio(in_channels)] };
+        let output = IoNode { inputs: vec![PortType::Audio(out_channels)], outputs: Vec::new() };
+
+        let mut state = GraphState {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            levels: Vec::new(),
+            running: Vec::new(),
+            sr,
+            block_size: block_size.max(1),
+            in_channels,
+            out_channels,
+            parallel: false
+        };
+        for node in [input, output] {
+            let mut slot = NodeSlot { inputs: Ports::new(&node.get_inputs()), outputs: Ports::new(&node.get_outputs()), node: Box::new(node) };
+            GraphState::prepare_slot(&mut slot, sr, state.block_size);
+            state.nodes.push(Some(slot));
+        }
+        state.update_plan();
+
+        Self { state: Arc::new(Mutex::new(state)) }
+    }
+
+    /// Input node of the graph (output port 0: `Audio(in_channels)`)
+    ///
+    pub fn get_input(&self) -> NodeId {
+        NodeId(INPUT_NODE)
+    }
+
+    /// Output node of the graph (input port 0: `Audio(out_channels)`)
+    ///
+    pub fn get_output(&self) -> NodeId {
+        NodeId(OUTPUT_NODE)
+    }
+
+    pub fn get_in_channels(&self) -> usize {
+        self.state.lock().unwrap().in_channels
+    }
+
+    pub fn get_out_channels(&self) -> usize {
+        self.state.lock().unwrap().out_channels
+    }
+
+    /// Process the nodes of independent branches in parallel (rayon)
+    ///
+    pub fn set_parallel(&self, parallel: bool) {
+        self.state.lock().unwrap().parallel = parallel;
+    }
+
+    /// Add a node to the graph. It is prepared with the sample rate and the block size of the graph
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `node`: any `GraphNode`
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `NodeId`
+    ///
+    pub fn add_node<N: GraphNode>(&self, node: N) -> NodeId {
+        let mut slot = NodeSlot { inputs: Ports::new(&node.get_inputs()), outputs: Ports::new(&node.get_outputs()), node: Box::new(node) };
+        let mut state = self.state.lock().unwrap();
+        GraphState::prepare_slot(&mut slot, state.sr, state.block_size);
+        state.nodes.push(Some(slot));
+        state.update_plan();
+        NodeId(state.nodes.len() - 1)
+    }
+
+    /// Remove a node and its connections
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), GraphError>`
+    ///
+    pub fn remove_node(&self, node: NodeId) -> Result<(), GraphError> {
+        if node.0 == INPUT_NODE || node.0 == OUTPUT_NODE {
+            return Err(GraphError::IoNode(node.0))
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.get_slot(node.0)?;
+        state.nodes[node.0] = None;
+        state.edges.retain(|edge| edge.from != node.0 && edge.to != node.0);
+        state.update_plan();
+        Ok(())
+    }
+
+    /// Connect an output port to an input port. An input port can receive more outputs (they are summed)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `from`, `out_port`: source node and output port
+    /// `to`, `in_port`: destination node and input port
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), GraphError>`: the ports do not exist, have different types or the connection creates a cycle
+    ///
+    pub fn connect(&self, from: NodeId, out_port: usize, to: NodeId, in_port: usize) -> Result<(), GraphError> {
+        let mut state = self.state.lock().unwrap();
+        let out_type = state.get_slot(from.0)?.node.get_outputs().get(out_port).copied()
+            .ok_or(GraphError::PortNotFound { node: from.0, port: out_port })?;
+        let in_type = state.get_slot(to.0)?.node.get_inputs().get(in_port).copied()
+            .ok_or(GraphError::PortNotFound { node: to.0, port: in_port })?;
+
+        if out_type != in_type {
+            return Err(GraphError::PortMismatch { from: out_type, to: in_type })
+        }
+        if from == to || state.reaches(to.0, from.0) {
+            return Err(GraphError::CycleDetected(from.0, to.0))
+        }
+
+        let edge = Edge { from: from.0, out_port, to: to.0, in_port };
+        if !state.edges.contains(&edge) {
+            state.edges.push(edge);
+            state.update_plan();
+        }
+        Ok(())
+    }
+
+    /// Remove a connection
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), GraphError>`: `GraphError::EdgeNotFound` if the ports are not connected
+    ///
+    pub fn disconnect(&self, from: NodeId, out_port: usize, to: NodeId, in_port: usize) -> Result<(), GraphError> {
+        let mut state = self.state.lock().unwrap();
+        let edge = Edge { from: from.0, out_port, to: to.0, in_port };
+        let Some(index) = state.edges.iter().position(|e| *e == edge) else {
+            return Err(GraphError::EdgeNotFound)
+        };
+        state.edges.remove(index);
+        state.update_plan();
+        Ok(())
+    }
+
+    /// Access a node of the graph (e.g. to change its params). The graph is locked while `f` runs
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `node`: node id
+    /// `f`: closure called with the node (of type `N`)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<R, GraphError>`: `GraphError::NodeType` if the node is not a `N`
+    ///
+    /// Example:
+    /// ```ignore
+    /// graph.with_node(vca, |vca: &mut GainNode| vca.set_gain(0.5))?;
+    /// ```
+    ///
+    pub fn with_node<N: GraphNode, R, F: FnOnce(&mut N) -> R>(&self, node: NodeId, f: F) -> Result<R, GraphError> {
+        let mut state = self.state.lock().unwrap();
+        state.get_slot(node.0)?;
+        let slot = state.nodes[node.0].as_mut().unwrap();
+        let any: &mut dyn Any = slot.node.as_mut();
+        any.downcast_mut::<N>().map(f).ok_or(GraphError::NodeType(node.0))
+    }
+
+    /// Graph as a master closure: the mix is the graph input, the graph output replaces it
+    ///
+    pub fn get_master_patch(&self) -> MasterPatchType {
+        let mut graph = self.clone();
+        let mut x = Vec::new();
+        Box::new(move |frame: &mut [f32]| {
+            x.clear();
+            x.extend_from_slice(frame);
+            graph.process(&x, frame);
+        })
+    }
+
+    /// Graph as a duplex closure: the input block is the graph input, the output block is the graph output
+    ///
+    pub fn get_duplex_patch(&self) -> DuplexPatchType {
+        let mut graph = self.clone();
+        let in_channels = self.get_in_channels().max(1);
+        let out_channels = self.get_out_channels();
+        Box::new(move |frame: &[f32]| {
+            let mut y = vec![0.0; frame.len() / in_channels * out_channels];
+            graph.process(frame, &mut y);
+            y
+        })
+    }
+
+    /// Graph as a dsp closure: render `n_frames` frames of the graph output (the graph input is silent)
+    ///
+    pub fn get_dsp_patch(&self, n_frames: usize) -> DspPatchType {
+        let graph = self.clone();
+        Box::new(move || {
+            let mut graph = graph.clone();
+            let mut y = vec![0.0; n_frames * graph.get_out_channels()];
+            graph.process(&[], &mut y);
+            y
+        })
+    }
+}
+
+/// The graph input has `in_channels` channels (stream channels are repeated if they are fewer), the output has
+/// `out_channels` channels. Blocks longer than the block size are rendered in more blocks
+///
+impl Processor for QGraph {
+    fn prepare(&mut self, sr: f32, block_size: usize, n_channels: usize) {
+        let mut state = self.state.lock().unwrap();
+        if n_channels != state.out_channels {
+            println!("[ERROR] The graph has {} output channels, the stream {}!", state.out_channels, n_channels);
+        }
+        state.sr = sr;
+        state.block_size = block_size.max(1);
+        let block_size = state.block_size;
+        for slot in state.nodes.iter_mut().flatten() {
+            GraphState::prepare_slot(slot, sr, block_size);
+        }
+    }
+
+    fn process(&mut self, input: &[f32], output: &mut [f32]) {
+        let Ok(mut state) = self.state.try_lock() else {
+            output.fill(0.0);
+            return
+        };
+
+        let out_channels = state.out_channels;
+        if out_channels == 0 {
+            output.fill(0.0);
+            return
+        }
+
+        let frames = output.len() / out_channels;
+        let in_stride = input.len().checked_div(frames).unwrap_or(0);
+        let block_size = state.block_size;
+
+        let mut start = 0;
+        while start < frames {
+            let n = block_size.min(frames - start);
+            let x = if in_stride > 0 { &input[start * in_stride..(start + n) * in_stride] } else { &[][..] };
+            state.render(x, &mut output[start * out_channels..(start + n) * out_channels], n);
+            start += n;
+        }
+        output[frames * out_channels..].fill(0.0);
+    }
+
+    fn reset(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        for slot in state.nodes.iter_mut().flatten() {
+            slot.node.reset();
+            slot.inputs.clear();
+            slot.outputs.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuse_cycles() {
+        let graph = QGraph::new(1000.0, 4, 1, 1);
+        let a = graph.add_node(GainNode::new(1, 1.0));
+        let b = graph.add_node(GainNode::new(1, 1.0));
+
+        graph.connect(graph.get_input(), 0, a, 0).unwrap();
+        graph.connect(a, 0, b, 0).unwrap();
+        assert_eq!(graph.connect(b, 0, a, 0), Err(GraphError::CycleDetected(b.get_index(), a.get_index())));
+        assert_eq!(graph.connect(a, 0, a, 0), Err(GraphError::CycleDetected(a.get_index(), a.get_index())));
+        assert!(graph.connect(b, 0, graph.get_output(), 0).is_ok());
+    }
+
+    #[test]
+    fn refuse_port_mismatch() {
+        let graph = QGraph::new(1000.0, 4, 2, 2);
+        let mono = graph.add_node(GainNode::new(1, 1.0));
+        let stereo = graph.add_node(GainNode::new(2, 1.0));
+
+        let mismatch = GraphError::PortMismatch { from: PortType::Audio(2), to: PortType::Audio(1) };
+        assert_eq!(graph.connect(graph.get_input(), 0, mono, 0), Err(mismatch));
+        let mismatch = GraphError::PortMismatch { from: PortType::Audio(2), to: PortType::Control };
+        assert_eq!(graph.connect(stereo, 0, mono, 1), Err(mismatch));
+        assert_eq!(graph.connect(stereo, 1, mono, 0), Err(GraphError::PortNotFound { node: stereo.get_index(), port: 1 }));
+        assert!(graph.connect(graph.get_input(), 0, stereo, 0).is_ok());
+    }
+
+    #[test]
+    fn process_chain() {
+        let mut graph = QGraph::new(1000.0, 4, 1, 1);
+        let a = graph.add_node(GainNode::new(1, 0.5));
+        let b = graph.add_node(GainNode::new(1, 3.0));
+        graph.connect(graph.get_input(), 0, a, 0).unwrap();
+        graph.connect(a, 0, b, 0).unwrap();
+        graph.connect(b, 0, graph.get_output(), 0).unwrap();
+        // an input port receives the sum of its connections
+        graph.connect(graph.get_input(), 0, graph.get_output(), 0).unwrap();
+
+        let x = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let mut y = [0.0; 6];
+        graph.process(&x, &mut y);
+        assert_eq!(y, [2.5, 5.0, 7.5, 10.0, 12.5, 15.0]);
+
+        graph.with_node(a, |gain: &mut GainNode| gain.set_gain(0.0)).unwrap();
+        graph.process(&x, &mut y);
+        assert_eq!(y, x);
+    }
+}
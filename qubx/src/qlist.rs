@@ -1,96 +1,544 @@
 #![allow(dead_code)]
 
+use std::sync::atomic::{ AtomicU64, AtomicUsize, Ordering };
+use std::sync::Arc;
+use std::thread;
+
 use concurrent_queue::ConcurrentQueue;
+use crate::qpool::{ FramePool, PoolFrame };
+use crate::qring::{ ring_channel, RingConsumer, RingProducer };
+use crate::qrouting::{ add_ramped, RoutingRender, MAX_BUSES };
+use crate::qstrip::{ SourceFade, StripRamp, StripState, RAMP_TIME };
+use crate::qubx_control::ProcessControl;
+use crate::qmetrics::ProcessMetrics;
+
+pub(crate) const MAX_QUEUES: usize = 1024;
+/// Max number of frames in the queue of a one-shot dsp process (the output is pushed while the master reads)
+pub(crate) const SOURCE_QUEUE_FRAMES: usize = 32;
+const POOL_CAPACITY: usize = 4096;
+const POOL_PREALLOCATED_FRAMES: usize = 256;
 
+pub type FrameProducer = RingProducer<PoolFrame>;
+pub type FrameConsumer = RingConsumer<PoolFrame>;
+
+/// # Stream Producer
+///
+/// Producer side of a bounded streaming queue (see `QListHandle::register_stream_queue()`). Unlike `FrameProducer`,
+/// the oldest frame can be replaced when the queue is full (see `force_push()`). The queue is closed when the producer
+/// is dropped
+///
 #[derive(Debug)]
-pub struct QList {
-    pub qlist: Vec<ConcurrentQueue<Vec<f32>>>,
-    pub length: u16,
-    index: usize,
+pub struct StreamProducer {
+    queue: Arc<ConcurrentQueue<PoolFrame>>
 }
 
-impl QList {
-    pub fn new() -> Self {
-        let q: Vec<ConcurrentQueue<Vec<f32>>> = Vec::new();
+impl StreamProducer {
+    /// Push a frame into the queue
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), PoolFrame>`: if the queue is full the frame is given back
+    ///
+    pub fn push(&mut self, frame: PoolFrame) -> Result<(), PoolFrame> {
+        self.queue.push(frame).map_err(|e| e.into_inner())
+    }
+
+    /// Push a frame into the queue, replacing the oldest frame if the queue is full
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Option<PoolFrame>`: the dropped frame
+    ///
+    pub fn force_push(&mut self, frame: PoolFrame) -> Option<PoolFrame> {
+        self.queue.force_push(frame).unwrap_or_else(|e| Some(e.into_inner()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.queue.is_full()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.queue.capacity().unwrap_or(0)
+    }
+
+    /// Check if the master has released the queue (see `QubxDspProcess::release()`): the stream can stop
+    ///
+    pub fn is_closed(&self) -> bool {
+        self.queue.is_closed()
+    }
+}
+
+impl Drop for StreamProducer {
+    fn drop(&mut self) {
+        self.queue.close();
+    }
+}
+
+/// Consumer side of a dsp queue: a ring for one-shot producers, a bounded lock-free queue for streaming producers
+///
+#[derive(Debug)]
+enum SlotQueue {
+    Ring(FrameConsumer),
+    Stream(Arc<ConcurrentQueue<PoolFrame>>)
+}
+
+impl SlotQueue {
+    #[inline]
+    fn pop(&mut self) -> Option<PoolFrame> {
+        match self {
+            Self::Ring(q) => q.pop(),
+            Self::Stream(q) => q.pop().ok()
+        }
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        match self {
+            Self::Ring(q) => q.len(),
+            Self::Stream(q) => q.len()
+        }
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        match self {
+            Self::Ring(q) => q.is_empty(),
+            Self::Stream(q) => q.is_empty()
+        }
+    }
+
+    #[inline]
+    fn is_finished(&self) -> bool {
+        match self {
+            Self::Ring(q) => q.is_finished(),
+            Self::Stream(q) => q.is_closed() && q.is_empty()
+        }
+    }
+
+    /// Check if the producer is done or the queue has been released (no more frames will be pushed)
+    ///
+    #[inline]
+    fn is_closed(&self) -> bool {
+        match self {
+            Self::Ring(q) => q.is_closed(),
+            Self::Stream(q) => q.is_closed()
+        }
+    }
+
+    /// Tell a streaming producer to stop (the queue has been released)
+    ///
+    fn close(&self) {
+        if let Self::Stream(q) = self {
+            q.close();
+        }
+    }
+}
+
+/// # QList Handle
+///
+/// Non real-time side of a `QList`. Each dsp producer registers its own queue and borrows its frames from the pool
+/// through the handle. The queues released by the real-time thread are freed here (see `collect_garbage()`)
+///
+#[derive(Debug, Clone)]
+pub struct QListHandle {
+    incoming: Arc<ConcurrentQueue<QueueSlot>>,
+    retired_queues: Arc<ConcurrentQueue<QueueSlot>>,
+    pool: FramePool,
+    clock: Arc<AtomicU64>,
+    solo_count: Arc<AtomicUsize>,
+    nchannels: usize,
+    ramp_frames: usize,
+}
+
+impl QListHandle {
+    /// Create a new handle
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `chunk`: frames per buffer
+    /// `nchannels`: number of out channels
+    /// `sr`: sample rate (smoothing of the channel strips, see `RAMP_TIME`)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `QListHandle`
+    ///
+    pub fn new(chunk: usize, nchannels: usize, sr: f32) -> Self {
         Self {
-            qlist: q,
-            length: 0,
-            index: 0,
+            incoming: Arc::new(ConcurrentQueue::bounded(MAX_QUEUES)),
+            retired_queues: Arc::new(ConcurrentQueue::bounded(MAX_QUEUES)),
+            pool: FramePool::new(chunk * nchannels, POOL_CAPACITY, POOL_PREALLOCATED_FRAMES),
+            clock: Arc::new(AtomicU64::new(0)),
+            solo_count: Arc::new(AtomicUsize::new(0)),
+            nchannels: nchannels.max(1),
+            ramp_frames: (RAMP_TIME * sr).round() as usize,
         }
     }
 
-    pub fn initialize(&mut self) {
-        self.qlist.push(ConcurrentQueue::<Vec<f32>>::unbounded());
-        self.length += 1;
+    /// Get the frame pool shared with the real-time thread
+    ///
+    pub fn get_pool(&self) -> &FramePool {
+        &self.pool
     }
 
-    pub fn put_frame(&mut self, frame: Vec<f32>) {
-        self.qlist[self.index].push(frame).unwrap()
+    /// Get the master sample clock
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `u64`: number of sample frames (samples per channel) mixed since the master started
+    ///
+    pub fn get_sample_clock(&self) -> u64 {
+        self.clock.load(Ordering::Acquire)
     }
 
-    pub fn get_frame(&mut self, index: usize) -> Vec<f32> {
-        self.qlist[index].pop().unwrap()
+    /// Get the number of soloed channel strips (see `ChannelStrip`)
+    ///
+    pub(crate) fn get_solo_count(&self) -> Arc<AtomicUsize> {
+        Arc::clone(&self.solo_count)
     }
 
-    pub fn is_empty_at_index(&self, index: usize) -> bool {
-        self.qlist[index].is_empty()
+    /// Register a new queue. Each dsp producer writes into its own queue
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `capacity`: max number of frames in the queue
+    /// `start`: master sample clock position of the first sample in the queue (`0` to start as soon as possible)
+    /// `strip`: channel strip applied to the queue in the mix
+    /// `control`: control of the dsp process (the queue is held while the process is paused and drained
+    /// when it is stopped or restarted)
+    /// `generation`: generation of the dsp thread that owns the queue
+    /// `fade`: fade-in and fade-out of the queue in the mix
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `FrameProducer`: the queue is closed when the producer is dropped
+    ///
+    pub(crate) fn register_queue(
+        &self,
+        capacity: usize,
+        start: u64,
+        strip: Arc<StripState>,
+        control: Arc<ProcessControl>,
+        generation: usize,
+        fade: SourceFade
+    ) -> FrameProducer {
+        let (producer, consumer) = ring_channel(capacity);
+        self.register(SlotQueue::Ring(consumer), start, strip, control, generation, fade);
+        producer
+    }
+
+    /// Register a new bounded streaming queue. The producer keeps writing while the master reads, so the queue holds
+    /// at most `capacity` frames (latency: `capacity` * chunk samples)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `capacity`: max number of frames in the queue
+    /// `start`: master sample clock position of the first sample in the queue (`0` to start as soon as possible)
+    /// `strip`: channel strip applied to the queue in the mix
+    /// `control`: control of the dsp process
+    /// `generation`: generation of the dsp thread that owns the queue
+    /// `fade`: fade-in of the queue in the mix and release time
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `StreamProducer`: the queue is closed when the producer is dropped
+    ///
+    pub(crate) fn register_stream_queue(
+        &self,
+        capacity: usize,
+        start: u64,
+        strip: Arc<StripState>,
+        control: Arc<ProcessControl>,
+        generation: usize,
+        fade: SourceFade
+    ) -> StreamProducer {
+        let queue = Arc::new(ConcurrentQueue::bounded(capacity.max(1)));
+        self.register(SlotQueue::Stream(Arc::clone(&queue)), start, strip, control, generation, fade);
+        StreamProducer { queue }
+    }
+
+    fn register(
+        &self,
+        queue: SlotQueue,
+        start: u64,
+        strip: Arc<StripState>,
+        control: Arc<ProcessControl>,
+        generation: usize,
+        mut fade: SourceFade
+    ) {
+        self.collect_garbage();
+        let send_gains = std::array::from_fn(|bus| strip.get_send(bus));
+        fade.set_release_epoch(strip.get_release_epoch());
+        let ramp = StripRamp::new(strip, self.nchannels, self.ramp_frames);
+        let mut slot = QueueSlot { queue, start, frame: None, position: 0, ramp, fade, send_gains, control, generation };
+        while let Err(e) = self.incoming.push(slot) {
+            slot = e.into_inner();
+            thread::yield_now();
+        }
+    }
+
+    /// Free queues released by the real-time thread and the frames of idle pool blocks (see `FramePool`)
+    ///
+    pub fn collect_garbage(&self) {
+        while self.retired_queues.pop().is_ok() { }
+        self.pool.collect_garbage();
     }
+}
 
-    pub fn get_next_empty_queue(&mut self) {
-        let mut counter = 0;
-        while counter < self.length {
-            if self.is_empty_at_index(self.index) {
-                break;
+/// Queue of a single dsp producer with its position on the master timeline
+///
+#[derive(Debug)]
+struct QueueSlot {
+    queue: SlotQueue,
+    start: u64,
+    frame: Option<PoolFrame>,
+    position: usize,
+    ramp: StripRamp,
+    fade: SourceFade,
+    send_gains: [f32; MAX_BUSES],
+    control: Arc<ProcessControl>,
+    generation: usize,
+}
+
+impl QueueSlot {
+    /// Add the queue samples to `block` starting at sample `offset`, applying the channel strip gains and the fades.
+    /// Frames are read across block boundaries, so the queue can start anywhere inside a block
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `bool`: the queue ran out of frames before the end of the block while the producer is still running (starved)
+    ///
+    fn read_into(&mut self, block: &mut [f32], offset: usize, nchannels: usize) -> bool {
+        let fading = self.fade.is_fading((block.len() - offset) / nchannels);
+        let played = self.fade.get_played();
+        let mut index = offset;
+        let mut starved = false;
+        while index < block.len() {
+            if self.frame.is_none() {
+                self.frame = self.queue.pop();
+                self.position = 0;
             }
 
-            self.index += 1;
-            self.index %= self.length as usize;
-            counter += 1;
+            let Some(frame) = self.frame.as_ref() else {
+                starved = !self.queue.is_finished();
+                break
+            };
+
+            let n = (block.len() - index).min(frame.len() - self.position);
+            for (k, f) in frame[self.position..self.position + n].iter().enumerate() {
+                let i = index + k;
+                let mut gain = self.ramp.gain_at(i / nchannels, i % nchannels);
+                if fading {
+                    gain *= self.fade.gain_at(played + (i - offset) / nchannels);
+                }
+                block[i] += f * gain;
+            }
+            index += n;
+            self.position += n;
 
-            if counter >= self.length {
-                let q = ConcurrentQueue::<Vec<f32>>::unbounded();
-                self.qlist.push(q);
-                self.length += 1;
-                self.index = (self.length - 1) as usize;
+            if self.position >= frame.len() {
+                self.frame = None; // back to the pool
             }
         }
+        self.fade.advance((index - offset) / nchannels);
+        starved
     }
 
-    pub fn is_all_empty(&self) -> bool {
-        self.qlist.iter().all(|x| x.is_empty())
+    /// Drop all pending frames (the process has been stopped or restarted)
+    ///
+    fn drain(&mut self) {
+        self.frame = None;
+        while self.queue.pop().is_some() { }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.frame.is_none() && self.queue.is_empty()
     }
+
+    fn is_finished(&self) -> bool {
+        self.frame.is_none() && self.queue.is_finished()
+    }
+}
+
+/// # QList
+///
+/// Real-time side of the master queues: one wait-free single-producer single-consumer queue for each dsp producer
+/// (bounded lock-free queue for streaming producers). All methods are real-time safe (no locks, no allocations)
+///
+#[derive(Debug)]
+pub struct QList {
+    qlist: Vec<QueueSlot>,
+    handle: QListHandle,
+    scratch: Vec<f32>,
 }
 
-impl Default for QList {
-    fn default() -> Self {
-        let qlist: Vec<ConcurrentQueue<Vec<f32>>> = vec![ConcurrentQueue::<Vec<f32>>::unbounded()];
+impl QList {
+    pub fn new(handle: &QListHandle) -> Self {
         Self {
-            qlist,
-            length: 1,
-            index: 0,
+            qlist: Vec::with_capacity(MAX_QUEUES),
+            handle: handle.clone(),
+            scratch: Vec::with_capacity(handle.pool.frame_size()),
         }
     }
-}
 
-impl Clone for QList {
-    fn clone(&self) -> Self {
-        let mut qclone: Vec<ConcurrentQueue<Vec<f32>>> = Vec::new();
+    /// Retire finished queues and take the new registered ones
+    ///
+    pub fn update_queues(&mut self) {
+        let mut index = 0;
+        while index < self.qlist.len() {
+            if self.qlist[index].is_finished() {
+                let q = self.qlist.swap_remove(index);
+                if let Err(e) = self.handle.retired_queues.push(q) {
+                    // the retired queue is full: the slot is retired on the next call (never freed here)
+                    self.qlist.push(e.into_inner());
+                    break
+                }
+            } else {
+                index += 1;
+            }
+        }
 
-        for q in &self.qlist {
-            let cloned_queue = ConcurrentQueue::<Vec<f32>>::unbounded();
-            for item in q.try_iter() {
-                cloned_queue.push(item.clone()).unwrap();
+        while self.qlist.len() < self.qlist.capacity() {
+            match self.handle.incoming.pop() {
+                Ok(q) => self.qlist.push(q),
+                Err(_) => break
             }
-            qclone.push(cloned_queue);
         }
+    }
 
-        Self {
-            qlist: qclone,
-            length: self.length,
-            index: self.index,
+    /// Mix all queues into `block` (or into their buses) and advance the master sample clock. Each queue is placed
+    /// on the timeline with sample accuracy (see `QListHandle::register_queue()`)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `block`: interleaved output block
+    /// `routing`: buses of the master streamout
+    /// `metrics`: metrics of the master streamout (queue depth and starved queues). Each dsp process gets its own
+    /// queue depth and starvation count too
+    ///
+    pub(crate) fn mix_into(&mut self, block: &mut [f32], routing: &mut RoutingRender, metrics: &ProcessMetrics) {
+        let nchannels = self.handle.nchannels;
+        let block_start = self.handle.clock.load(Ordering::Acquire);
+        let block_frames = (block.len() / nchannels) as u64;
+        let block_end = block_start + block_frames;
+        let mut depth = 0;
+
+        for slot in self.qlist.iter_mut() {
+            if !slot.control.is_alive(slot.generation) {
+                slot.drain();
+                continue
+            }
+
+            // a released queue is dropped at the end of its fade-out
+            slot.fade.update_release(slot.ramp.get_state());
+            if slot.fade.is_done() {
+                slot.drain();
+                slot.queue.close();
+                continue
+            }
+
+            slot.control.get_metrics().set_queue_depth(slot.queue.len());
+            depth += slot.queue.len();
+
+            if slot.start >= block_end || slot.control.is_paused() {
+                continue
+            }
+
+            let offset = (slot.start.saturating_sub(block_start) as usize) * nchannels;
+            self.scratch.clear();
+            self.scratch.resize(block.len(), 0.0);
+            slot.ramp.prepare(nchannels);
+            let starved = slot.read_into(&mut self.scratch, offset, nchannels);
+            slot.ramp.advance(block_frames as usize);
+
+            if starved {
+                metrics.add_underrun();
+                slot.control.get_metrics().add_underrun();
+            }
+
+            let state = slot.ramp.get_state();
+            match state.get_output().and_then(|bus| routing.get_buffer(bus)) {
+                Some(dst) => dst.iter_mut().zip(self.scratch.iter()).for_each(|(d, s)| *d += s),
+                None => block.iter_mut().zip(self.scratch.iter()).for_each(|(d, s)| *d += s)
+            }
+
+            if routing.has_buses() {
+                for (bus, gain) in slot.send_gains.iter_mut().enumerate() {
+                    let level = state.get_send(bus);
+                    if let Some(dst) = routing.get_buffer(bus) {
+                        add_ramped(dst, &self.scratch, *gain, level, nchannels);
+                    }
+                    *gain = level;
+                }
+            }
         }
+
+        metrics.set_queue_depth(depth);
+        self.handle.clock.store(block_end, Ordering::Release);
+    }
+
+    pub fn len(&self) -> usize {
+        self.qlist.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.qlist.is_empty()
     }
-}
 
-unsafe impl Send for QList {}
-unsafe impl Sync for QList {}
+    pub fn is_empty_at_index(&self, index: usize) -> bool {
+        self.qlist[index].is_empty()
+    }
+
+    pub fn is_all_empty(&self) -> bool {
+        self.qlist.iter().all(|x| x.is_empty()) && self.handle.incoming.is_empty()
+    }
+
+    /// Check if a running producer has not written the samples needed by the next block yet (offline rendering holds
+    /// the clock until they are ready)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `block_len`: number of samples of the next block (interleaved)
+    ///
+    pub fn is_starved(&self, block_len: usize) -> bool {
+        let block_start = self.handle.clock.load(Ordering::Acquire);
+        let block_end = block_start + (block_len / self.handle.nchannels) as u64;
+        let frame_size = self.handle.pool.frame_size();
+        self.qlist.iter().any(|slot| {
+            if slot.queue.is_closed() || slot.start >= block_end || slot.fade.is_done() {
+                return false
+            }
+            if !slot.control.is_alive(slot.generation) || slot.control.is_paused() {
+                return false
+            }
+            let pending = slot.frame.as_ref().map_or(0, |frame| frame.len() - slot.position);
+            pending + slot.queue.len() * frame_size < block_len
+        })
+    }
+
+    /// Check if all the producers are done and all queues are consumed
+    ///
+    pub fn is_drained(&self) -> bool {
+        self.qlist.iter().all(|x| x.is_finished()) && self.handle.incoming.is_empty()
+    }
+}
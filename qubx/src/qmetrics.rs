@@ -0,0 +1,251 @@
+#![allow(dead_code)]
+
+use std::sync::atomic::{ AtomicU32, AtomicU64, AtomicUsize, Ordering };
+use std::time::Duration;
+
+const SUB_BUCKETS_BITS: usize = 3;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKETS_BITS;
+const OCTAVES: usize = 40; // up to ~18 min in ns
+const BUCKETS: usize = OCTAVES * SUB_BUCKETS;
+
+/// Histogram bucket of a duration in ns (log scale, 8 buckets per octave: max error 12.5%)
+///
+#[inline]
+fn bucket_index(ns: u64) -> usize {
+    if ns < SUB_BUCKETS as u64 {
+        return ns as usize
+    }
+    let octave = 63 - ns.leading_zeros() as usize;
+    let sub = ((ns >> (octave - SUB_BUCKETS_BITS)) as usize) & (SUB_BUCKETS - 1);
+    ((octave - SUB_BUCKETS_BITS + 1) * SUB_BUCKETS + sub).min(BUCKETS - 1)
+}
+
+/// Lower bound (in ns) of a histogram bucket
+///
+fn bucket_lower_bound(index: usize) -> u64 {
+    if index < SUB_BUCKETS {
+        return index as u64
+    }
+    let octave = index / SUB_BUCKETS + SUB_BUCKETS_BITS - 1;
+    let sub = (index % SUB_BUCKETS) as u64;
+    (SUB_BUCKETS as u64 + sub) << (octave - SUB_BUCKETS_BITS)
+}
+
+/// # Process Metrics
+///
+/// Runtime statistics of a single process, written by the audio thread with atomics only (no locks, no allocations)
+/// and read by any control thread (see `snapshot()`).
+///
+/// Master and duplex streams record one sample per callback, dsp processes one sample per processed job. Underruns
+/// come from the device status flags and from queue starvation in the master mix, overruns from the device status
+/// flags. A callback longer than its buffer period is counted as late
+///
+#[derive(Debug)]
+pub struct ProcessMetrics {
+    histogram: Box<[AtomicU64]>,
+    callbacks: AtomicU64,
+    busy_ns: AtomicU64,
+    period_ns: AtomicU64,
+    max_ns: AtomicU64,
+    load: AtomicU32,
+    peak_load: AtomicU32,
+    underruns: AtomicU64,
+    overruns: AtomicU64,
+    late_callbacks: AtomicU64,
+    queue_depth: AtomicUsize,
+    queue_peak: AtomicUsize
+}
+
+impl Default for ProcessMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProcessMetrics {
+    pub fn new() -> Self {
+        Self {
+            histogram: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            callbacks: AtomicU64::new(0),
+            busy_ns: AtomicU64::new(0),
+            period_ns: AtomicU64::new(0),
+            max_ns: AtomicU64::new(0),
+            load: AtomicU32::new(0.0f32.to_bits()),
+            peak_load: AtomicU32::new(0.0f32.to_bits()),
+            underruns: AtomicU64::new(0),
+            overruns: AtomicU64::new(0),
+            late_callbacks: AtomicU64::new(0),
+            queue_depth: AtomicUsize::new(0),
+            queue_peak: AtomicUsize::new(0)
+        }
+    }
+
+    /// Record the duration of a callback (real-time safe)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `elapsed`: time spent in the callback
+    /// `period`: duration of the audio processed by the callback (chunk / sr)
+    ///
+    pub fn record_callback(&self, elapsed: Duration, period: Duration) {
+        let ns = elapsed.as_nanos().min(u64::MAX as u128) as u64;
+        let period_ns = period.as_nanos().min(u64::MAX as u128) as u64;
+
+        self.histogram[bucket_index(ns)].fetch_add(1, Ordering::Relaxed);
+        self.callbacks.fetch_add(1, Ordering::Relaxed);
+        self.busy_ns.fetch_add(ns, Ordering::Relaxed);
+        self.period_ns.fetch_add(period_ns, Ordering::Relaxed);
+        self.max_ns.fetch_max(ns, Ordering::Relaxed);
+
+        if period_ns > 0 {
+            let load = ns as f32 / period_ns as f32;
+            self.load.store(load.to_bits(), Ordering::Relaxed);
+            // positive floats have the same order as their bits
+            self.peak_load.fetch_max(load.to_bits(), Ordering::Relaxed);
+            if ns > period_ns {
+                self.late_callbacks.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn add_underrun(&self) {
+        self.underruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_overrun(&self) {
+        self.overruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Set the number of frames (chunk buffers) waiting in the queues of the process
+    ///
+    pub fn set_queue_depth(&self, depth: usize) {
+        self.queue_depth.store(depth, Ordering::Relaxed);
+        self.queue_peak.fetch_max(depth, Ordering::Relaxed);
+    }
+
+    /// Callback duration at quantile `q` (upper bound of the histogram bucket)
+    ///
+    fn percentile(&self, counts: &[u64], total: u64, q: f64, max_ns: u64) -> Duration {
+        if total == 0 {
+            return Duration::ZERO
+        }
+
+        let target = ((q * total as f64).ceil() as u64).max(1);
+        let mut cumulative = 0;
+        for (index, count) in counts.iter().enumerate() {
+            cumulative += count;
+            if cumulative >= target {
+                let upper = if index + 1 < BUCKETS { bucket_lower_bound(index + 1) } else { max_ns };
+                return Duration::from_nanos(upper.min(max_ns))
+            }
+        }
+        Duration::from_nanos(max_ns)
+    }
+
+    /// Take a snapshot of the metrics. It never blocks the audio thread
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `MetricsSnapshot`
+    ///
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let counts: Vec<u64> = self.histogram.iter().map(|x| x.load(Ordering::Relaxed)).collect();
+        let total: u64 = counts.iter().sum();
+        let max_ns = self.max_ns.load(Ordering::Relaxed);
+        let callbacks = self.callbacks.load(Ordering::Relaxed);
+        let busy_ns = self.busy_ns.load(Ordering::Relaxed);
+        let period_ns = self.period_ns.load(Ordering::Relaxed);
+
+        MetricsSnapshot {
+            callbacks,
+            callback_mean: Duration::from_nanos(busy_ns.checked_div(callbacks).unwrap_or(0)),
+            callback_p50: self.percentile(&counts, total, 0.5, max_ns),
+            callback_p90: self.percentile(&counts, total, 0.9, max_ns),
+            callback_p99: self.percentile(&counts, total, 0.99, max_ns),
+            callback_max: Duration::from_nanos(max_ns),
+            cpu_load: f32::from_bits(self.load.load(Ordering::Relaxed)),
+            cpu_load_avg: if period_ns > 0 { (busy_ns as f64 / period_ns as f64) as f32 } else { 0.0 },
+            cpu_load_peak: f32::from_bits(self.peak_load.load(Ordering::Relaxed)),
+            underruns: self.underruns.load(Ordering::Relaxed),
+            overruns: self.overruns.load(Ordering::Relaxed),
+            late_callbacks: self.late_callbacks.load(Ordering::Relaxed),
+            queue_depth: self.queue_depth.load(Ordering::Relaxed),
+            queue_peak: self.queue_peak.load(Ordering::Relaxed)
+        }
+    }
+
+    /// Clear all the metrics
+    ///
+    pub fn reset(&self) {
+        self.histogram.iter().for_each(|x| x.store(0, Ordering::Relaxed));
+        self.callbacks.store(0, Ordering::Relaxed);
+        self.busy_ns.store(0, Ordering::Relaxed);
+        self.period_ns.store(0, Ordering::Relaxed);
+        self.max_ns.store(0, Ordering::Relaxed);
+        self.load.store(0.0f32.to_bits(), Ordering::Relaxed);
+        self.peak_load.store(0.0f32.to_bits(), Ordering::Relaxed);
+        self.underruns.store(0, Ordering::Relaxed);
+        self.overruns.store(0, Ordering::Relaxed);
+        self.late_callbacks.store(0, Ordering::Relaxed);
+        self.queue_depth.store(0, Ordering::Relaxed);
+        self.queue_peak.store(0, Ordering::Relaxed);
+    }
+}
+
+/// # Metrics Snapshot
+///
+/// `callbacks`: number of callbacks (dsp processes: number of processed jobs)
+/// `callback_mean`, `callback_p50`, `callback_p90`, `callback_p99`, `callback_max`: callback duration statistics
+/// (percentiles have a max error of 12.5%)
+/// `cpu_load`: duration of the last callback as a fraction of its buffer period
+/// `cpu_load_avg`: total callback time as a fraction of the total audio time
+/// `cpu_load_peak`: max load of a single callback
+/// `underruns`: output underflows reported by the device and starved queues in the master mix
+/// `overruns`: input overflows and output overflows reported by the device
+/// `late_callbacks`: callbacks longer than their buffer period
+/// `queue_depth`: frames (chunk buffers) waiting in the queues of the process (master: all dsp queues, dsp: its own
+/// queue)
+/// `queue_peak`: max queue depth
+///
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MetricsSnapshot {
+    pub callbacks: u64,
+    pub callback_mean: Duration,
+    pub callback_p50: Duration,
+    pub callback_p90: Duration,
+    pub callback_p99: Duration,
+    pub callback_max: Duration,
+    pub cpu_load: f32,
+    pub cpu_load_avg: f32,
+    pub cpu_load_peak: f32,
+    pub underruns: u64,
+    pub overruns: u64,
+    pub late_callbacks: u64,
+    pub queue_depth: usize,
+    pub queue_peak: usize
+}
+
+impl std::fmt::Display for MetricsSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            ":::Number of iterations: {}\n:::Latency average: {:?}\n:::Latency p50/p90/p99/max: {:?} / {:?} / {:?} / {:?}\n:::CPU load (last/avg/peak): {:.3} / {:.3} / {:.3}\n:::Underruns: {}\n:::Overruns: {}\n:::Late callbacks: {}\n:::Queue depth (frames/peak): {} / {}",
+            self.callbacks,
+            self.callback_mean,
+            self.callback_p50,
+            self.callback_p90,
+            self.callback_p99,
+            self.callback_max,
+            self.cpu_load,
+            self.cpu_load_avg,
+            self.cpu_load_peak,
+            self.underruns,
+            self.overruns,
+            self.late_callbacks,
+            self.queue_depth,
+            self.queue_peak
+        )
+    }
+}
@@ -1,7 +1,7 @@
 use crate::{ 
     qbuffers::ReadBufferDirection, qinterp::Interp, qmod::shared_tools::check_range_value, qsignals::{ SignalMode, SignalParams }, qtable::{ QTable, TableParams, TableMode },
 };
-use rand::{ rngs::ThreadRng, thread_rng };
+use rand::{ rngs::StdRng, SeedableRng };
 use rand_distr::{Distribution, Uniform};
 
 
@@ -97,6 +97,7 @@ impl ModulationParams
 
 }
 
+#[derive(Debug, Clone, Copy)]
 pub enum ModulationMode
 {
     Procedural,         // without table
@@ -132,8 +133,41 @@ pub struct GranularParams<'a>
     pub grain_read_direction: ReadBufferDirection,
     pub interp_mode: Interp,
     pub(crate) audio_amp: f32,
-    pub(crate) envelope_table: &'a mut TableParams,
-    rnd_generator: ThreadRng,
+    pub(crate) envelope_table: GrainEnvelope<'a>,
+    rnd_generator: StdRng,
+}
+
+/// Grain envelope table, borrowed (`GranularParams::new()`) or owned (`GranularParams::with_envelope()`)
+/// 
+pub(crate) enum GrainEnvelope<'a>
+{
+    Borrowed(&'a mut TableParams),
+    Owned(TableParams)
+}
+
+impl GrainEnvelope<'_>
+{
+    pub(crate) fn get_table_mut(&mut self) -> &mut TableParams {
+        match self {
+            GrainEnvelope::Borrowed(table) => table,
+            GrainEnvelope::Owned(table) => table
+        }
+    }
+}
+
+impl GranularParams<'static>
+{
+    /// Create new Granular params object that owns the grain envelope. Use it when the params must be moved to
+    /// another thread (e.g. `QGranulator` as `Processor`)
+    /// 
+    /// # Args  
+    /// -----  
+    /// `interp_mode`: table interpolation mode (see `Interp`)  
+    /// `grain_envelope`: grain envelope shape as `TableParams` (e.g. a clone of `QTable::get_table()`)  
+    /// 
+    pub fn with_envelope(interp_mode: Interp, grain_envelope: TableParams) -> Self {
+        Self::with_grain_envelope(interp_mode, GrainEnvelope::Owned(grain_envelope))
+    }
 }
 
 impl<'a> GranularParams<'a>
@@ -148,7 +182,11 @@ impl<'a> GranularParams<'a>
     /// `table_id`: id of table to read  
     /// 
     pub fn new(interp_mode: Interp, grain_envelope: &'a mut TableParams) -> Self {
-        let rnd_generator = thread_rng();
+        Self::with_grain_envelope(interp_mode, GrainEnvelope::Borrowed(grain_envelope))
+    }
+
+    fn with_grain_envelope(interp_mode: Interp, envelope_table: GrainEnvelope<'a>) -> Self {
+        let rnd_generator = StdRng::from_entropy();
         Self { 
             frequency_range: (90.0, 500.0), 
             amplitude_range: (0.1, 0.7), 
@@ -159,7 +197,7 @@ impl<'a> GranularParams<'a>
             grain_read_direction: ReadBufferDirection::Forward,
             interp_mode,
             audio_amp: 0.0, 
-            envelope_table: grain_envelope,
+            envelope_table,
             rnd_generator,
         }
     }
@@ -180,7 +180,7 @@ impl GranularSynthesis
             let mut inactive_events = Vec::new();
             for (i, event) in self.events.iter_mut().enumerate() {
                 if event.is_event_active() {
-                    sample += event.get_sample(&mut self.signal, params.envelope_table, params.interp_mode);
+                    sample += event.get_sample(&mut self.signal, params.envelope_table.get_table_mut(), params.interp_mode);
                 } else {
                     inactive_events.push(i);
                 }
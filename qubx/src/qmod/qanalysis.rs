@@ -305,7 +305,7 @@ impl QSpectra
 
         let win = window.get_window(win_size);
 
-        for i in 0..ncols {
+        for (i, frame) in stft.iter_mut().enumerate().take(ncols) {
             let start = i * hop_size;
             if start < n - 2 {
                 let end = n.min(start + win_size);
@@ -316,7 +316,7 @@ impl QSpectra
                 }
 
                 planner.process_with_scratch(&mut chunk, &mut fft_buffer, &mut scratch_buffer).unwrap();
-                stft[..][i].copy_from_slice(&fft_buffer);
+                frame.copy_from_slice(&fft_buffer);
             }
         }
 
@@ -700,11 +700,9 @@ impl FromComplexData {
         let power = FromComplexData::get_mag(x);
         let fs = (0..x.ncols).map(|i| x.sr * i as f32 / x.ncols as f32).collect::<Vec<f32>>();
         let muf = fs.iter().sum::<f32>() / fs.len() as f32;
-        let mut mup = Vec::new();
-        for i in 0..x.ncols {
-            let s = (0..x.nrows).map(|j| power[j][i]).sum::<f32>();
-            mup.push(s / x.nrows as f32)
-        }
+        let mup = (0..x.ncols)
+            .map(|i| (0..x.nrows).map(|j| power[j][i]).sum::<f32>() / x.nrows as f32)
+            .collect::<Vec<f32>>();
 
         slope.iter_mut().enumerate().for_each(|(col, value)| {
             let (num, den) = (0..x.nrows).fold((0.0, 0.0), |(n, d), r| {
@@ -7,6 +7,7 @@ use std::{
 use portaudio::stream::Buffer;
 
 use crate::qubx_common::{ Channels, ChannelError, WriteToFile, ToFileError, TimeDomainFloat };
+use crate::qprocessor::{ process_samples, Processor };
 use super::{
     qinterp::{ Interp, PhaseInterpolationIndex }, qoperations::split_into_nchannels, shared_tools::{ interp_buffer_write_from_table, update_and_reset_increment, update_increment, write_to_file }
 };
@@ -567,7 +568,7 @@ impl DelayBuffer
     /// This method must precede `feedforward_delayed_sample()` or `feedback_delayed_sample()` method.  
     /// Each tapped sample will be summed internally in a main delay line and putted out a single sample.
     /// 
-    /// ```rust 
+    /// ```ignore
     /// let mut d = DelayBuffer::new(44100);
     /// 
     /// while true {
@@ -599,7 +600,7 @@ impl DelayBuffer
     /// This method must used in a block `read_buffer()` - `write_buffer()`.  
     /// Each tap line return an indipendent sample  
     /// 
-    /// ```rust 
+    /// ```ignore
     /// let mut d = DelayBuffer::new(44100);
     ///
     /// while true {
@@ -663,6 +664,20 @@ impl DelayBuffer
 
 }
 
+/// Mono processor: feed-forward delay line with its internal taps (see `feedforward_delayed_sample()`). Use
+/// `PerChannel` on multichannel streams
+///
+impl Processor for DelayBuffer
+{
+    fn process(&mut self, input: &[f32], output: &mut [f32]) {
+        process_samples(input, output, |x| self.feedforward_delayed_sample(x));
+    }
+
+    fn reset(&mut self) {
+        self.reset_buffer();
+    }
+}
+
 // DATA BUS MUST BE DEBUGGED
 #[derive(Debug)]
 pub struct DataBus
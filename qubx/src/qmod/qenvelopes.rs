@@ -146,7 +146,7 @@ pub struct AdsrParams
 
 impl AdsrParams
 {
-	fn get_env_params(&self) -> EnvParams {
+	pub(crate) fn get_env_params(&self) -> EnvParams {
 		let t0 = self.attack_dur;
 		let t1 = self.decay_dur;
 		let t2 = self.sustain_dur;
@@ -176,7 +176,6 @@ impl QEnvelope
 	///
 	/// `sr`: sample rate
 	///
-
 	pub fn new(sr: f32) -> Self {
 		Self { sr, cache_vec: HashMap::new(), cache_rt: HashMap::new() }
 	}
@@ -1,4 +1,5 @@
 use crate::{filtertype::FilterError, qubx_common::FilteredSample};
+use crate::qprocessor::{ process_samples, Processor };
 
 use super::filters::{
     biquadeq::Biquad, 
@@ -109,6 +110,7 @@ pub enum FilterType
     Dc
 }
 
+#[derive(Debug, Clone, Copy)]
 pub enum FilterParams
 {
     BiquadParams(BiquadFilter, f32, f32, Option<f32>),    // mode, fc, q, dbgain
@@ -121,6 +123,35 @@ pub enum FilterParams
     DcParams(f32)                                         // fc
 }
 
+impl FilterParams
+{
+    /// Same params with another cutoff (center) frequency. Useful to redesign a filter from a smoothed param
+    /// (see `ParamReader::apply()`)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `fc`: cutoff frequency (`HarmonicParams`: fundamental frequency)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `FilterParams`
+    ///
+    pub fn with_freq(self, fc: f32) -> Self {
+        match self {
+            Self::BiquadParams(mode, _, q, dbgain) => Self::BiquadParams(mode, fc, q, dbgain),
+            Self::ButterParams(mode, _, bw) => Self::ButterParams(mode, fc, bw),
+            Self::HarmonicParams(mode, t60, _) => Self::HarmonicParams(mode, t60, Some(fc)),
+            Self::NarrowParams(mode, _, fw) => Self::NarrowParams(mode, fc, fw),
+            Self::OnePoleParams(mode, _) => Self::OnePoleParams(mode, fc),
+            Self::TwoZeroTwoPoleParams(mode, _, bw) => Self::TwoZeroTwoPoleParams(mode, fc, bw),
+            Self::ZavalishinParams(mode, _, spread) => Self::ZavalishinParams(mode, fc, spread),
+            Self::DcParams(_) => Self::DcParams(fc)
+        }
+    }
+}
+
 pub enum Filter
 {
     Biquad(Biquad),
@@ -242,7 +273,44 @@ impl Filter
         Ok(f)
     }
 
+    /// Clear the delayed samples of the filter (coefficients are kept)
+    ///
+    pub fn clear(&mut self) {
+        match self {
+            Self::Biquad(biquad) => biquad.clear_delayed_samples_cache(),
+            Self::Butter(butter) => butter.clear_delayed_samples_cache(),
+            Self::TwoZeroTwoPole(twozerotwopole) => twozerotwopole.clear_delayed_samples_cache(),
+            Self::OnePole(onepole) => onepole.clear_delayed_samples_cache(),
+            Self::Narrow(narrow) => narrow.clear_delayed_samples_cache(),
+            Self::Harmonic(harmonic) => harmonic.clear_delayed_samples_cache(),
+            Self::Zavalishin(zavalishin) => zavalishin.clear_delayed_samples_cache(),
+            Self::Dc(dc) => dc.clear_delayed_samples_cache()
+        }
+    }
+
+}
 
+/// Mono processor: each sample of the block is filtered in sequence, so use `PerChannel` on multichannel streams.
+/// The filter keeps the sample rate it was designed with. `Zavalishin` outputs its low pass
+///
+impl Processor for Filter
+{
+    fn process(&mut self, input: &[f32], output: &mut [f32]) {
+        match self {
+            Self::Zavalishin(zavalishin) => process_samples(input, output, |x| zavalishin.filt_sample(x).lp),
+            Self::Biquad(biquad) => process_samples(input, output, |x| biquad.filtered_sample(x)),
+            Self::Butter(butter) => process_samples(input, output, |x| butter.filtered_sample(x)),
+            Self::TwoZeroTwoPole(twozerotwopole) => process_samples(input, output, |x| twozerotwopole.filtered_sample(x)),
+            Self::OnePole(onepole) => process_samples(input, output, |x| onepole.filtered_sample(x)),
+            Self::Narrow(narrow) => process_samples(input, output, |x| narrow.filtered_sample(x)),
+            Self::Harmonic(harmonic) => process_samples(input, output, |x| harmonic.filtered_sample(x)),
+            Self::Dc(dc) => process_samples(input, output, |x| dc.filtered_sample(x))
+        }
+    }
+
+    fn reset(&mut self) {
+        self.clear();
+    }
 }
 
 pub struct QFilter
@@ -1,20 +1,40 @@
 use super::genesis::{
-    genesis_params::{ ModulationMode, ModulationType, GranularParams },
+    genesis_params::{ ModulationMode, ModulationParams, ModulationType, GranularParams },
     fap_modulation::Fap,
     granulation::GranularSynthesis
 };
 use super::qtable::TableMode;
+use crate::qprocessor::Processor;
+
+enum ModulationKind
+{
+    Fm,
+    Am,
+    Pm
+}
+
+/// Modulation generated by `QModulation` as `Processor` (see `set_voice()`)
+///
+struct ModulationVoice
+{
+    kind: ModulationKind,
+    params: ModulationParams,
+    initial: ModulationParams,
+    mode: ModulationMode
+}
 
 pub struct QModulation
 {
-    model: Fap
+    model: Fap,
+    voice: Option<ModulationVoice>,
+    n_channels: usize
 }
 
 impl QModulation
 {
     pub fn new(sr: f32) -> Self {
         let model = Fap::new(sr);
-        Self { model }
+        Self { model, voice: None, n_channels: 1 }
     }
 
     /// Generate modulated sample (FM, AM-RM or PM)
@@ -48,19 +68,134 @@ impl QModulation
             }
         }
     }
+
+    /// Set the modulation generated when `QModulation` is used as `Processor`. The params are copied: `reset()`
+    /// restores this copy
+    /// 
+    /// # Args
+    /// -----
+    /// 
+    /// `modulation_type`: type of modulation (see `ModulationType`)  
+    /// `mode`: generation sample mode (see `ModulationMode`)  
+    /// 
+    pub fn set_voice(&mut self, modulation_type: ModulationType, mode: ModulationMode) {
+        let (kind, params) = match modulation_type {
+            ModulationType::Fm(params) => (ModulationKind::Fm, params.clone()),
+            ModulationType::Am(params) => (ModulationKind::Am, params.clone()),
+            ModulationType::Pm(params) => (ModulationKind::Pm, params.clone())
+        };
+        self.voice = Some(ModulationVoice { kind, initial: params.clone(), params, mode });
+    }
 }
 
-pub struct QGranulator
+/// Generator: the modulated signal (see `set_voice()`) is written to every channel, the input is ignored. The
+/// sample rate is taken from `prepare()`
+///
+impl Processor for QModulation
 {
-    granulator: GranularSynthesis
+    fn prepare(&mut self, sr: f32, _block_size: usize, n_channels: usize) {
+        self.model = Fap::new(sr);
+        self.n_channels = n_channels.max(1);
+    }
+
+    fn process(&mut self, _input: &[f32], output: &mut [f32]) {
+        let Some(mut voice) = self.voice.take() else {
+            output.fill(0.0);
+            return
+        };
+
+        for frame in output.chunks_mut(self.n_channels) {
+            let mut modulation = match voice.kind {
+                ModulationKind::Fm => ModulationType::Fm(&mut voice.params),
+                ModulationKind::Am => ModulationType::Am(&mut voice.params),
+                ModulationKind::Pm => ModulationType::Pm(&mut voice.params)
+            };
+            let sample = QModulation::process(self, &mut modulation, voice.mode);
+            frame.fill(sample);
+        }
+
+        self.voice = Some(voice);
+    }
+
+    fn reset(&mut self) {
+        if let Some(ref mut voice) = self.voice {
+            voice.params = voice.initial.clone();
+        }
+    }
 }
 
-impl QGranulator {
+pub struct QGranulator<'a>
+{
+    granulator: GranularSynthesis,
+    source_table: TableMode,
+    sr: f32,
+    params: Option<GranularParams<'a>>,
+    n_channels: usize
+}
+
+impl<'a> QGranulator<'a> {
     pub fn new(source_table: TableMode, sr: f32) -> Self {
-        Self { granulator: GranularSynthesis::new(source_table, sr).unwrap() }
+        Self {
+            granulator: GranularSynthesis::new(source_table.clone(), sr).unwrap(),
+            source_table,
+            sr,
+            params: None,
+            n_channels: 1
+        }
     }
 
     pub fn process(&mut self, params: &mut GranularParams) -> f32 {
         self.granulator.granulate(params)
     }
-}
\ No newline at end of file
+
+    /// Set the grain params used when `QGranulator` is used as `Processor`. Streams need a `QGranulator<'static>`,
+    /// so the params must own the grain envelope (see `GranularParams::with_envelope()`)
+    /// 
+    /// # Args
+    /// -----
+    /// 
+    /// `params`: grain params (see `GranularParams`)  
+    /// 
+    pub fn set_params(&mut self, params: GranularParams<'a>) {
+        self.params = Some(params)
+    }
+}
+
+/// Generator: the grains (see `set_params()`) are written to every channel, the input is ignored. A new sample rate
+/// from `prepare()` rebuilds the source table
+///
+/// Example:
+/// ```ignore
+/// let mut params = GranularParams::with_envelope(Interp::Linear, grain_envelope.get_table(table_id).clone());
+/// params.set_frequency_range((500.0, 3000.0));
+/// let mut granulator = QGranulator::new(TableMode::Signal(SignalMode::Sine), sr);
+/// granulator.set_params(params);
+/// dsp_process.start(DspProcessArg::Processor::<DspPatchType, DspHybridType>(vec![0.0; n_samples], Box::new(granulator)))?;
+/// ```
+///
+impl Processor for QGranulator<'_>
+{
+    fn prepare(&mut self, sr: f32, _block_size: usize, n_channels: usize) {
+        if sr != self.sr {
+            self.sr = sr;
+            self.reset();
+        }
+        self.n_channels = n_channels.max(1);
+    }
+
+    fn process(&mut self, _input: &[f32], output: &mut [f32]) {
+        let Some(ref mut params) = self.params else {
+            output.fill(0.0);
+            return
+        };
+
+        for frame in output.chunks_mut(self.n_channels) {
+            frame.fill(self.granulator.granulate(params));
+        }
+    }
+
+    fn reset(&mut self) {
+        // active grains are dropped
+        self.granulator = GranularSynthesis::new(self.source_table.clone(), self.sr).unwrap();
+    }
+}
@@ -5,6 +5,8 @@ use rustfft::num_complex::Complex;
 
 use crate::qubx_common::ChannelError;
 use super::{
+    qinterp::Interp,
+    qwindow::QWindow,
     qsignals::SignalObject,
     qenvelopes::{ 
         EnvelopeObject, 
@@ -67,6 +69,182 @@ pub fn split_into_nchannels(input: &mut Vec<f32>, in_channels: usize, out_channe
     Ok(())
 }
 
+/// Mixing matrix between two channel layouts
+///
+/// Channels are placed on a line from left to right. Mono is copied to all the output channels, a downmix to mono is
+/// the average of the input channels. Otherwise each output channel interpolates its two nearest input channels
+/// (upmix) or each input channel is panned between its two nearest output channels and each output is normalized by
+/// the sum of its gains (downmix)
+///
+/// # Args
+/// ------
+///
+/// `in_channels`: input channel numbers
+/// `out_channels`: output channel numbers
+///
+/// # Return
+/// --------
+///
+/// `Vec<Vec<f32>>`: gains, `matrix[out][in]`
+///
+pub fn channel_matrix(in_channels: usize, out_channels: usize) -> Vec<Vec<f32>> {
+    let mut matrix = vec![vec![0.0; in_channels]; out_channels];
+    if in_channels == 0 || out_channels == 0 { return matrix }
+
+    if in_channels == out_channels {
+        matrix.iter_mut().enumerate().for_each(|(j, row)| row[j] = 1.0);
+    } else if in_channels == 1 {
+        matrix.iter_mut().for_each(|row| row[0] = 1.0);
+    } else if out_channels == 1 {
+        matrix[0].iter_mut().for_each(|gain| *gain = 1.0 / in_channels as f32);
+    } else if in_channels < out_channels {
+        // output position on the input channels
+        for (j, row) in matrix.iter_mut().enumerate() {
+            let position = j as f32 * (in_channels - 1) as f32 / (out_channels - 1) as f32;
+            for (k, gain) in row.iter_mut().enumerate() {
+                *gain = (1.0 - (position - k as f32).abs()).max(0.0);
+            }
+        }
+    } else {
+        // input position on the output channels
+        for (j, row) in matrix.iter_mut().enumerate() {
+            for (k, gain) in row.iter_mut().enumerate() {
+                let position = k as f32 * (out_channels - 1) as f32 / (in_channels - 1) as f32;
+                *gain = (1.0 - (position - j as f32).abs()).max(0.0);
+            }
+            let sum: f32 = row.iter().sum();
+            if sum > 0.0 { row.iter_mut().for_each(|gain| *gain /= sum) }
+        }
+    }
+    matrix
+}
+
+/// Up/downmix an interleaved signal with a mixing matrix (see `channel_matrix()`)
+///
+/// # Args
+/// ------
+///
+/// `input`: interleaved input signal
+/// `in_channels`: input channel numbers
+/// `out_channels`: output signal channel numbers
+///
+/// # Return
+/// --------
+///
+/// `Result<Vec<f32>, ChannelError>`
+///
+pub fn remix_channels(input: &[f32], in_channels: usize, out_channels: usize) -> Result<Vec<f32>, ChannelError> {
+    if input.is_empty() { return Err(ChannelError::VectorIsEmpty) }
+    if in_channels == 0 || out_channels == 0 { return Err(ChannelError::ChannelNumbersError) }
+    if in_channels == out_channels { return Ok(input.to_vec()) }
+
+    let matrix = channel_matrix(in_channels, out_channels);
+    let mut output = Vec::with_capacity(input.len() / in_channels * out_channels);
+    for segment in input.chunks_exact(in_channels) {
+        for row in matrix.iter() {
+            output.push(segment.iter().zip(row.iter()).map(|(sample, gain)| sample * gain).sum());
+        }
+    }
+    Ok(output)
+}
+
+/// Resample an interleaved signal
+///
+/// Output samples are interpolated from the input (see `Interp`). When downsampling, the input is low-pass filtered
+/// first (Blackman windowed sinc at 0.45 * `sr_out`) to avoid aliasing
+///
+/// # Args
+/// ------
+///
+/// `input`: interleaved input signal
+/// `n_channels`: channel numbers
+/// `sr_in`: sample rate of the input
+/// `sr_out`: sample rate of the output
+/// `interp`: interpolation (`Interp::Cubic` or `Interp::Hermite` are recommended)
+///
+/// # Return
+/// --------
+///
+/// `Vec<f32>`: resampled signal (duration is preserved)
+///
+pub fn resample(input: &[f32], n_channels: usize, sr_in: f32, sr_out: f32, interp: Interp) -> Vec<f32> {
+    if n_channels == 0 || input.len() < n_channels || sr_in <= 0.0 || sr_out <= 0.0 || sr_in == sr_out {
+        return input.to_vec()
+    }
+
+    let in_frames = input.len() / n_channels;
+    let ratio = sr_in as f64 / sr_out as f64;
+    let out_frames = (in_frames as f64 / ratio).round() as usize;
+
+    let filtered = if sr_out < sr_in { lowpass_interleaved(input, n_channels, 0.45 * sr_out / sr_in) } else { input.to_vec() };
+    let sample = |frame: isize, channel: usize| -> f32 {
+        filtered[(frame.clamp(0, in_frames as isize - 1) as usize) * n_channels + channel]
+    };
+
+    let mut output = Vec::with_capacity(out_frames * n_channels);
+    for i in 0..out_frames {
+        let position = i as f64 * ratio;
+        let index = position.floor() as isize;
+        let mu = (position - index as f64) as f32;
+        for channel in 0..n_channels {
+            let y = match interp {
+                Interp::NoInterp => sample(index, channel),
+                Interp::Linear | Interp::Cosine => {
+                    let buffer = [sample(index, channel), sample(index + 1, channel)];
+                    interp.get_table_interpolation(mu, &buffer).unwrap_or(0.0)
+                },
+                Interp::Cubic | Interp::Hermite => {
+                    let buffer = [
+                        sample(index - 1, channel),
+                        sample(index, channel),
+                        sample(index + 1, channel),
+                        sample(index + 2, channel)
+                    ];
+                    interp.get_table_interpolation(mu, &buffer).unwrap_or(0.0)
+                }
+            };
+            output.push(y);
+        }
+    }
+    output
+}
+
+/// Low-pass FIR (Blackman windowed sinc, unity gain at DC) applied to each channel of an interleaved signal
+///
+fn lowpass_interleaved(input: &[f32], n_channels: usize, cutoff: f32) -> Vec<f32> {
+    // transition band of about 0.1 * output sr (Blackman: 5.5 / ntaps)
+    let half = (12.0 / cutoff).ceil() as usize;
+    let ntaps = 2 * half + 1;
+    let mut window = QWindow::Blackman.get_window(ntaps - 1);
+    window.push(window[0]);
+
+    let mut taps: Vec<f32> = (0..ntaps)
+        .map(|n| {
+            let t = n as f32 - half as f32;
+            let sinc = if t == 0.0 { 1.0 } else { (std::f32::consts::PI * 2.0 * cutoff * t).sin() / (std::f32::consts::PI * 2.0 * cutoff * t) };
+            sinc * window[n]
+        })
+        .collect();
+    let sum: f32 = taps.iter().sum();
+    taps.iter_mut().for_each(|tap| *tap /= sum);
+
+    let nframes = input.len() / n_channels;
+    let mut output = vec![0.0; nframes * n_channels];
+    for frame in 0..nframes {
+        for channel in 0..n_channels {
+            let mut y = 0.0;
+            for (n, tap) in taps.iter().enumerate() {
+                let k = frame as isize + n as isize - half as isize;
+                if k >= 0 && (k as usize) < nframes {
+                    y += tap * input[k as usize * n_channels + channel];
+                }
+            }
+            output[frame * n_channels + channel] = y;
+        }
+    }
+    output
+}
+
 /// Apply envelope to signal
 /// 
 /// # Args
@@ -124,3 +302,53 @@ pub fn vector_zero_padding<T: Float>(x: &[T], length: usize) -> Vec<T> {
     padded
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &[f32], b: &[f32]) {
+        assert_eq!(a.len(), b.len());
+        assert!(a.iter().zip(b.iter()).all(|(x, y)| (x - y).abs() < 1e-5), "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn remix_mono_stereo() {
+        assert_eq!(remix_channels(&[1.0, 2.0, 3.0], 1, 2).unwrap(), vec![1.0, 1.0, 2.0, 2.0, 3.0, 3.0]);
+        assert_eq!(remix_channels(&[1.0, 3.0, 2.0, -2.0], 2, 1).unwrap(), vec![2.0, 0.0]);
+        assert_eq!(remix_channels(&[1.0, 2.0], 2, 2).unwrap(), vec![1.0, 2.0]);
+
+        assert!(matches!(remix_channels(&[], 1, 2), Err(ChannelError::VectorIsEmpty)));
+        assert!(matches!(remix_channels(&[1.0], 0, 2), Err(ChannelError::ChannelNumbersError)));
+    }
+
+    #[test]
+    fn remix_multichannel() {
+        // stereo to 4 channels: the inner outputs interpolate left and right
+        let y = remix_channels(&[1.0, 0.0, 0.0, 1.0], 2, 4).unwrap();
+        assert_close(&y, &[1.0, 2.0 / 3.0, 1.0 / 3.0, 0.0, 0.0, 1.0 / 3.0, 2.0 / 3.0, 1.0]);
+
+        // and back: each output is normalized by the sum of its gains
+        let y = remix_channels(&[1.0, 1.0, 1.0, 1.0], 4, 2).unwrap();
+        assert_close(&y, &[1.0, 1.0]);
+    }
+
+    #[test]
+    fn resample_length_and_ratio() {
+        let x = (0..200).map(|i| if i % 2 == 0 { i as f32 / 200.0 } else { 0.5 }).collect::<Vec<f32>>();
+        assert_eq!(resample(&x, 2, 1000.0, 1000.0, Interp::Linear), x);
+        assert_eq!(resample(&x, 2, 1000.0, 2000.0, Interp::Linear).len(), 400);
+        assert_eq!(resample(&x, 2, 1000.0, 500.0, Interp::Linear).len(), 100);
+        assert_eq!(resample(&x, 2, 44100.0, 22050.0, Interp::Cubic).len(), 100);
+        assert_eq!(resample(&x, 2, 1000.0, 3000.0, Interp::Cubic).len(), 600);
+
+        // upsampling by 2: the input samples are kept and the others are interpolated, channels stay apart
+        let y = resample(&x, 2, 1000.0, 2000.0, Interp::Linear);
+        let left = y.iter().step_by(2).copied().collect::<Vec<f32>>();
+        assert_close(&left[..6], &[0.0, 0.005, 0.01, 0.015, 0.02, 0.025]);
+        assert!(y.iter().skip(1).step_by(2).all(|sample| (sample - 0.5).abs() < 1e-6));
+
+        // downsampling low-pass filters the input: a constant keeps its level away from the edges
+        let y = resample(&vec![1.0; 400], 1, 1000.0, 500.0, Interp::Linear);
+        assert!(y[40..160].iter().all(|sample| (sample - 1.0).abs() < 1e-3));
+    }
+}
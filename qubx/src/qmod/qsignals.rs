@@ -1,6 +1,6 @@
 #![allow(unused)]
 
-use rand::{ rngs::ThreadRng, thread_rng };
+use rand::{ rngs::StdRng, SeedableRng };
 use rand_distr::Uniform;
 use super::{ qbuffers::ReadBufferDirection, shared_tools::interp_buffer_write_from_table };
 
@@ -130,7 +130,7 @@ pub struct SignalParams
     pub read_direction_vec: ReadBufferDirection,
     pub(crate) phase_motion: f32,
     pub(crate) interp_buffer: Vec<f32>,
-    pub(crate) noise_tools: Option<(ThreadRng, Uniform<f32>)>,
+    pub(crate) noise_tools: Option<(StdRng, Uniform<f32>)>,
     pub(crate) t: f32
 }
 
@@ -160,7 +160,7 @@ impl SignalParams
     pub fn new(mode: SignalMode, freq: f32, amp: f32, phase_offset: f32, sr: f32) -> Self {
         let noise_tools = if mode == SignalMode::WhiteNoise {
             let distr = Uniform::<f32>::new(-1.0, 1.0);
-            let noise_gen = thread_rng();
+            let noise_gen = StdRng::from_entropy();
             Some((noise_gen, distr))
         } else {
             None
@@ -176,6 +176,14 @@ impl SignalParams
         }
     }
 
+    pub fn set_freq(&mut self, freq: f32) {
+        self.freq = freq
+    }
+
+    pub fn set_amp(&mut self, amp: f32) {
+        self.amp = amp
+    }
+
     pub(crate) fn update_and_set_pmotion(&mut self, value: f32, table_length: f32) {
         update_and_reset_increment(&mut self.phase_motion, value, table_length, self.read_direction_vec);
     }
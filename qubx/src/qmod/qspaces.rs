@@ -6,7 +6,8 @@ use geo::{ ConvexHull, Coord, LineString };
 use portaudio::Sample;
 use statistical::median;
 use super::qoperations::precision_float;
-use crate::{ clamp_angle, degtorad, poltocar, scale_in_range };
+use crate::{ clamp_angle, degtorad, poltocar, radtodeg, scale_in_range };
+use crate::qprocessor::Processor;
 
 
 static N_DECIMALS: i32 = 15;
@@ -309,6 +310,18 @@ impl<'a> QSpace<'a>
 		Self { space_object, line_intersecator: LineLineIntersection::default() }
 	}
 
+	/// Gains of the loudspeakers for a source position, according to the space mode
+	/// 
+	fn get_gains(&mut self, source_position: &PolarPoint) -> Result<Vec<f32>, SpaceError> {
+		match self.space_object.mode {
+			SpaceMode::StereoLinear | SpaceMode::StereoCostantPower | SpaceMode::StereoCompromise => {
+				self.stereo_pan(&radtodeg!(source_position.theta))
+			},
+			SpaceMode::Vbap => self.vbap(source_position),
+			SpaceMode::Dbap(_, _, _ ) => self.dbap(source_position)
+		}
+	}
+
 	/// Stereo pan
 	/// 
 	/// # Args
@@ -536,6 +549,79 @@ impl<'a> QSpace<'a>
 
 }
 
+/// # Space Processor
+/// 
+/// `Processor` that owns its `SpaceObject` (a `QSpace` borrows it, so it can not be moved to the audio thread).
+/// It spatializes a mono source on the loudspeakers: the stream must have one channel per loudspeaker, in the order of
+/// the space object, multichannel inputs are mixed down to mono
+/// 
+/// Example:
+/// ```ignore
+/// let space_object = SpaceObject::new(&loudspeakers, SpaceMode::Vbap)?;
+/// let mut spacer = SpaceProcessor::new(space_object);
+/// let mut source = PolarPoint::new();
+/// source.set_theta(30.0);
+/// spacer.set_source_position(source);
+/// master_out.start(ProcessArg::Processor::<MasterPatchType>(Box::new(spacer)))?;
+/// ```
+/// 
+pub struct SpaceProcessor
+{
+	pub space_object: SpaceObject,
+	source_position: PolarPoint,
+	gains: Vec<f32>
+}
+
+impl SpaceProcessor
+{
+	/// Create a new space processor
+	/// 
+	/// # Args
+	/// -----
+	/// 
+	/// `space_object`: loudspeakers configuration and space mode (see `SpaceObject`)
+	/// 
+	pub fn new(space_object: SpaceObject) -> Self {
+		let gains = vec![0.0; space_object.n_loudspeakers];
+		Self { space_object, source_position: PolarPoint::default(), gains }
+	}
+
+	/// Set the virtual source position (it can be changed between two blocks)
+	/// 
+	/// # Args
+	/// -----
+	/// 
+	/// `source_position`: virtual source position
+	/// 
+	pub fn set_source_position(&mut self, source_position: PolarPoint) {
+		self.source_position = source_position
+	}
+}
+
+/// Gains are computed once per block from the source position (see `set_source_position()`): if the position is not
+/// valid for the space mode, the last gains are kept
+/// 
+impl Processor for SpaceProcessor
+{
+	fn process(&mut self, input: &[f32], output: &mut [f32]) {
+		let n_loudspeakers = self.space_object.n_loudspeakers;
+		if let Ok(gains) = QSpace::new(&mut self.space_object).get_gains(&self.source_position) {
+			self.gains = gains;
+		}
+
+		let frames = output.len() / n_loudspeakers;
+		let in_channels = (input.len() / frames.max(1)).max(1);
+		for (i, frame) in output.chunks_exact_mut(n_loudspeakers).enumerate() {
+			let start = (i * in_channels).min(input.len());
+			let end = (start + in_channels).min(input.len());
+			let x = input[start..end].iter().sum::<f32>() / in_channels as f32;
+			for (sample, gain) in frame.iter_mut().zip(self.gains.iter()) {
+				*sample = x * gain;
+			}
+		}
+	}
+}
+
 struct LineLineIntersection
 {
 	pub(crate) start_point: CartesianPoint,
@@ -580,4 +666,25 @@ impl Default for LineLineIntersection
 	fn default() -> Self {
 		Self { start_point: CartesianPoint { x: 0.0, y: 0.0 }, end_point: CartesianPoint { x: 0.0, y: 0.0 } }
 	}
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::qubx_types::ProcessorType;
+
+	#[test]
+	fn space_processor() {
+		let space_object = SpaceObject::new(&[0.0, 90.0], SpaceMode::StereoLinear).unwrap();
+		let mut spacer = SpaceProcessor::new(space_object);
+		let mut source = PolarPoint::new();
+		source.set_theta(45.0);
+		spacer.set_source_position(source);
+
+		let mut processor: ProcessorType = Box::new(spacer);
+		processor.prepare(1000.0, 4, 2);
+		let mut output = vec![0.0; 8];
+		processor.process(&[1.0, 0.5, 0.25, 0.0], &mut output);
+		let expected = [0.5, 0.5, 0.25, 0.25, 0.125, 0.125, 0.0, 0.0];
+		assert!(output.iter().zip(expected.iter()).all(|(y, e)| (y - e).abs() < 1e-6));
+	}
+}
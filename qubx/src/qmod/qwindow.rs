@@ -9,7 +9,7 @@ pub enum WindowError
     WindowLengthExceeded
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum QWindow
 {
     Rect,
@@ -3,7 +3,7 @@ use std::path::Path;
 use std::process::{ Command, Stdio };
 use std::fs;
 use std::io::Write;
-use rand::rngs::ThreadRng;
+use rand::rngs::StdRng;
 use rand_distr::{ Distribution, Uniform };
 
 use super::qbuffers::ReadBufferDirection;
@@ -19,7 +19,7 @@ const TWOPI: f32 = 2.0 * std::f32::consts::PI;
 
 // --- SIGNAL TOOLS ---
 
-pub(crate) fn get_phase_motion(phase: f32, mode: &SignalMode, noise_tools: &mut Option<(ThreadRng, Uniform<f32>)>) -> Result<f32, SignalError> {
+pub(crate) fn get_phase_motion(phase: f32, mode: &SignalMode, noise_tools: &mut Option<(StdRng, Uniform<f32>)>) -> Result<f32, SignalError> {
     let sample = match mode {
         SignalMode::Sine => (TWOPI * phase).sin(),
         SignalMode::Saw => 1.0 - 2.0 * (phase - (phase).floor()),
@@ -0,0 +1,360 @@
+#![allow(dead_code)]
+
+use std::sync::atomic::{ AtomicU32, Ordering };
+use std::sync::Arc;
+
+/// Snap threshold of the one-pole smoothing
+///
+const SMOOTHING_EPSILON: f32 = 1e-6;
+
+/// Smoothing of a parameter (see `QParam`)
+///
+/// `None`: the new value is applied immediately
+/// `Linear(time)`: linear ramp to the new value in `time` sec. A new value during a ramp starts a new ramp from the
+/// current value
+/// `OnePole(time)`: exponential approach to the new value, `time` is the time constant in sec (~63% of the change)
+///
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Smoothing {
+    #[default]
+    None,
+    Linear(f32),
+    OnePole(f32)
+}
+
+#[derive(Debug)]
+struct ParamShared {
+    target: AtomicU32,
+    smoothing: Smoothing
+}
+
+/// # Q Param
+///
+/// Parameter shared between a control thread and the audio thread. The control side sets the target value (a single
+/// atomic store, it never blocks), the audio side reads it through a `ParamReader` per sample or per block, with the
+/// configured smoothing (see `Smoothing`). `QParam` is a handle: clones share the same value
+///
+/// The reader fits the setters of the dsp params: `SignalParams::set_freq()`, `ModulationParams::set_carrier_freq()`,
+/// `Filter::design_filter()` with `FilterParams::with_freq()`...
+///
+/// Example:
+/// ```ignore
+/// let gain = QParam::new(1.0, Smoothing::Linear(0.05));
+/// let mut reader = gain.get_reader(44100.0);
+/// let master_clos: MasterPatchType = Box::new(move |frame| {
+///     frame.chunks_mut(2).for_each(|f| { let g = reader.next_value(); f.iter_mut().for_each(|sample| *sample *= g) })
+/// });
+/// master_out.start(ProcessArg::PatchSpace(master_clos))?;
+///
+/// gain.set(0.5); // from the control thread
+/// ```
+///
+#[derive(Debug, Clone)]
+pub struct QParam {
+    shared: Arc<ParamShared>
+}
+
+impl QParam {
+    /// Create a new parameter
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `value`: initial value
+    /// `smoothing`: smoothing of the changes (see `Smoothing`)
+    ///
+    pub fn new(value: f32, smoothing: Smoothing) -> Self {
+        Self { shared: Arc::new(ParamShared { target: AtomicU32::new(value.to_bits()), smoothing }) }
+    }
+
+    /// Set the target value (real-time safe, it never blocks)
+    ///
+    pub fn set(&self, value: f32) {
+        self.shared.target.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Get the target value
+    ///
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.shared.target.load(Ordering::Relaxed))
+    }
+
+    pub fn get_smoothing(&self) -> Smoothing {
+        self.shared.smoothing
+    }
+
+    /// Create a reader for the audio side. Each reader has its own smoothing state and starts at the target value
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `sr`: sample rate of the reader
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `ParamReader`
+    ///
+    pub fn get_reader(&self, sr: f32) -> ParamReader {
+        let value = self.get();
+        ParamReader {
+            shared: Arc::clone(&self.shared),
+            sr,
+            value,
+            target: value,
+            step: 0.0,
+            remaining: 0,
+            coeff: 0.0,
+            applied: false
+        }
+    }
+}
+
+/// # Param Reader
+///
+/// Audio side of a `QParam`. It reads the target value (one atomic load per sample or per block) and smooths the
+/// changes
+///
+#[derive(Debug)]
+pub struct ParamReader {
+    shared: Arc<ParamShared>,
+    sr: f32,
+    value: f32,
+    target: f32,
+    step: f32,
+    remaining: usize,
+    coeff: f32,
+    applied: bool
+}
+
+impl ParamReader {
+    /// Set the sample rate (e.g. in `Processor::prepare()`)
+    ///
+    pub fn set_sr(&mut self, sr: f32) {
+        self.sr = sr;
+    }
+
+    /// Current (smoothed) value
+    ///
+    pub fn get_value(&self) -> f32 {
+        self.value
+    }
+
+    /// Check if the value is still moving to the target
+    ///
+    pub fn is_smoothing(&self) -> bool {
+        self.value != self.target
+    }
+
+    /// Jump to the target value
+    ///
+    pub fn reset(&mut self) {
+        self.poll();
+        self.value = self.target;
+        self.remaining = 0;
+    }
+
+    /// Read a new target value
+    ///
+    #[inline]
+    fn poll(&mut self) {
+        let target = f32::from_bits(self.shared.target.load(Ordering::Relaxed));
+        if target == self.target { return }
+
+        self.target = target;
+        match self.shared.smoothing {
+            Smoothing::None => self.value = target,
+            Smoothing::Linear(time) => {
+                let n = (time * self.sr).round().max(1.0) as usize;
+                self.step = (target - self.value) / n as f32;
+                self.remaining = n;
+            },
+            Smoothing::OnePole(time) => {
+                let n = time * self.sr;
+                self.coeff = if n > 0.0 { (-1.0 / n).exp() } else { 0.0 };
+            }
+        }
+    }
+
+    /// Advance the smoothing by `n` samples
+    ///
+    #[inline]
+    fn advance(&mut self, n: usize) {
+        match self.shared.smoothing {
+            Smoothing::None => self.value = self.target,
+            Smoothing::Linear(_) => {
+                let k = n.min(self.remaining);
+                self.value += self.step * k as f32;
+                self.remaining -= k;
+                if self.remaining == 0 {
+                    self.value = self.target;
+                }
+            },
+            Smoothing::OnePole(_) => {
+                self.value = self.target + self.coeff.powi(n as i32) * (self.value - self.target);
+                if (self.value - self.target).abs() < SMOOTHING_EPSILON {
+                    self.value = self.target;
+                }
+            }
+        }
+    }
+
+    /// Next value (per sample)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `f32`
+    ///
+    #[inline]
+    pub fn next_value(&mut self) -> f32 {
+        self.poll();
+        if self.value != self.target {
+            self.advance(1);
+        }
+        self.value
+    }
+
+    /// Advance by a block of `n` samples (per block)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `f32`: value at the end of the block
+    ///
+    pub fn next_block(&mut self, n: usize) -> f32 {
+        self.poll();
+        if self.value != self.target {
+            self.advance(n);
+        }
+        self.value
+    }
+
+    /// Write the next `buffer.len()` values
+    ///
+    pub fn fill(&mut self, buffer: &mut [f32]) {
+        self.poll();
+        if self.value == self.target {
+            buffer.fill(self.value);
+            return
+        }
+        buffer.iter_mut().for_each(|sample| {
+            self.advance(1);
+            *sample = self.value;
+        });
+    }
+
+    /// Advance by a block of `n` samples and call a setter if the value has changed (and on the first call). Useful for
+    /// params that are expensive to set (e.g. `Filter::design_filter()`)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `n`: block length in samples
+    /// `setter`: closure called with the new value
+    ///
+    /// Example:
+    /// ```ignore
+    /// let params = FilterParams::BiquadParams(BiquadFilter::Lp, 1000.0, 0.707, None);
+    /// cutoff.apply(block.len(), |fc| filter.design_filter(params.with_freq(fc)).unwrap());
+    /// signal_freq.apply(1, |freq| signal_params.set_freq(freq));
+    /// ```
+    ///
+    pub fn apply<F: FnOnce(f32)>(&mut self, n: usize, setter: F) {
+        let before = self.value;
+        let value = self.next_block(n);
+        if value != before || !self.applied {
+            self.applied = true;
+            setter(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SR: f32 = 1000.0;
+
+    fn assert_close(a: &[f32], b: &[f32]) {
+        assert_eq!(a.len(), b.len());
+        assert!(a.iter().zip(b.iter()).all(|(x, y)| (x - y).abs() < 1e-6), "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn linear_ramp_length() {
+        let param = QParam::new(0.0, Smoothing::Linear(0.004));
+        let mut reader = param.get_reader(SR);
+        param.set(1.0);
+
+        let values = (0..6).map(|_| reader.next_value()).collect::<Vec<f32>>();
+        assert_close(&values, &[0.25, 0.5, 0.75, 1.0, 1.0, 1.0]);
+        assert!(!reader.is_smoothing());
+    }
+
+    #[test]
+    fn linear_restart_mid_ramp() {
+        let param = QParam::new(0.0, Smoothing::Linear(0.004));
+        let mut reader = param.get_reader(SR);
+        param.set(1.0);
+        assert_close(&[reader.next_value(), reader.next_value()], &[0.25, 0.5]);
+
+        // the new ramp starts from the current value and has the full length
+        param.set(0.0);
+        let values = (0..5).map(|_| reader.next_value()).collect::<Vec<f32>>();
+        assert_close(&values, &[0.375, 0.25, 0.125, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn one_pole_block_matches_samples() {
+        let param = QParam::new(0.0, Smoothing::OnePole(0.01));
+        let (mut per_block, mut per_sample) = (param.get_reader(SR), param.get_reader(SR));
+        param.set(1.0);
+
+        let value = per_block.next_block(8);
+        (0..8).for_each(|_| { per_sample.next_value(); });
+        let expected = 1.0 - (-1.0f32 / 10.0).exp().powi(8);
+        assert_close(&[value, per_sample.get_value()], &[expected, expected]);
+
+        let mut buffer = vec![0.0; 4];
+        per_sample.fill(&mut buffer);
+        let values = (0..4).map(|_| per_block.next_value()).collect::<Vec<f32>>();
+        assert_close(&buffer, &values);
+    }
+
+    #[test]
+    fn one_pole_converges() {
+        let param = QParam::new(0.0, Smoothing::OnePole(0.01));
+        let mut reader = param.get_reader(SR);
+        param.set(1.0);
+
+        let values = (0..500).map(|_| reader.next_value()).collect::<Vec<f32>>();
+        assert!(values.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(values.iter().all(|value| *value <= 1.0));
+        assert_eq!(reader.get_value(), 1.0);
+        assert!(!reader.is_smoothing());
+    }
+
+    #[test]
+    fn apply_once_per_change() {
+        let param = QParam::new(100.0, Smoothing::None);
+        let mut reader = param.get_reader(SR);
+        let mut calls = Vec::new();
+
+        reader.apply(16, |value| calls.push(value));
+        reader.apply(16, |value| calls.push(value));
+        param.set(200.0);
+        reader.apply(16, |value| calls.push(value));
+        reader.apply(16, |value| calls.push(value));
+        assert_eq!(calls, vec![100.0, 200.0]);
+
+        // a ramp calls the setter on each block until the target is reached
+        let param = QParam::new(0.0, Smoothing::Linear(0.032));
+        let mut reader = param.get_reader(SR);
+        let mut calls = Vec::new();
+        reader.apply(16, |value| calls.push(value));
+        param.set(1.0);
+        (0..4).for_each(|_| reader.apply(16, |value| calls.push(value)));
+        assert_eq!(calls, vec![0.0, 0.5, 1.0]);
+    }
+}
@@ -0,0 +1,304 @@
+#![allow(dead_code)]
+
+use std::ops::{ Deref, DerefMut };
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::sync::{ Arc, Mutex, OnceLock };
+
+use concurrent_queue::ConcurrentQueue;
+
+/// # Frame Pool
+///
+/// Pool of preallocated frame buffers shared by the dsp threads and the master streamout.
+/// Dsp threads borrow a `PoolFrame` (see `acquire()`), fill it and hand it to the master. When the master has summed
+/// the frame, dropping it gives the buffer back to the pool, so in steady state no heap allocation is performed.
+/// Frames are allocated in blocks: a block never allocates more frames than its free queue can hold, so giving a frame
+/// back never fails and dropping a frame in the real-time thread never frees memory. When all the blocks are in use
+/// `acquire()` adds a new block, the frames of idle blocks are freed by the control thread of the master (see
+/// `collect_garbage()`)
+///
+#[derive(Debug, Clone)]
+pub struct FramePool {
+    blocks: Arc<PoolBlocks>,
+    frame_size: usize
+}
+
+/// Max number of blocks of a `FramePool` (each new block doubles the pool)
+///
+const MAX_BLOCKS: usize = 32;
+
+/// Blocks of a `FramePool`. Blocks are read without locking, `grow` is locked only to add a block
+///
+#[derive(Debug)]
+struct PoolBlocks {
+    blocks: Box<[OnceLock<Arc<PoolBlock>>]>,
+    grow: Mutex<()>
+}
+
+impl PoolBlocks {
+    fn iter(&self) -> impl Iterator<Item = &Arc<PoolBlock>> {
+        self.blocks.iter().map_while(|block| block.get())
+    }
+}
+
+/// Frames of a `FramePool` allocated together. At most `capacity` frames are allocated, the free queue can hold them
+/// all
+///
+#[derive(Debug)]
+struct PoolBlock {
+    free: ConcurrentQueue<Vec<f32>>,
+    allocated: AtomicUsize,
+    capacity: usize
+}
+
+impl PoolBlock {
+    fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self { free: ConcurrentQueue::bounded(capacity), allocated: AtomicUsize::new(0), capacity }
+    }
+
+    /// Allocate a new frame if the block is not full
+    ///
+    fn allocate(&self, frame_size: usize) -> Option<Vec<f32>> {
+        self.allocated
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| (n < self.capacity).then_some(n + 1))
+            .ok()
+            .map(|_| vec![0.0; frame_size])
+    }
+
+    /// Borrow a free frame or allocate a new one
+    ///
+    fn acquire(self: &Arc<Self>, frame_size: usize) -> Option<PoolFrame> {
+        let data = self.free.pop().ok().or_else(|| self.allocate(frame_size))?;
+        Some(PoolFrame { data, block: Arc::clone(self) })
+    }
+
+    /// Free the frames of the block if all of them are back
+    ///
+    fn trim(&self) {
+        if self.free.len() == self.allocated.load(Ordering::Acquire) {
+            while self.free.pop().is_ok() {
+                self.allocated.fetch_sub(1, Ordering::AcqRel);
+            }
+        }
+    }
+}
+
+impl FramePool {
+    /// Create a new frame pool
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `frame_size`: length of each frame (chunk * number of channels)
+    /// `capacity`: max number of frames of the first block of the pool (never freed)
+    /// `preallocate`: number of frames allocated at creation
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `FramePool`
+    ///
+    pub fn new(frame_size: usize, capacity: usize, preallocate: usize) -> Self {
+        let blocks: Box<[OnceLock<Arc<PoolBlock>>]> = (0..MAX_BLOCKS).map(|_| OnceLock::new()).collect();
+        let _ = blocks[0].set(Arc::new(PoolBlock::new(capacity)));
+        let pool = Self { blocks: Arc::new(PoolBlocks { blocks, grow: Mutex::new(()) }), frame_size };
+        pool.preallocate(preallocate);
+        pool
+    }
+
+    fn primary(&self) -> &Arc<PoolBlock> {
+        self.blocks.blocks[0].get().unwrap()
+    }
+
+    /// Allocate new frames into the first block of the pool (until the block is full)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `nframes`: number of frames
+    ///
+    pub fn preallocate(&self, nframes: usize) {
+        let primary = self.primary();
+        for _ in 0..nframes {
+            let Some(data) = primary.allocate(self.frame_size) else { break };
+            let _ = primary.free.push(data);
+        }
+    }
+
+    /// Borrow a frame from the pool. A new frame is allocated only if no frame is free (never call it in the
+    /// real-time thread)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `PoolFrame`: frame of length `frame_size`. The content is not cleared (see `PoolFrame::fill_from()`)
+    ///
+    pub fn acquire(&self) -> PoolFrame {
+        if let Some(frame) = self.blocks.iter().find_map(|block| block.free.pop().ok().map(|data| (data, block))) {
+            return PoolFrame { data: frame.0, block: Arc::clone(frame.1) }
+        }
+        if let Some(frame) = self.blocks.iter().find_map(|block| block.acquire(self.frame_size)) {
+            return frame
+        }
+
+        // all the blocks are in use: the new block doubles the pool (unless another thread has just added one)
+        let _grow = self.blocks.grow.lock().unwrap();
+        if let Some(frame) = self.blocks.iter().find_map(|block| block.acquire(self.frame_size)) {
+            return frame
+        }
+        let n_blocks = self.blocks.iter().count();
+        let capacity = self.blocks.iter().map(|block| block.capacity).sum::<usize>();
+        let block = Arc::new(PoolBlock::new(capacity));
+        let frame = block.acquire(self.frame_size).unwrap();
+        self.blocks.blocks.get(n_blocks).expect("frame pool exhausted").set(block).unwrap();
+        frame
+    }
+
+    /// Borrow a free frame of the first block without allocating (real-time thread)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Option<PoolFrame>`: `None` if no frame of the first block is free
+    ///
+    pub fn try_acquire(&self) -> Option<PoolFrame> {
+        let primary = self.primary();
+        let data = primary.free.pop().ok()?;
+        Some(PoolFrame { data, block: Arc::clone(primary) })
+    }
+
+    pub fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+
+    /// Number of free frames in the pool
+    ///
+    pub fn available(&self) -> usize {
+        self.blocks.iter().map(|block| block.free.len()).sum()
+    }
+
+    /// Free the frames of the blocks added by `acquire()` when all their frames are back (never called in the
+    /// real-time thread)
+    ///
+    pub fn collect_garbage(&self) {
+        self.blocks.iter().skip(1).for_each(|block| block.trim());
+    }
+}
+
+/// # Pool Frame
+///
+/// Frame buffer borrowed from a `FramePool`. It derefs to `[f32]` and goes back to its block when dropped
+///
+#[derive(Debug)]
+pub struct PoolFrame {
+    data: Vec<f32>,
+    block: Arc<PoolBlock>
+}
+
+impl PoolFrame {
+    /// Copy samples into the frame. If `source` is shorter than the frame the remaining samples are set to zero,
+    /// if it is longer it is truncated
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `source`: samples
+    ///
+    pub fn fill_from(&mut self, source: &[f32]) {
+        let n = source.len().min(self.data.len());
+        self.data[..n].copy_from_slice(&source[..n]);
+        self.data[n..].fill(0.0);
+    }
+}
+
+impl Deref for PoolFrame {
+    type Target = [f32];
+
+    fn deref(&self) -> &Self::Target {
+        &self.data
+    }
+}
+
+impl DerefMut for PoolFrame {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.data
+    }
+}
+
+impl Drop for PoolFrame {
+    fn drop(&mut self) {
+        // the free queue holds all the frames of the block: the push never fails
+        let data = std::mem::take(&mut self.data);
+        let pushed = self.block.free.push(data);
+        debug_assert!(pushed.is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuse_returned_frames() {
+        let pool = FramePool::new(4, 2, 2);
+        assert_eq!(pool.available(), 2);
+
+        let frame = pool.acquire();
+        let ptr = frame.as_ptr();
+        assert_eq!(frame.len(), 4);
+        assert_eq!(pool.available(), 1);
+
+        drop(frame);
+        assert_eq!(pool.available(), 2);
+        let frames = [pool.acquire(), pool.acquire()];
+        assert!(frames.iter().any(|frame| frame.as_ptr() == ptr));
+    }
+
+    #[test]
+    fn try_acquire_never_allocates() {
+        let pool = FramePool::new(4, 2, 1);
+        let frame = pool.try_acquire().unwrap();
+        assert!(pool.try_acquire().is_none());
+
+        drop(frame);
+        assert!(pool.try_acquire().is_some());
+    }
+
+    #[test]
+    fn grow_and_collect() {
+        let pool = FramePool::new(4, 2, 0);
+        let frames = (0..7).map(|_| pool.acquire()).collect::<Vec<PoolFrame>>();
+        assert!(frames.iter().all(|frame| frame.len() == 4));
+        // 2 + 2 + 4 frames
+        assert_eq!(pool.blocks.iter().map(|block| block.capacity).collect::<Vec<usize>>(), vec![2, 2, 4]);
+
+        // frames still borrowed are kept
+        pool.collect_garbage();
+        assert_eq!(pool.blocks.iter().map(|block| block.allocated.load(Ordering::Acquire)).sum::<usize>(), 7);
+
+        // every frame goes back to its block, the frames of the added blocks are freed
+        drop(frames);
+        assert_eq!(pool.available(), 7);
+        pool.collect_garbage();
+        assert_eq!(pool.available(), 2);
+        assert_eq!(pool.blocks.iter().map(|block| block.allocated.load(Ordering::Acquire)).sum::<usize>(), 2);
+    }
+
+    #[test]
+    fn return_frames_from_other_threads() {
+        let pool = FramePool::new(8, 4, 4);
+        let frames = (0..16).map(|_| pool.acquire()).collect::<Vec<PoolFrame>>();
+        std::thread::spawn(move || drop(frames)).join().unwrap();
+        assert_eq!(pool.available(), 16);
+    }
+
+    #[test]
+    fn fill_from() {
+        let pool = FramePool::new(4, 1, 1);
+        let mut frame = pool.acquire();
+        frame.fill_from(&[1.0, 2.0]);
+        assert_eq!(&frame[..], &[1.0, 2.0, 0.0, 0.0]);
+        frame.fill_from(&[1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert_eq!(&frame[..], &[1.0, 2.0, 3.0, 4.0]);
+    }
+}
@@ -0,0 +1,216 @@
+#![allow(dead_code)]
+
+/// # Processor
+///
+/// Stateful audio processor with a prepare/process/reset lifecycle. It can be passed in place of a closure to master,
+/// duplex and input streams (`ProcessArg::Processor`) and to dsp processes (`DspProcessArg::Processor`).
+/// Implemented by `Filter`, `DelayBuffer`, `SpaceProcessor`, `QModulation`, `QGranulator` and `PerChannel`
+///
+/// `prepare()`: called before streaming and on each restart, with the stream format
+/// `process()`: called for each block. `input` and `output` are interleaved, `output` has `n_channels` channels and at
+/// most `block_size` frames. `input` can have fewer samples (e.g. generators get an empty or silent input)
+/// `reset()`: clear the internal state (delay lines, phases, grains...), called on restart
+/// `latency()`: delay introduced by the processor in frames. Dsp processes compensate it, so that the output stays
+/// aligned with the master timeline
+/// `set_param()`, `trigger()`: called by `ProcessCommand::SetParam` and `ProcessCommand::Trigger` sent to a running
+/// master or duplex stream (see `ProcessCommandSender`), between two blocks in the audio thread. `id` and `event` are
+/// defined by the processor
+///
+/// Example:
+/// ```ignore
+/// struct Gain { gain: f32 }
+///
+/// impl Processor for Gain {
+///     fn process(&mut self, input: &[f32], output: &mut [f32]) {
+///         output.iter_mut().zip(input.iter()).for_each(|(y, x)| *y = x * self.gain);
+///     }
+/// }
+///
+/// master_out.start(ProcessArg::Processor::<MasterPatchType>(Box::new(Gain { gain: 0.7 })))?;
+/// ```
+///
+pub trait Processor: Send {
+    fn prepare(&mut self, _sr: f32, _block_size: usize, _n_channels: usize) { }
+
+    fn process(&mut self, input: &[f32], output: &mut [f32]);
+
+    fn reset(&mut self) { }
+
+    fn latency(&self) -> usize {
+        0
+    }
+
+    fn set_param(&mut self, _id: usize, _value: f32) { }
+
+    fn trigger(&mut self, _event: usize) { }
+}
+
+impl<P: Processor + ?Sized> Processor for Box<P> {
+    fn prepare(&mut self, sr: f32, block_size: usize, n_channels: usize) {
+        (**self).prepare(sr, block_size, n_channels)
+    }
+
+    fn process(&mut self, input: &[f32], output: &mut [f32]) {
+        (**self).process(input, output)
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+
+    fn latency(&self) -> usize {
+        (**self).latency()
+    }
+
+    fn set_param(&mut self, id: usize, value: f32) {
+        (**self).set_param(id, value)
+    }
+
+    fn trigger(&mut self, event: usize) {
+        (**self).trigger(event)
+    }
+}
+
+impl std::fmt::Debug for dyn Processor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Processor").field("latency", &self.latency()).finish()
+    }
+}
+
+/// Master closure as a processor (the closure processes the block in place, see `MasterStreamoutProcess::swap_patch()`)
+///
+pub(crate) struct InPlacePatch<F>(pub(crate) F);
+
+impl<F: for<'a> FnMut(&'a mut [f32]) + Send> Processor for InPlacePatch<F> {
+    fn process(&mut self, input: &[f32], output: &mut [f32]) {
+        output.iter_mut().zip(input.iter()).for_each(|(y, x)| *y = *x);
+        (self.0)(output);
+    }
+}
+
+/// Duplex closure as a processor (see `DuplexProcess::swap_patch()`). A frame of the wrong length is muted
+///
+pub(crate) struct MappedPatch<F>(pub(crate) F);
+
+impl<F: for<'a> FnMut(&'a [f32]) -> Vec<f32> + Send> Processor for MappedPatch<F> {
+    fn process(&mut self, input: &[f32], output: &mut [f32]) {
+        let frame = (self.0)(input);
+        if frame.len() == output.len() {
+            output.copy_from_slice(&frame);
+        } else {
+            output.fill(0.0);
+        }
+    }
+}
+
+/// Process a mono processor sample by sample (missing input samples are 0)
+///
+#[inline]
+pub(crate) fn process_samples<F: FnMut(f32) -> f32>(input: &[f32], output: &mut [f32], mut f: F) {
+    for (i, sample) in output.iter_mut().enumerate() {
+        *sample = f(input.get(i).copied().unwrap_or(0.0));
+    }
+}
+
+/// # Per Channel
+///
+/// Run a mono processor on each channel of a multichannel stream. An instance for each channel is created by the
+/// factory in `prepare()` (e.g. a filter per channel). If the input has fewer channels than the output, input channels
+/// are repeated
+///
+/// Example:
+/// ```ignore
+/// let lowpass = PerChannel::new(|| {
+///     let mut filter = QFilter::new(44100.0).get_filter(FilterType::Biquad);
+///     filter.design_filter(FilterParams::BiquadParams(BiquadFilter::Lp, 1000.0, 0.707, None)).unwrap();
+///     filter
+/// });
+/// master_out.start(ProcessArg::Processor::<MasterPatchType>(Box::new(lowpass)))?;
+/// ```
+///
+pub struct PerChannel<P: Processor> {
+    factory: Box<dyn FnMut() -> P + Send>,
+    channels: Vec<P>,
+    x: Vec<f32>,
+    y: Vec<f32>
+}
+
+impl<P: Processor> std::fmt::Debug for PerChannel<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PerChannel").field("channels", &self.channels.len()).finish()
+    }
+}
+
+impl<P: Processor> PerChannel<P> {
+    /// Create a multichannel processor
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `factory`: create the processor of a single channel
+    ///
+    pub fn new<F: FnMut() -> P + Send + 'static>(factory: F) -> Self {
+        Self { factory: Box::new(factory), channels: Vec::new(), x: Vec::new(), y: Vec::new() }
+    }
+
+    /// Processors of each channel (created by `prepare()`)
+    ///
+    pub fn get_channels_mut(&mut self) -> &mut [P] {
+        &mut self.channels
+    }
+}
+
+impl<P: Processor> Processor for PerChannel<P> {
+    fn prepare(&mut self, sr: f32, block_size: usize, n_channels: usize) {
+        let n_channels = n_channels.max(1);
+        while self.channels.len() < n_channels {
+            self.channels.push((self.factory)());
+        }
+        self.channels.truncate(n_channels);
+        self.channels.iter_mut().for_each(|channel| channel.prepare(sr, block_size, 1));
+        self.x = vec![0.0; block_size];
+        self.y = vec![0.0; block_size];
+    }
+
+    fn process(&mut self, input: &[f32], output: &mut [f32]) {
+        let n_channels = self.channels.len();
+        if n_channels == 0 {
+            output.fill(0.0);
+            return
+        }
+
+        let frames = output.len() / n_channels;
+        if self.x.len() < frames {
+            self.x.resize(frames, 0.0);
+            self.y.resize(frames, 0.0);
+        }
+        let in_channels = (input.len() / frames.max(1)).max(1);
+
+        for (c, channel) in self.channels.iter_mut().enumerate() {
+            let ic = c % in_channels;
+            for (i, x) in self.x[..frames].iter_mut().enumerate() {
+                *x = input.get(i * in_channels + ic).copied().unwrap_or(0.0);
+            }
+            channel.process(&self.x[..frames], &mut self.y[..frames]);
+            for (i, y) in self.y[..frames].iter().enumerate() {
+                output[i * n_channels + c] = *y;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.channels.iter_mut().for_each(|channel| channel.reset());
+    }
+
+    fn latency(&self) -> usize {
+        self.channels.iter().map(|channel| channel.latency()).max().unwrap_or(0)
+    }
+
+    fn set_param(&mut self, id: usize, value: f32) {
+        self.channels.iter_mut().for_each(|channel| channel.set_param(id, value));
+    }
+
+    fn trigger(&mut self, event: usize) {
+        self.channels.iter_mut().for_each(|channel| channel.trigger(event));
+    }
+}
@@ -0,0 +1,378 @@
+#![allow(dead_code)]
+
+use std::fs::File;
+use std::io::{ self, BufWriter, Seek, SeekFrom, Write };
+use std::sync::atomic::{ AtomicBool, AtomicU64, Ordering };
+use std::sync::{ Arc, Mutex };
+use std::thread::{ self, JoinHandle };
+use std::time::Duration;
+
+use crate::qlist::{ FrameConsumer, FrameProducer };
+use crate::qpool::FramePool;
+use crate::qring::ring_channel;
+use crate::qubx_common::{ QubxError, RecordTarget };
+
+const WAV_HEADER_SIZE: u32 = 44;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+
+/// Max number of blocks waiting for the writer (about 6 sec. with chunk 1024 at 44100 Hz)
+///
+pub(crate) const RECORD_QUEUE_CAPACITY: usize = 256;
+/// Frames of a recording pool: the full ring, the frame written by the writer and the frame filled by the audio
+/// thread, so that the audio thread never allocates
+///
+pub(crate) const RECORD_PREALLOCATED_FRAMES: usize = RECORD_QUEUE_CAPACITY + 2;
+
+/// # Wav Writer
+///
+/// Streaming wav file (32 bit float): samples are appended while recording and the header sizes are completed on
+/// `finalize()`, so that nothing is kept in memory
+///
+#[derive(Debug)]
+pub(crate) struct WavWriter {
+    writer: BufWriter<File>,
+    name: String,
+    n_channels: u16,
+    sr: u32,
+    data_bytes: u64
+}
+
+impl WavWriter {
+    /// Create the file (an existing file is overwritten)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `file_name`: file name (`.wav` is appended if missing)
+    /// `n_channels`: number of interleaved channels
+    /// `sr`: sample rate
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `io::Result<WavWriter>`
+    ///
+    pub(crate) fn create(file_name: &str, n_channels: usize, sr: u32) -> io::Result<Self> {
+        let name = if file_name.ends_with(".wav") { file_name.to_string() } else { format!("{}.wav", file_name) };
+        let file = File::create(&name)?;
+        let mut wav = Self { writer: BufWriter::new(file), name, n_channels: n_channels as u16, sr, data_bytes: 0 };
+        wav.write_header()?;
+        Ok(wav)
+    }
+
+    pub(crate) fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let data_bytes = self.data_bytes.min((u32::MAX - WAV_HEADER_SIZE) as u64) as u32;
+        let block_align = self.n_channels * 4;
+        let w = &mut self.writer;
+        w.write_all(b"RIFF")?;
+        w.write_all(&(WAV_HEADER_SIZE - 8 + data_bytes).to_le_bytes())?;
+        w.write_all(b"WAVEfmt ")?;
+        w.write_all(&16u32.to_le_bytes())?;
+        w.write_all(&WAVE_FORMAT_IEEE_FLOAT.to_le_bytes())?;
+        w.write_all(&self.n_channels.to_le_bytes())?;
+        w.write_all(&self.sr.to_le_bytes())?;
+        w.write_all(&(self.sr * block_align as u32).to_le_bytes())?;
+        w.write_all(&block_align.to_le_bytes())?;
+        w.write_all(&32u16.to_le_bytes())?;
+        w.write_all(b"data")?;
+        w.write_all(&data_bytes.to_le_bytes())
+    }
+
+    pub(crate) fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        for sample in samples.iter() {
+            self.writer.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_bytes += samples.len() as u64 * 4;
+        Ok(())
+    }
+
+    /// Complete the header and close the file
+    ///
+    pub(crate) fn finalize(mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.writer.flush()
+    }
+}
+
+/// Destination of recorded frames
+///
+/// `Memory(data)`: frames are appended to a growing vector
+/// `File(wav)`: frames are streamed to disk
+///
+#[derive(Debug)]
+pub(crate) enum RecordSink {
+    Memory(Arc<Mutex<Vec<f32>>>),
+    File(WavWriter)
+}
+
+impl RecordSink {
+    /// Open the destination of a recording
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `target`: memory or file (see `RecordTarget`)
+    /// `memory`: growing vector used by `RecordTarget::Memory` (it is cleared)
+    /// `n_channels`: number of interleaved channels
+    /// `sr`: sample rate
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<RecordSink, QubxError>`: `QubxError::RecordError` if the file can not be created
+    ///
+    pub(crate) fn open(target: &RecordTarget, memory: &Arc<Mutex<Vec<f32>>>, n_channels: usize, sr: u32) -> Result<Self, QubxError> {
+        match target {
+            RecordTarget::Memory => {
+                memory.lock().unwrap().clear();
+                Ok(Self::Memory(Arc::clone(memory)))
+            },
+            RecordTarget::File(name) => {
+                let wav = WavWriter::create(name, n_channels, sr)
+                    .map_err(|e| QubxError::RecordError(format!("{}: {}", name, e)))?;
+                Ok(Self::File(wav))
+            }
+        }
+    }
+
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        match self {
+            Self::Memory(data) => {
+                data.lock().unwrap().extend_from_slice(samples);
+                Ok(())
+            },
+            Self::File(wav) => wav.write_samples(samples)
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Self::Memory(_) => Ok(()),
+            Self::File(wav) => wav.finalize()
+        }
+    }
+}
+
+/// # Record Writer
+///
+/// Non real-time side of a recording. The audio thread pushes its blocks into a wait-free ring (see `FrameProducer`)
+/// and the writer thread moves them to memory or to disk, so that the callback never locks, allocates or touches
+/// the file system
+///
+#[derive(Debug)]
+pub(crate) struct RecordWriter {
+    done: Arc<AtomicBool>,
+    running: Arc<AtomicBool>,
+    handle: JoinHandle<io::Result<()>>
+}
+
+impl RecordWriter {
+    /// Spawn the writer thread
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `queue`: frames pushed by the audio thread
+    /// `sink`: where the frames are written
+    /// `poll`: sleep time when the queue is empty (less than the duration of the queue)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `RecordWriter`
+    ///
+    pub(crate) fn spawn(mut queue: FrameConsumer, mut sink: RecordSink, poll: Duration) -> Self {
+        let done = Arc::new(AtomicBool::new(false));
+        let done_clone = Arc::clone(&done);
+        let running = Arc::new(AtomicBool::new(true));
+        let running_clone = Arc::clone(&running);
+
+        let handle = thread::spawn(move || {
+            let mut write = || {
+                loop {
+                    // frames pushed before `done` are drained in the same pass
+                    let done = done_clone.load(Ordering::Acquire);
+                    while let Some(frame) = queue.pop() {
+                        sink.write(&frame)?;
+                    }
+                    if done || queue.is_finished() { break }
+                    thread::sleep(poll);
+                }
+                Ok(())
+            };
+            let result = write().and_then(|_| sink.finish());
+            running_clone.store(false, Ordering::Release);
+            result
+        });
+
+        Self { done, running, handle }
+    }
+
+    /// Flag cleared when the writer thread ends (also on a write error)
+    ///
+    pub(crate) fn get_running(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.running)
+    }
+
+    /// Write the remaining frames and close the sink. Call it once the audio thread has stopped pushing
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), QubxError>`: `QubxError::RecordError` if the sink could not be written
+    ///
+    pub(crate) fn finish(self) -> Result<(), QubxError> {
+        self.done.store(true, Ordering::Release);
+        match self.handle.join() {
+            Ok(result) => result.map_err(|e| QubxError::RecordError(e.to_string())),
+            Err(_) => Err(QubxError::RecordError(String::from("writer thread panicked")))
+        }
+    }
+}
+
+/// Audio side of a `MasterTap`
+///
+#[derive(Debug)]
+struct TapInput {
+    producer: FrameProducer,
+    pool: FramePool,
+    running: Arc<AtomicBool>
+}
+
+/// # Master Tap
+///
+/// Records the mix of a master streamout while it plays. The audio thread copies each block into a frame of a
+/// dedicated pool and pushes it to a `RecordWriter` through a wait-free ring. The tap is only `try_lock`ed by the
+/// audio thread: while a recording starts or stops a block can be skipped, but the callback never waits
+///
+#[derive(Debug, Default)]
+pub(crate) struct MasterTap {
+    input: Mutex<Option<TapInput>>,
+    writer: Mutex<Option<(RecordWriter, RecordTarget)>>,
+    memory: Arc<Mutex<Vec<f32>>>,
+    lost: AtomicU64
+}
+
+impl MasterTap {
+    /// Start a recording
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `target`: memory or file (see `RecordTarget`)
+    /// `n_channels`: number of channels of the master
+    /// `sr`: sample rate of the master
+    /// `chunk`: block size of the master
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), QubxError>`: `QubxError::RecordError` if a recording is already running or the file can not be
+    /// created
+    ///
+    pub(crate) fn start(&self, target: RecordTarget, n_channels: usize, sr: u32, chunk: usize) -> Result<(), QubxError> {
+        let mut writer = self.writer.lock().unwrap();
+        if writer.is_some() {
+            return Err(QubxError::RecordError(String::from("the master is already recording")))
+        }
+
+        let sink = RecordSink::open(&target, &self.memory, n_channels, sr)?;
+        let poll = Duration::from_secs_f64(chunk as f64 / sr.max(1) as f64 / 2.0);
+        let (producer, consumer) = ring_channel(RECORD_QUEUE_CAPACITY);
+        let record_writer = RecordWriter::spawn(consumer, sink, poll);
+        let running = record_writer.get_running();
+
+        self.lost.store(0, Ordering::Relaxed);
+        *self.input.lock().unwrap() = Some(TapInput {
+            producer,
+            pool: FramePool::new(chunk * n_channels, RECORD_PREALLOCATED_FRAMES, RECORD_PREALLOCATED_FRAMES),
+            running
+        });
+        *writer = Some((record_writer, target));
+        Ok(())
+    }
+
+    /// Forward a block to the writer (audio thread)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `block`: interleaved block
+    /// `wait`: wait for the writer when the ring or the pool is full (offline render only), otherwise the block is lost
+    ///
+    #[inline]
+    pub(crate) fn write(&self, block: &[f32], wait: bool) {
+        let Ok(mut input) = self.input.try_lock() else { return };
+        let Some(TapInput { producer, pool, running }) = input.as_mut() else { return };
+
+        let mut frame = loop {
+            match pool.try_acquire() {
+                Some(frame) => break frame,
+                None if wait && running.load(Ordering::Acquire) => thread::yield_now(),
+                None => {
+                    self.lost.fetch_add(1, Ordering::Relaxed);
+                    return
+                }
+            }
+        };
+        frame.fill_from(block);
+        while let Err(rejected) = producer.push(frame) {
+            if !wait || !running.load(Ordering::Acquire) {
+                self.lost.fetch_add(1, Ordering::Relaxed);
+                return
+            }
+            frame = rejected;
+            thread::yield_now();
+        }
+    }
+
+    pub(crate) fn is_recording(&self) -> bool {
+        self.writer.lock().unwrap().is_some()
+    }
+
+    /// Stop the recording: the blocks still in the ring are written and the sink is closed
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<Option<Vec<f32>>, QubxError>`: the recorded samples for `RecordTarget::Memory`, `None` for a file.
+    /// `QubxError::RecordError` if the master is not recording or the sink could not be written
+    ///
+    pub(crate) fn stop(&self) -> Result<Option<Vec<f32>>, QubxError> {
+        let mut writer = self.writer.lock().unwrap();
+        let Some((record_writer, target)) = writer.take() else {
+            return Err(QubxError::RecordError(String::from("the master is not recording")))
+        };
+
+        // dropping the producer closes the ring
+        *self.input.lock().unwrap() = None;
+        record_writer.finish()?;
+
+        let lost = self.lost.load(Ordering::Relaxed);
+        if lost > 0 {
+            println!("[ERROR] Master tap: {} blocks lost (the writer is too slow)", lost);
+        }
+
+        match target {
+            RecordTarget::Memory => Ok(Some(std::mem::take(&mut *self.memory.lock().unwrap()))),
+            RecordTarget::File(_) => Ok(None)
+        }
+    }
+}
+
+impl Drop for MasterTap {
+    fn drop(&mut self) {
+        // complete the wav header of a recording that was never stopped
+        if self.is_recording() {
+            if let Err(e) = self.stop() {
+                println!("[ERROR] {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,261 @@
+#![allow(dead_code)]
+
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+use std::sync::Arc;
+
+/// # Single-producer single-consumer ring buffer
+///
+/// Wait-free bounded queue. The producer and the consumer sides are split into `RingProducer` and `RingConsumer`
+/// (see `ring_channel()`), so that each side can only be owned by one thread. `push` and `pop` never block and
+/// never allocate.
+///
+pub struct SpscRing<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    closed: AtomicBool
+}
+
+unsafe impl<T: Send> Send for SpscRing<T> {}
+unsafe impl<T: Send> Sync for SpscRing<T> {}
+
+impl<T> SpscRing<T> {
+    fn with_capacity(capacity: usize) -> Self {
+        let slots = (0..capacity.max(1))
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect::<Vec<UnsafeCell<MaybeUninit<T>>>>()
+            .into_boxed_slice();
+
+        Self {
+            slots,
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            closed: AtomicBool::new(false)
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn len(&self) -> usize {
+        self.tail.load(Ordering::Acquire).wrapping_sub(self.head.load(Ordering::Acquire))
+    }
+}
+
+impl<T> Drop for SpscRing<T> {
+    fn drop(&mut self) {
+        let capacity = self.capacity();
+        let tail = *self.tail.get_mut();
+        let mut index = *self.head.get_mut();
+        while index != tail {
+            unsafe { (*self.slots[index % capacity].get()).assume_init_drop() };
+            index = index.wrapping_add(1);
+        }
+    }
+}
+
+/// Create a new single-producer single-consumer ring buffer
+///
+/// # Args
+/// ------
+///
+/// `capacity`: max number of elements in the ring (at least 1)
+///
+/// # Return
+/// --------
+///
+/// `(RingProducer<T>, RingConsumer<T>)`
+///
+pub fn ring_channel<T>(capacity: usize) -> (RingProducer<T>, RingConsumer<T>) {
+    let ring = Arc::new(SpscRing::with_capacity(capacity));
+    (RingProducer { ring: Arc::clone(&ring) }, RingConsumer { ring })
+}
+
+/// Producer side of a `SpscRing`. The ring is closed when the producer is dropped
+///
+pub struct RingProducer<T> {
+    ring: Arc<SpscRing<T>>
+}
+
+impl<T> RingProducer<T> {
+    /// Push value into the ring
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), T>`: if the ring is full the value is given back
+    ///
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        let capacity = self.ring.capacity();
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+        let head = self.ring.head.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) == capacity { return Err(value) }
+
+        unsafe { (*self.ring.slots[tail % capacity].get()).write(value) };
+        self.ring.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == self.ring.capacity()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.ring.capacity()
+    }
+}
+
+impl<T> std::fmt::Debug for RingProducer<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RingProducer").field("len", &self.len()).field("capacity", &self.capacity()).finish()
+    }
+}
+
+impl<T> Drop for RingProducer<T> {
+    fn drop(&mut self) {
+        self.ring.closed.store(true, Ordering::Release);
+    }
+}
+
+/// Consumer side of a `SpscRing`
+///
+pub struct RingConsumer<T> {
+    ring: Arc<SpscRing<T>>
+}
+
+impl<T> std::fmt::Debug for RingConsumer<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RingConsumer").field("len", &self.len()).field("capacity", &self.capacity()).finish()
+    }
+}
+
+impl<T> RingConsumer<T> {
+    /// Pop the oldest value from the ring
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Option<T>`: `None` if the ring is empty
+    ///
+    pub fn pop(&mut self) -> Option<T> {
+        let capacity = self.ring.capacity();
+        let head = self.ring.head.load(Ordering::Relaxed);
+        let tail = self.ring.tail.load(Ordering::Acquire);
+        if head == tail { return None }
+
+        let value = unsafe { (*self.ring.slots[head % capacity].get()).assume_init_read() };
+        self.ring.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.ring.capacity()
+    }
+
+    /// Check if the producer has been dropped (no more values will be pushed)
+    ///
+    pub fn is_closed(&self) -> bool {
+        self.ring.closed.load(Ordering::Acquire)
+    }
+
+    /// Check if the producer has been dropped and all values have been consumed
+    ///
+    pub fn is_finished(&self) -> bool {
+        self.ring.closed.load(Ordering::Acquire) && self.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_pop_in_order() {
+        let (mut producer, mut consumer) = ring_channel(4);
+        for round in 0..3 {
+            for i in 0..4 {
+                assert!(producer.push(round * 10 + i).is_ok());
+            }
+            assert!(producer.is_full());
+            assert_eq!(producer.push(99), Err(99));
+            assert_eq!(consumer.len(), 4);
+            for i in 0..4 {
+                assert_eq!(consumer.pop(), Some(round * 10 + i));
+            }
+            assert_eq!(consumer.pop(), None);
+        }
+    }
+
+    #[test]
+    fn finished_when_producer_dropped() {
+        let (mut producer, mut consumer) = ring_channel(2);
+        producer.push(1).unwrap();
+        assert!(!consumer.is_closed());
+
+        drop(producer);
+        assert!(consumer.is_closed());
+        assert!(!consumer.is_finished());
+        assert_eq!(consumer.pop(), Some(1));
+        assert!(consumer.is_finished());
+    }
+
+    #[test]
+    fn drop_remaining_values() {
+        let value = Arc::new(());
+        let (mut producer, consumer) = ring_channel(8);
+        for _ in 0..5 {
+            producer.push(Arc::clone(&value)).unwrap();
+        }
+        assert_eq!(Arc::strong_count(&value), 6);
+
+        drop(producer);
+        drop(consumer);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn threads() {
+        let (mut producer, mut consumer) = ring_channel(16);
+        let n = 100_000;
+        let t = std::thread::spawn(move || {
+            for mut i in 0..n {
+                while let Err(back) = producer.push(i) {
+                    i = back;
+                    std::thread::yield_now();
+                }
+            }
+        });
+
+        let mut expected = 0;
+        while !consumer.is_finished() {
+            match consumer.pop() {
+                Some(i) => {
+                    assert_eq!(i, expected);
+                    expected += 1;
+                },
+                None => std::thread::yield_now()
+            }
+        }
+        t.join().unwrap();
+        assert_eq!(expected, n);
+    }
+}
@@ -0,0 +1,680 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::sync::atomic::{ AtomicBool, AtomicU32, Ordering };
+use std::sync::{ Arc, Mutex };
+use std::thread;
+
+use concurrent_queue::ConcurrentQueue;
+use crate::qubx_types::BusPatchType;
+
+pub(crate) const MAX_BUSES: usize = 16;
+const COMMANDS_CAPACITY: usize = 256;
+
+/// Routing errors
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum RoutingError {
+    BusNotFound(String),
+    BusAlreadyExists(String),
+    TooManyBuses,
+    CycleDetected(String, String)
+}
+
+impl std::fmt::Display for RoutingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BusNotFound(name) => write!(f, "bus {} not found", name),
+            Self::BusAlreadyExists(name) => write!(f, "bus {} already exists", name),
+            Self::TooManyBuses => write!(f, "max number of buses ({}) reached", MAX_BUSES),
+            Self::CycleDetected(from, to) => write!(f, "routing {} -> {} creates a cycle", from, to)
+        }
+    }
+}
+
+/// Route destination of a dsp process or a bus
+///
+/// `Master`: the master streamout mix
+/// `Bus(name)`: a named bus of the same master streamout
+///
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum RouteTarget {
+    #[default]
+    Master,
+    Bus(String)
+}
+
+/// Add `src` scaled by a linear ramp (`from` -> `to` over the block) to `dst`
+///
+#[inline]
+pub(crate) fn add_ramped(dst: &mut [f32], src: &[f32], from: f32, to: f32, nchannels: usize) {
+    if from == 0.0 && to == 0.0 { return }
+    let nframes = (src.len() / nchannels).max(1) as f32;
+    let step = (to - from) / nframes;
+    for (i, (d, s)) in dst.iter_mut().zip(src.iter()).enumerate() {
+        *d += s * (from + step * (i / nchannels + 1) as f32);
+    }
+}
+
+/// Send levels toward each bus, shared with the real-time thread
+///
+#[derive(Debug)]
+pub(crate) struct SendLevels {
+    levels: [AtomicU32; MAX_BUSES]
+}
+
+impl Default for SendLevels {
+    fn default() -> Self {
+        Self { levels: std::array::from_fn(|_| AtomicU32::new(0.0f32.to_bits())) }
+    }
+}
+
+impl SendLevels {
+    pub(crate) fn set(&self, bus: usize, level: f32) {
+        self.levels[bus].store(level.max(0.0).to_bits(), Ordering::Release);
+    }
+
+    #[inline]
+    pub(crate) fn get(&self, bus: usize) -> f32 {
+        f32::from_bits(self.levels[bus].load(Ordering::Acquire))
+    }
+}
+
+/// Bus values shared with the real-time thread
+///
+#[derive(Debug)]
+pub(crate) struct BusState {
+    gain: AtomicU32,
+    muted: AtomicBool,
+    sends: SendLevels
+}
+
+impl Default for BusState {
+    fn default() -> Self {
+        Self {
+            gain: AtomicU32::new(1.0f32.to_bits()),
+            muted: AtomicBool::new(false),
+            sends: SendLevels::default()
+        }
+    }
+}
+
+impl BusState {
+    fn get_target_gain(&self) -> f32 {
+        if self.muted.load(Ordering::Acquire) { return 0.0 }
+        f32::from_bits(self.gain.load(Ordering::Acquire))
+    }
+}
+
+/// Real-time side of a bus
+///
+struct BusNode {
+    buffer: Vec<f32>,
+    effect: Option<BusPatchType>,
+    state: Arc<BusState>,
+    gain: f32,
+    send_gains: [f32; MAX_BUSES]
+}
+
+/// Bus in processing order, with its output and its sends
+///
+#[derive(Debug, Clone)]
+struct PlanNode {
+    bus: usize,
+    output: Option<usize>,
+    sends: Vec<usize>
+}
+
+enum RoutingCommand {
+    AddBus(usize, BusNode),
+    SetEffect(usize, Option<BusPatchType>),
+    SetPlan(Vec<PlanNode>)
+}
+
+enum RoutingRetired {
+    Effect(BusPatchType),
+    Plan(Vec<PlanNode>)
+}
+
+/// Control side of the routing graph (bus names, outputs and sends)
+///
+#[derive(Debug, Default)]
+struct RoutingGraph {
+    names: Vec<String>,
+    index: HashMap<String, usize>,
+    outputs: Vec<Option<usize>>,
+    sends: Vec<Vec<usize>>,
+    states: Vec<Arc<BusState>>
+}
+
+impl RoutingGraph {
+    fn get_index(&self, name: &str) -> Result<usize, RoutingError> {
+        self.index.get(name).copied().ok_or(RoutingError::BusNotFound(name.to_string()))
+    }
+
+    fn get_target(&self, target: &RouteTarget) -> Result<Option<usize>, RoutingError> {
+        match target {
+            RouteTarget::Master => Ok(None),
+            RouteTarget::Bus(name) => Ok(Some(self.get_index(name)?))
+        }
+    }
+
+    /// Check if `to` is reachable from `from`
+    ///
+    fn reaches(&self, from: usize, to: usize) -> bool {
+        let mut visited = vec![false; self.names.len()];
+        let mut stack = vec![from];
+        while let Some(node) = stack.pop() {
+            if node == to { return true }
+            if visited[node] { continue }
+            visited[node] = true;
+            stack.extend(self.outputs[node].iter().chain(self.sends[node].iter()).copied());
+        }
+        false
+    }
+
+    /// Check that the edge `from` -> `to` does not create a cycle
+    ///
+    fn validate_edge(&self, from: usize, to: usize) -> Result<(), RoutingError> {
+        if self.reaches(to, from) {
+            return Err(RoutingError::CycleDetected(self.names[from].clone(), self.names[to].clone()))
+        }
+        Ok(())
+    }
+
+    /// Processing order: each bus comes before the buses it feeds (Kahn algorithm)
+    ///
+    fn get_plan(&self) -> Vec<PlanNode> {
+        let n = self.names.len();
+        let mut indegree = vec![0usize; n];
+        for bus in 0..n {
+            for to in self.outputs[bus].iter().chain(self.sends[bus].iter()) {
+                indegree[*to] += 1;
+            }
+        }
+
+        let mut ready: Vec<usize> = (0..n).filter(|bus| indegree[*bus] == 0).collect();
+        let mut plan = Vec::with_capacity(n);
+        while let Some(bus) = ready.pop() {
+            for to in self.outputs[bus].iter().chain(self.sends[bus].iter()) {
+                indegree[*to] -= 1;
+                if indegree[*to] == 0 { ready.push(*to) }
+            }
+            plan.push(PlanNode { bus, output: self.outputs[bus], sends: self.sends[bus].clone() });
+        }
+        plan
+    }
+}
+
+/// # Routing Handle
+///
+/// Non real-time side of the buses of a master streamout. Changes are validated (no cycles) and sent to the
+/// real-time thread without locks
+///
+#[derive(Clone)]
+pub(crate) struct RoutingHandle {
+    graph: Arc<Mutex<RoutingGraph>>,
+    commands: Arc<ConcurrentQueue<RoutingCommand>>,
+    retired: Arc<ConcurrentQueue<RoutingRetired>>,
+    render: Arc<Mutex<Option<RoutingRender>>>,
+    frame_size: usize,
+    nchannels: usize
+}
+
+impl std::fmt::Debug for RoutingHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RoutingHandle").field("graph", &self.graph).finish()
+    }
+}
+
+impl RoutingHandle {
+    pub(crate) fn new(chunk: usize, nchannels: usize) -> Self {
+        let commands = Arc::new(ConcurrentQueue::bounded(COMMANDS_CAPACITY));
+        let retired = Arc::new(ConcurrentQueue::bounded(COMMANDS_CAPACITY));
+        let nchannels = nchannels.max(1);
+        let render = RoutingRender {
+            nodes: (0..MAX_BUSES).map(|_| None).collect(),
+            plan: Vec::with_capacity(MAX_BUSES),
+            commands: Arc::clone(&commands),
+            retired: Arc::clone(&retired),
+            nchannels
+        };
+
+        Self {
+            graph: Arc::new(Mutex::new(RoutingGraph::default())),
+            commands,
+            retired,
+            render: Arc::new(Mutex::new(Some(render))),
+            frame_size: chunk * nchannels,
+            nchannels
+        }
+    }
+
+    /// Take the real-time side (once, when the master streamout starts)
+    ///
+    pub(crate) fn take_render(&self) -> Option<RoutingRender> {
+        self.render.lock().unwrap().take()
+    }
+
+    /// Give the real-time side back (the master streamout failed to start)
+    ///
+    pub(crate) fn restore_render(&self, render: RoutingRender) {
+        *self.render.lock().unwrap() = Some(render);
+    }
+
+    fn send_command(&self, command: RoutingCommand) {
+        // before the master streamout starts, commands are applied directly
+        let mut render = self.render.lock().unwrap();
+        if let Some(r) = render.as_mut() {
+            r.apply(command);
+            drop(render);
+            self.collect_garbage();
+            return
+        }
+        drop(render);
+
+        let mut command = command;
+        while let Err(e) = self.commands.push(command) {
+            command = e.into_inner();
+            thread::yield_now();
+        }
+    }
+
+    /// Free plans and effects released by the real-time thread
+    ///
+    pub(crate) fn collect_garbage(&self) {
+        while self.retired.pop().is_ok() { }
+    }
+
+    pub(crate) fn get_bus_index(&self, name: &str) -> Result<usize, RoutingError> {
+        self.graph.lock().unwrap().get_index(name)
+    }
+
+    pub(crate) fn get_index(&self, target: &RouteTarget) -> Result<Option<usize>, RoutingError> {
+        self.graph.lock().unwrap().get_target(target)
+    }
+
+    pub(crate) fn create_bus(&self, name: &str) -> Result<QubxBus, RoutingError> {
+        let mut graph = self.graph.lock().unwrap();
+        if graph.index.contains_key(name) { return Err(RoutingError::BusAlreadyExists(name.to_string())) }
+        if graph.names.len() >= MAX_BUSES { return Err(RoutingError::TooManyBuses) }
+
+        let bus = graph.names.len();
+        let state = Arc::new(BusState::default());
+        graph.names.push(name.to_string());
+        graph.index.insert(name.to_string(), bus);
+        graph.outputs.push(None);
+        graph.sends.push(Vec::new());
+        graph.states.push(Arc::clone(&state));
+        let plan = graph.get_plan();
+        drop(graph);
+
+        let node = BusNode {
+            buffer: Vec::with_capacity(self.frame_size),
+            effect: None,
+            state: Arc::clone(&state),
+            gain: 1.0,
+            send_gains: [0.0; MAX_BUSES]
+        };
+        self.send_command(RoutingCommand::AddBus(bus, node));
+        self.send_command(RoutingCommand::SetPlan(plan));
+
+        Ok(QubxBus { name: name.to_string(), bus, state, routing: self.clone() })
+    }
+
+    fn set_output(&self, bus: usize, target: &RouteTarget) -> Result<(), RoutingError> {
+        let mut graph = self.graph.lock().unwrap();
+        let output = graph.get_target(target)?;
+        let previous = graph.outputs[bus].take();
+        if let Some(to) = output {
+            if let Err(e) = graph.validate_edge(bus, to) {
+                graph.outputs[bus] = previous;
+                return Err(e)
+            }
+        }
+        graph.outputs[bus] = output;
+        let plan = graph.get_plan();
+        drop(graph);
+
+        self.send_command(RoutingCommand::SetPlan(plan));
+        Ok(())
+    }
+
+    fn set_send(&self, bus: usize, to_bus: &str, level: f32) -> Result<(), RoutingError> {
+        let mut graph = self.graph.lock().unwrap();
+        let to = graph.get_index(to_bus)?;
+        let state = Arc::clone(&graph.states[bus]);
+        if graph.sends[bus].contains(&to) {
+            drop(graph);
+            state.sends.set(to, level);
+            return Ok(())
+        }
+
+        graph.validate_edge(bus, to)?;
+        graph.sends[bus].push(to);
+        let plan = graph.get_plan();
+        drop(graph);
+
+        state.sends.set(to, level);
+        self.send_command(RoutingCommand::SetPlan(plan));
+        Ok(())
+    }
+
+    fn remove_send(&self, bus: usize, to_bus: &str) -> Result<(), RoutingError> {
+        let mut graph = self.graph.lock().unwrap();
+        let to = graph.get_index(to_bus)?;
+        graph.states[bus].sends.set(to, 0.0);
+        graph.sends[bus].retain(|x| *x != to);
+        let plan = graph.get_plan();
+        drop(graph);
+
+        self.send_command(RoutingCommand::SetPlan(plan));
+        Ok(())
+    }
+
+    fn set_effect(&self, bus: usize, effect: Option<BusPatchType>) {
+        self.send_command(RoutingCommand::SetEffect(bus, effect));
+    }
+}
+
+/// # Routing Render
+///
+/// Real-time side of the buses of a master streamout. All methods are real-time safe (no locks, no allocations)
+///
+pub(crate) struct RoutingRender {
+    nodes: Vec<Option<BusNode>>,
+    plan: Vec<PlanNode>,
+    commands: Arc<ConcurrentQueue<RoutingCommand>>,
+    retired: Arc<ConcurrentQueue<RoutingRetired>>,
+    nchannels: usize
+}
+
+impl RoutingRender {
+    fn retire(&self, retired: RoutingRetired) {
+        // commands are applied only while the retired queue has room (see `prepare()`): the push never fails
+        let pushed = self.retired.push(retired);
+        debug_assert!(pushed.is_ok());
+    }
+
+    fn apply(&mut self, command: RoutingCommand) {
+        match command {
+            RoutingCommand::AddBus(bus, node) => self.nodes[bus] = Some(node),
+            RoutingCommand::SetEffect(bus, effect) => {
+                if let Some(node) = self.nodes[bus].as_mut() {
+                    if let Some(old) = std::mem::replace(&mut node.effect, effect) {
+                        self.retire(RoutingRetired::Effect(old));
+                    }
+                }
+            },
+            RoutingCommand::SetPlan(plan) => {
+                let old = std::mem::replace(&mut self.plan, plan);
+                self.retire(RoutingRetired::Plan(old));
+            }
+        }
+    }
+
+    /// Apply pending routing changes and clear all bus buffers
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `block_len`: length of the output block
+    ///
+    pub(crate) fn prepare(&mut self, block_len: usize) {
+        // a command replacing a plan or an effect waits for the next block if the retired queue is full, so the
+        // real-time thread never frees them
+        while !self.retired.is_full() {
+            let Ok(command) = self.commands.pop() else { break };
+            self.apply(command);
+        }
+
+        for node in self.nodes.iter_mut().flatten() {
+            node.buffer.clear();
+            node.buffer.resize(block_len, 0.0);
+        }
+    }
+
+    pub(crate) fn has_buses(&self) -> bool {
+        !self.plan.is_empty()
+    }
+
+    /// Get the buffer of a bus
+    ///
+    #[inline]
+    pub(crate) fn get_buffer(&mut self, bus: usize) -> Option<&mut [f32]> {
+        self.nodes.get_mut(bus).and_then(|node| node.as_mut()).map(|node| node.buffer.as_mut_slice())
+    }
+
+    /// Process all buses in order (effect, gain, sends) and mix them into their outputs
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `block`: master output block
+    ///
+    pub(crate) fn process(&mut self, block: &mut [f32]) {
+        let nchannels = self.nchannels;
+        for plan_node in self.plan.iter() {
+            let Some(node) = self.nodes[plan_node.bus].as_mut() else { continue };
+            let mut buffer = std::mem::take(&mut node.buffer);
+
+            if let Some(effect) = node.effect.as_mut() {
+                effect(&mut buffer);
+            }
+
+            let from = node.gain;
+            let to = node.state.get_target_gain();
+            node.gain = to;
+            let step = (to - from) / (buffer.len() / nchannels).max(1) as f32;
+            for (i, sample) in buffer.iter_mut().enumerate() {
+                *sample *= from + step * (i / nchannels + 1) as f32;
+            }
+
+            let mut send_gains = node.send_gains;
+            let mut levels = [0.0; MAX_BUSES];
+            for to_bus in plan_node.sends.iter() {
+                levels[*to_bus] = node.state.sends.get(*to_bus);
+            }
+
+            for to_bus in plan_node.sends.iter() {
+                let level = levels[*to_bus];
+                if let Some(dst) = self.nodes[*to_bus].as_mut() {
+                    add_ramped(&mut dst.buffer, &buffer, send_gains[*to_bus], level, nchannels);
+                }
+                send_gains[*to_bus] = level;
+            }
+
+            match plan_node.output.and_then(|o| self.nodes[o].as_mut()) {
+                Some(dst) => dst.buffer.iter_mut().zip(buffer.iter()).for_each(|(d, s)| *d += s),
+                None => block.iter_mut().zip(buffer.iter()).for_each(|(d, s)| *d += s)
+            }
+
+            if let Some(node) = self.nodes[plan_node.bus].as_mut() {
+                node.buffer = buffer;
+                node.send_gains = send_gains;
+            }
+        }
+    }
+}
+
+/// # Bus
+///
+/// Named multichannel bus of a master streamout (same number of channels). Dsp processes write into buses through
+/// their channel strip (`ChannelStrip::set_output()` and `ChannelStrip::set_send()`), buses can feed other buses
+/// (output and sends) and process the signal with an effect closure. By default a bus is mixed into the master streamout.
+/// Routing changes are validated: a change that creates a cycle is refused
+///
+/// Example:
+///
+/// ```ignore
+/// let reverb = q.create_bus(String::from("M1"), String::from("reverb")).unwrap();
+/// let reverb_clos: BusPatchType = Box::new(move |frame| { ... });
+/// reverb.set_effect(reverb_clos);
+///
+/// let submix = q.create_bus(String::from("M1"), String::from("drums")).unwrap();
+/// submix.set_send("reverb", 0.3).unwrap();
+///
+/// dsp_process.get_channel_strip().set_output(RouteTarget::Bus(String::from("drums"))).unwrap();
+/// ```
+///
+#[derive(Debug, Clone)]
+pub struct QubxBus {
+    name: String,
+    bus: usize,
+    state: Arc<BusState>,
+    routing: RoutingHandle
+}
+
+impl QubxBus {
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Set the effect closure. It takes one arg `frame`: `&mut [f32]` (bus frame to be processed)
+    ///
+    pub fn set_effect(&self, effect: BusPatchType) {
+        self.routing.set_effect(self.bus, Some(effect));
+    }
+
+    pub fn remove_effect(&self) {
+        self.routing.set_effect(self.bus, None);
+    }
+
+    /// Set the bus output
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `target`: master streamout (default) or another bus
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), RoutingError>`
+    ///
+    pub fn set_output(&self, target: RouteTarget) -> Result<(), RoutingError> {
+        self.routing.set_output(self.bus, &target)
+    }
+
+    /// Send the bus (post gain) to another bus
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `to_bus`: bus name
+    /// `level`: send level (linear)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), RoutingError>`
+    ///
+    pub fn set_send(&self, to_bus: &str, level: f32) -> Result<(), RoutingError> {
+        self.routing.set_send(self.bus, to_bus, level)
+    }
+
+    pub fn remove_send(&self, to_bus: &str) -> Result<(), RoutingError> {
+        self.routing.remove_send(self.bus, to_bus)
+    }
+
+    pub fn set_gain(&self, gain: f32) {
+        self.state.gain.store(gain.max(0.0).to_bits(), Ordering::Release);
+    }
+
+    pub fn get_gain(&self) -> f32 {
+        f32::from_bits(self.state.gain.load(Ordering::Acquire))
+    }
+
+    pub fn set_mute(&self, mute: bool) {
+        self.state.muted.store(mute, Ordering::Release);
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.state.muted.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_buses(routing: &RoutingHandle, names: &[&str]) -> Vec<QubxBus> {
+        names.iter().map(|name| routing.create_bus(name).unwrap()).collect()
+    }
+
+    #[test]
+    fn refuse_cycles() {
+        let routing = RoutingHandle::new(4, 1);
+        let buses = create_buses(&routing, &["a", "b", "c"]);
+
+        buses[0].set_output(RouteTarget::Bus(String::from("b"))).unwrap();
+        buses[1].set_send("c", 0.5).unwrap();
+
+        let cycle = RoutingError::CycleDetected(String::from("c"), String::from("a"));
+        assert_eq!(buses[2].set_output(RouteTarget::Bus(String::from("a"))), Err(cycle.clone()));
+        assert_eq!(buses[2].set_send("a", 1.0), Err(cycle));
+        assert_eq!(buses[0].set_send("a", 1.0), Err(RoutingError::CycleDetected(String::from("a"), String::from("a"))));
+
+        // the refused edges are not added
+        buses[1].remove_send("c").unwrap();
+        assert!(buses[2].set_output(RouteTarget::Bus(String::from("b"))).is_ok());
+    }
+
+    #[test]
+    fn bus_names() {
+        let routing = RoutingHandle::new(4, 1);
+        let buses = create_buses(&routing, &["a"]);
+
+        assert_eq!(routing.create_bus("a").err(), Some(RoutingError::BusAlreadyExists(String::from("a"))));
+        assert_eq!(buses[0].set_send("b", 1.0), Err(RoutingError::BusNotFound(String::from("b"))));
+        assert_eq!(routing.get_index(&RouteTarget::Master), Ok(None));
+        assert_eq!(routing.get_bus_index("a"), Ok(0));
+    }
+
+    #[test]
+    fn process_in_order() {
+        let routing = RoutingHandle::new(4, 1);
+        let buses = create_buses(&routing, &["fx", "sub"]);
+        buses[1].set_output(RouteTarget::Bus(String::from("fx"))).unwrap();
+        buses[0].set_effect(Box::new(|frame: &mut [f32]| frame.iter_mut().for_each(|sample| *sample *= 2.0)));
+
+        let mut render = routing.take_render().unwrap();
+        let mut block = vec![1.0; 4];
+        render.prepare(block.len());
+        render.get_buffer(1).unwrap().fill(0.5);
+        render.process(&mut block);
+
+        // sub -> fx (x2) -> master
+        assert_eq!(block, vec![2.0; 4]);
+    }
+
+    #[test]
+    fn wait_for_retired_room() {
+        let routing = RoutingHandle::new(4, 1);
+        let buses = create_buses(&routing, &["fx"]);
+        let mut render = routing.take_render().unwrap();
+
+        let gain = |g: f32| -> BusPatchType { Box::new(move |frame: &mut [f32]| frame.iter_mut().for_each(|sample| *sample *= g)) };
+        for _ in 0..COMMANDS_CAPACITY {
+            buses[0].set_effect(gain(1.0));
+        }
+        render.prepare(4);
+        for _ in 0..COMMANDS_CAPACITY - 1 {
+            buses[0].set_effect(gain(1.0));
+        }
+        buses[0].set_effect(gain(3.0));
+
+        // the replaced effects are never dropped in the real-time thread: the commands wait for room
+        render.prepare(4);
+        assert!(routing.retired.is_full());
+        assert_eq!(routing.commands.len(), COMMANDS_CAPACITY - 1);
+
+        routing.collect_garbage();
+        render.prepare(4);
+        assert!(routing.commands.is_empty());
+        render.get_buffer(0).unwrap().fill(1.0);
+        let mut block = vec![0.0; 4];
+        render.process(&mut block);
+        assert_eq!(block, vec![3.0; 4]);
+    }
+}
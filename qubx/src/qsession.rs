@@ -0,0 +1,741 @@
+#![allow(dead_code)]
+
+use std::collections::{ HashMap, HashSet };
+use std::path::{ Path, PathBuf };
+use serde::{ Deserialize, Serialize };
+
+use crate::qubx::Qubx;
+use crate::qubx_backend::BackendType;
+use crate::qubx_common::{ DspOptions, DspProcessArg, FadeParams, OfflineParams, ProcessArg, QubxError, RenderTarget, SourceFormat, StreamParameters };
+use crate::qubx_control::ProcessHandle;
+use crate::qubx_pmanage::{ QubxDspProcess, QubxDuplexProcess, QubxMasterProcess };
+use crate::qubx_types::{ DspHybridType, DspPatchType, MasterPatchType };
+use crate::qbuffers::AudioBuffer;
+use crate::qdevices::{ select_device, DeviceDescriptor };
+use crate::qrouting::{ QubxBus, RouteTarget };
+
+/// Session errors
+///
+/// `Io`: the session file can not be read or written
+/// `Parse`: syntax error or unknown field in the session file (with line and column)
+/// `Format`: unsupported file extension (`.toml` or `.json`)
+/// `Serialize`: the session can not be written as toml or json
+/// `Invalid`: the session is not consistent, all the problems are listed
+/// `Audio`: an audio file of a source can not be decoded
+/// `NotFound`: audio file or session entry not found
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionError {
+    Io(String),
+    Parse(String),
+    Format(String),
+    Serialize(String),
+    Invalid(Vec<String>),
+    Audio(String),
+    NotFound(String)
+}
+
+impl std::fmt::Display for SessionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Parse(e) => write!(f, "parse error: {}", e),
+            Self::Format(ext) => write!(f, "unsupported session file extension '{}' (toml or json)", ext),
+            Self::Serialize(e) => write!(f, "serialization error: {}", e),
+            Self::Invalid(errors) => {
+                write!(f, "invalid session ({} errors)", errors.len())?;
+                errors.iter().try_for_each(|e| write!(f, "\n  - {}", e))
+            },
+            Self::Audio(e) => write!(f, "can not decode audio file {}", e),
+            Self::NotFound(e) => write!(f, "{} not found", e)
+        }
+    }
+}
+
+/// Audio backend of a session (see `BackendType`)
+///
+/// `PortAudio`: real time streams on PortAudio devices (default)
+/// `Null`: dummy device clocked by a timer
+/// `FileSink { file, duration }`: offline render to `file`.wav (memory if not set), `duration` in sec (see
+/// `OfflineParams`)
+///
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(tag = "type", deny_unknown_fields)]
+pub enum SessionBackend {
+    #[default]
+    PortAudio,
+    Null,
+    FileSink {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        file: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        duration: Option<f32>
+    }
+}
+
+/// Master streamout of a session
+///
+/// # Args
+/// ------
+///
+/// `name`: master streamout name (id)
+/// `stream`: stream params, devices included (see `StreamParameters`)
+/// `buses`: names of the buses of the master streamout (see `QubxBus`)
+///
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MasterConfig {
+    pub name: String,
+    #[serde(default)]
+    pub stream: StreamParameters,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub buses: Vec<String>
+}
+
+/// Duplex stream of a session
+///
+/// # Args
+/// ------
+///
+/// `name`: duplex stream name (id)
+/// `stream`: stream params, devices included (see `StreamParameters`)
+///
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DuplexConfig {
+    pub name: String,
+    #[serde(default)]
+    pub stream: StreamParameters
+}
+
+/// Named dsp source of a session: an audio file played by a dsp process in the mix of a master streamout
+///
+/// # Args
+/// ------
+///
+/// `name`: source name (id)
+/// `master`: name of the master streamout
+/// `file`: audio file path (relative paths start from the directory of the session file). It is resampled to the
+/// sample rate of the master streamout (see `AudioBuffer`)
+/// `parallel`: use parallel-data in the dsp process
+/// `gain`: linear gain in the mix (default 1)
+/// `pan`: pan position in [-1, 1]
+/// `fade_in`, `fade_out`: fades in sec (see `FadeParams`)
+/// `output`: bus of the master streamout (default the master mix)
+///
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SourceConfig {
+    pub name: String,
+    pub master: String,
+    pub file: String,
+    pub parallel: bool,
+    pub gain: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pan: Option<f32>,
+    pub fade_in: f32,
+    pub fade_out: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>
+}
+
+impl Default for SourceConfig {
+    fn default() -> Self {
+        Self {
+
+            name: String::new(),
+            master: String::new(),
+            file: String::new(),
+            parallel: false,
+            gain: 1.0,
+            pan: None,
+            fade_in: 0.0,
+            fade_out: 0.0,
+            output: None
+
+        }
+    }
+}
+
+/// # Session Config
+///
+/// Declarative description of a Qubx topology: backend, master streamouts with their buses, duplex streams, devices
+/// and named sources. Sessions are read from and saved to TOML or JSON (round trip), unknown fields are errors.
+/// Relative paths start from `base_dir` (set by `load()`, default the current directory)
+///
+/// Example (TOML):
+/// ```ignore
+/// transport_master = "main"
+///
+/// [backend]
+/// type = "PortAudio"
+///
+/// [[master]]
+/// name = "main"
+/// buses = ["reverb"]
+/// stream = { sr = 48000, chunk = 512, outchannels = 2, outdevice_name = "speakers" }
+///
+/// [[source]]
+/// name = "drone"
+/// master = "main"
+/// file = "audio/drone.wav"
+/// gain = 0.8
+/// fade_in = 2.0
+/// output = "reverb"
+/// ```
+///
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    pub verbose: bool,
+    pub backend: SessionBackend,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transport_master: Option<String>,
+    #[serde(rename = "master", skip_serializing_if = "Vec::is_empty")]
+    pub masters: Vec<MasterConfig>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub duplex: Vec<DuplexConfig>,
+    #[serde(rename = "source", skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<SourceConfig>,
+    #[serde(skip)]
+    pub base_dir: Option<PathBuf>
+}
+
+/// Check that the names of a section are set and unique
+///
+fn check_names<'a, I: Iterator<Item = &'a str>>(section: &str, names: I, errors: &mut Vec<String>) {
+    let mut seen = HashSet::new();
+    for (i, name) in names.enumerate() {
+        if name.trim().is_empty() {
+            errors.push(format!("{} #{}: name is empty", section, i + 1));
+        } else if !seen.insert(name) {
+            errors.push(format!("{} '{}': duplicate name", section, name));
+        }
+    }
+}
+
+/// Message of a params error (without the error kind)
+///
+fn params_message(error: QubxError) -> String {
+    match error {
+        QubxError::ParamsError(message) => message,
+        e => e.to_string()
+    }
+}
+
+impl SessionConfig {
+    /// Read a session from TOML
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<SessionConfig, SessionError>`: `SessionError::Parse` with line and column on errors
+    ///
+    pub fn from_toml(s: &str) -> Result<Self, SessionError> {
+        toml::from_str(s).map_err(|e| SessionError::Parse(e.to_string()))
+    }
+
+    /// Read a session from JSON
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<SessionConfig, SessionError>`: `SessionError::Parse` with line and column on errors
+    ///
+    pub fn from_json(s: &str) -> Result<Self, SessionError> {
+        serde_json::from_str(s).map_err(|e| SessionError::Parse(e.to_string()))
+    }
+
+    /// Read a session file (`.toml` or `.json`). Relative paths in the session start from the directory of the file
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `path`: session file
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<SessionConfig, SessionError>`
+    ///
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SessionError> {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+        if extension != "toml" && extension != "json" {
+            return Err(SessionError::Format(extension))
+        }
+
+        let s = std::fs::read_to_string(path).map_err(|e| SessionError::Io(format!("{}: {}", path.display(), e)))?;
+        let mut config = if extension == "toml" { Self::from_toml(&s) } else { Self::from_json(&s) }
+            .map_err(|e| match e {
+                SessionError::Parse(e) => SessionError::Parse(format!("{}: {}", path.display(), e)),
+                e => e
+            })?;
+        config.base_dir = path.parent().map(|p| p.to_path_buf());
+        Ok(config)
+    }
+
+    pub fn to_toml(&self) -> Result<String, SessionError> {
+        toml::to_string_pretty(self).map_err(|e| SessionError::Serialize(e.to_string()))
+    }
+
+    pub fn to_json(&self) -> Result<String, SessionError> {
+        serde_json::to_string_pretty(self).map_err(|e| SessionError::Serialize(e.to_string()))
+    }
+
+    /// Save the session (`.toml` or `.json`)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `path`: session file
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), SessionError>`
+    ///
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SessionError> {
+        let path = path.as_ref();
+        let s = match path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase().as_str() {
+            "toml" => self.to_toml()?,
+            "json" => self.to_json()?,
+            extension => return Err(SessionError::Format(extension.to_string()))
+        };
+        std::fs::write(path, s).map_err(|e| SessionError::Io(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Resolve a path of the session
+    ///
+    pub fn resolve_path(&self, path: &str) -> PathBuf {
+        match &self.base_dir {
+            Some(dir) if Path::new(path).is_relative() => dir.join(path),
+            _ => PathBuf::from(path)
+        }
+    }
+
+    /// Check the session: names, references between sections, stream params, gains, pans and fades. Devices and audio
+    /// files are checked when the session is built (see `QubxSession::build()`)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), SessionError>`: `SessionError::Invalid` with all the problems found
+    ///
+    pub fn validate(&self) -> Result<(), SessionError> {
+        let mut errors = Vec::new();
+
+        check_names("master", self.masters.iter().map(|m| m.name.as_str()), &mut errors);
+        check_names("duplex", self.duplex.iter().map(|d| d.name.as_str()), &mut errors);
+        check_names("source", self.sources.iter().map(|s| s.name.as_str()), &mut errors);
+
+        for master in self.masters.iter() {
+            if let Err(e) = master.stream.validate(false) {
+                errors.push(format!("master '{}': {}", master.name, params_message(e)));
+            }
+            check_names(&format!("master '{}' bus", master.name), master.buses.iter().map(|b| b.as_str()), &mut errors);
+        }
+
+        for duplex in self.duplex.iter() {
+            if let Err(e) = duplex.stream.validate(true) {
+                errors.push(format!("duplex '{}': {}", duplex.name, params_message(e)));
+            }
+        }
+
+        for source in self.sources.iter() {
+            match self.masters.iter().find(|m| m.name == source.master) {
+                None => errors.push(format!("source '{}': master '{}' not found", source.name, source.master)),
+                Some(master) => {
+                    if let Some(bus) = &source.output {
+                        if !master.buses.contains(bus) {
+                            errors.push(format!("source '{}': bus '{}' not found in master '{}'", source.name, bus, master.name));
+                        }
+                    }
+                }
+            }
+            if source.file.trim().is_empty() {
+                errors.push(format!("source '{}': file is empty", source.name));
+            }
+            if !(source.gain.is_finite() && source.gain >= 0.0) {
+                errors.push(format!("source '{}': gain must be >= 0 (got {})", source.name, source.gain));
+            }
+            if let Some(pan) = source.pan {
+                if !(-1.0..=1.0).contains(&pan) {
+                    errors.push(format!("source '{}': pan must be in [-1, 1] (got {})", source.name, pan));
+                }
+            }
+            if let Err(e) = FadeParams::new(source.fade_in, source.fade_out).validate() {
+                errors.push(format!("source '{}': {}", source.name, params_message(e)));
+            }
+        }
+
+        if let Some(name) = &self.transport_master {
+            if !self.masters.iter().any(|m| &m.name == name) {
+                errors.push(format!("transport master '{}' not found", name));
+            }
+        }
+
+        if let SessionBackend::FileSink { duration: Some(duration), .. } = self.backend {
+            if duration.is_nan() || duration <= 0.0 {
+                errors.push(format!("backend: render duration must be > 0 (got {})", duration));
+            }
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(SessionError::Invalid(errors)) }
+    }
+
+    /// Check the devices of the streams
+    ///
+    fn validate_devices(&self, devices: &[DeviceDescriptor]) -> Result<(), SessionError> {
+        let mut errors = Vec::new();
+        let names = devices.iter().map(|d| format!("'{}'", d.name)).collect::<Vec<String>>().join(", ");
+
+        let mut check = |section: &str, name: &str, params: &StreamParameters, input: bool| {
+            let (device_name, device, channels, kind) = if input {
+                (&params.indevice_name, params.indevice, params.inchannels, "input")
+            } else {
+                (&params.outdevice_name, params.outdevice, params.outchannels, "output")
+            };
+
+            if let Some(pattern) = device_name {
+                if let Err(e) = select_device(devices, pattern, input, channels) {
+                    errors.push(format!("{} '{}': {} (devices: {})", section, name, QubxError::from(e), names));
+                }
+            } else if let Some(index) = device {
+                if !devices.iter().any(|d| d.index == index) {
+                    errors.push(format!("{} '{}': {} device {} not found", section, name, kind, index));
+                }
+            }
+        };
+
+        for master in self.masters.iter() {
+            check("master", &master.name, &master.stream, false);
+        }
+        for duplex in self.duplex.iter() {
+            check("duplex", &duplex.name, &duplex.stream, false);
+            check("duplex", &duplex.name, &duplex.stream, true);
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(SessionError::Invalid(errors)) }
+    }
+
+    fn uses_devices(&self) -> bool {
+        let selected = |params: &StreamParameters, input: bool| {
+            if input { params.indevice_name.is_some() || params.indevice.is_some() }
+            else { params.outdevice_name.is_some() || params.outdevice.is_some() }
+        };
+        self.masters.iter().any(|m| selected(&m.stream, false))
+            || self.duplex.iter().any(|d| selected(&d.stream, false) || selected(&d.stream, true))
+    }
+
+    fn get_backend_type(&self) -> BackendType {
+        match &self.backend {
+            SessionBackend::PortAudio => BackendType::PortAudio,
+            SessionBackend::Null => BackendType::Null,
+            SessionBackend::FileSink { file, duration } => {
+                let target = match file {
+                    Some(file) => RenderTarget::File(self.resolve_path(file).to_string_lossy().into_owned()),
+                    None => RenderTarget::Memory
+                };
+                BackendType::FileSink(OfflineParams { target, duration: *duration })
+            }
+        }
+    }
+}
+
+struct SessionSource {
+    process: QubxDspProcess,
+    audio: Vec<f32>,
+    format: SourceFormat,
+    fade: FadeParams
+}
+
+/// # Qubx Session
+///
+/// Qubx built from a `SessionConfig`: master streamouts, buses, duplex streams and dsp processes are created, devices
+/// are checked and the audio files of the sources are loaded. The monitoring of active processes is started (see
+/// `Qubx::start_monitoring_active_processes()`). Master and duplex streams are started with their patches (see
+/// `get_master()`, `get_duplex()`) or with `start()`
+///
+/// Example:
+/// ```ignore
+/// let mut session = QubxSession::load("live.toml")?;
+/// session.get_master("main").unwrap().start(ProcessArg::PatchSpace(master_clos))?;
+/// session.start_source("drone")?;
+/// ...
+/// session.close();
+/// ```
+///
+pub struct QubxSession {
+    qubx: Qubx,
+    masters: HashMap<String, QubxMasterProcess>,
+    buses: HashMap<(String, String), QubxBus>,
+    duplex: HashMap<String, QubxDuplexProcess>,
+    sources: HashMap<String, SessionSource>,
+    config: SessionConfig
+}
+
+impl QubxSession {
+    /// Load and build a session file (see `SessionConfig::load()` and `build()`)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<QubxSession, QubxError>`
+    ///
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, QubxError> {
+        Self::build(SessionConfig::load(path)?)
+    }
+
+    /// Build a session
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `config`: session config, checked before creating anything (see `SessionConfig::validate()`)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<QubxSession, QubxError>`: `QubxError::Session` if the session is not valid, a device is not available
+    /// or an audio file can not be loaded
+    ///
+    pub fn build(config: SessionConfig) -> Result<Self, QubxError> {
+        config.validate()?;
+
+        let mut qubx = Qubx::new(config.verbose, config.get_backend_type());
+        if matches!(config.backend, SessionBackend::PortAudio) && config.uses_devices() {
+            config.validate_devices(&qubx.get_devices()?)?;
+        }
+
+        let mut audio = HashMap::new();
+        for source in config.sources.iter() {
+            let master = config.masters.iter().find(|m| m.name == source.master).unwrap();
+            let path = config.resolve_path(&source.file);
+            if !path.is_file() {
+                return Err(SessionError::NotFound(format!("source '{}': audio file {}", source.name, path.display())).into())
+            }
+            let audio_object = AudioBuffer::new(master.stream.sr)
+                .to_audio_object(&path.to_string_lossy())
+                .map_err(|e| SessionError::Audio(format!("{} (source '{}'): {:?}", path.display(), source.name, e)))?;
+            audio.insert(source.name.clone(), audio_object);
+        }
+
+        let mut masters = HashMap::new();
+        let mut buses = HashMap::new();
+        for master in config.masters.iter() {
+            let process = qubx.create_master_streamout(master.name.clone(), master.stream.clone())?;
+            for bus in master.buses.iter() {
+                buses.insert((master.name.clone(), bus.clone()), qubx.create_bus(master.name.clone(), bus.clone())?);
+            }
+            masters.insert(master.name.clone(), process);
+        }
+        if let Some(name) = &config.transport_master {
+            qubx.set_transport_master(name.clone())?;
+        }
+
+        let mut duplex = HashMap::new();
+        for d in config.duplex.iter() {
+            duplex.insert(d.name.clone(), qubx.create_duplex_dsp_process(d.stream.clone())?);
+        }
+
+        let mut sources = HashMap::new();
+        for source in config.sources.iter() {
+            let process = qubx.create_parallel_dsp_process(source.master.clone(), source.parallel)?;
+            let strip = process.get_channel_strip();
+            strip.set_gain(source.gain);
+            if let Some(pan) = source.pan {
+                strip.set_pan(pan).map_err(|e| QubxError::ParamsError(format!("source '{}': {:?}", source.name, e)))?;
+            }
+            if let Some(bus) = &source.output {
+                strip.set_output(RouteTarget::Bus(bus.clone()))?;
+            }
+
+            let audio_object = audio.remove(&source.name).unwrap();
+            sources.insert(source.name.clone(), SessionSource {
+                process,
+                format: SourceFormat::new(audio_object.n_channels, audio_object.sr),
+                audio: audio_object.vector_signal,
+                fade: FadeParams::new(source.fade_in, source.fade_out)
+            });
+        }
+
+        if config.verbose {
+            println!(
+                "[INFO] Session: {} master streamouts, {} duplex streams, {} sources",
+                masters.len(), duplex.len(), sources.len()
+            );
+        }
+
+        // started last: a failed build leaves no monitor thread behind
+        qubx.start_monitoring_active_processes();
+        Ok(Self { qubx, masters, buses, duplex, sources, config })
+    }
+
+    pub fn get_qubx(&self) -> &Qubx {
+        &self.qubx
+    }
+
+    pub fn get_qubx_mut(&mut self) -> &mut Qubx {
+        &mut self.qubx
+    }
+
+    pub fn get_config(&self) -> &SessionConfig {
+        &self.config
+    }
+
+    pub fn get_master(&self, name: &str) -> Option<&QubxMasterProcess> {
+        self.masters.get(name)
+    }
+
+    pub fn get_bus(&self, master: &str, bus: &str) -> Option<&QubxBus> {
+        self.buses.get(&(master.to_string(), bus.to_string()))
+    }
+
+    pub fn get_duplex(&self, name: &str) -> Option<&QubxDuplexProcess> {
+        self.duplex.get(name)
+    }
+
+    /// Get the dsp process of a source (e.g. to release it, see `QubxDspProcess::release()`)
+    ///
+    pub fn get_source(&self, name: &str) -> Option<&QubxDspProcess> {
+        self.sources.get(name).map(|s| &s.process)
+    }
+
+    /// Play a source with its format and fades. A source can be started again while it plays
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `name`: source name
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<ProcessHandle, QubxError>`
+    ///
+    pub fn start_source(&self, name: &str) -> Result<ProcessHandle, QubxError> {
+        let Some(source) = self.sources.get(name) else {
+            return Err(SessionError::NotFound(format!("source '{}'", name)).into())
+        };
+        source.process.start_with(
+            DspProcessArg::Source::<DspPatchType, DspHybridType>(source.audio.clone()),
+            DspOptions::default().with_format(source.format).with_fades(source.fade)
+        )
+    }
+
+    /// Play all the sources, then start the master streamouts without patch (offline renders need the sources first,
+    /// see `Qubx::new()`)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<Vec<ProcessHandle>, QubxError>`: handles of the sources and of the master streamouts, in session order
+    ///
+    pub fn start(&self) -> Result<Vec<ProcessHandle>, QubxError> {
+        let mut handles = Vec::new();
+        for source in self.config.sources.iter() {
+            handles.push(self.start_source(&source.name)?);
+        }
+        for master in self.config.masters.iter() {
+            handles.push(self.masters[&master.name].start(ProcessArg::NoArgs::<MasterPatchType>)?);
+        }
+        Ok(handles)
+    }
+
+    /// Save the session config (see `SessionConfig::save()`)
+    ///
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SessionError> {
+        self.config.save(path)
+    }
+
+    pub fn close(&mut self) {
+        self.qubx.close_qubx();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SESSION: &str = r#"
+transport_master = "main"
+
+[backend]
+type = "FileSink"
+duration = 2.0
+
+[[master]]
+name = "main"
+buses = ["reverb"]
+stream = { sr = 48000, chunk = 512, outchannels = 2 }
+
+[[source]]
+name = "drone"
+master = "main"
+file = "audio/drone.wav"
+gain = 0.8
+pan = -0.5
+fade_in = 2.0
+output = "reverb"
+"#;
+
+    fn get_errors(config: &SessionConfig) -> Vec<String> {
+        match config.validate() {
+            Err(SessionError::Invalid(errors)) => errors,
+            other => panic!("expected invalid session, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let config = SessionConfig::from_toml(SESSION).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.masters[0].stream.chunk, 512);
+        assert_eq!(config.sources[0].output.as_deref(), Some("reverb"));
+        assert_eq!(config.sources[0].fade_out, 0.0);
+        assert!(matches!(config.backend, SessionBackend::FileSink { file: None, duration: Some(_) }));
+
+        let toml = config.to_toml().unwrap();
+        assert_eq!(SessionConfig::from_toml(&toml).unwrap().to_toml().unwrap(), toml);
+
+        let json = config.to_json().unwrap();
+        let from_json = SessionConfig::from_json(&json).unwrap();
+        assert_eq!(from_json.to_toml().unwrap(), toml);
+    }
+
+    #[test]
+    fn refuse_unknown_fields() {
+        let result = SessionConfig::from_toml("[[master]]\nname = \"main\"\nchunk = 512\n");
+        assert!(matches!(result, Err(SessionError::Parse(_))));
+    }
+
+    #[test]
+    fn validate_references() {
+        let mut config = SessionConfig::from_toml(SESSION).unwrap();
+        config.transport_master = Some(String::from("other"));
+        config.sources[0].master = String::from("other");
+        let errors = get_errors(&config);
+        assert!(errors.contains(&String::from("source 'drone': master 'other' not found")));
+        assert!(errors.contains(&String::from("transport master 'other' not found")));
+
+        let mut config = SessionConfig::from_toml(SESSION).unwrap();
+        config.sources[0].output = Some(String::from("delay"));
+        assert_eq!(get_errors(&config), vec![String::from("source 'drone': bus 'delay' not found in master 'main'")]);
+    }
+
+    #[test]
+    fn validate_values() {
+        let mut config = SessionConfig::from_toml(SESSION).unwrap();
+        config.sources.push(config.sources[0].clone());
+        config.sources[0].gain = -1.0;
+        config.sources[0].pan = Some(2.0);
+        config.backend = SessionBackend::FileSink { file: None, duration: Some(0.0) };
+        let errors = get_errors(&config);
+        assert_eq!(errors.len(), 4);
+        assert!(errors.contains(&String::from("source 'drone': duplicate name")));
+        assert!(errors.contains(&String::from("source 'drone': gain must be >= 0 (got -1)")));
+        assert!(errors.contains(&String::from("source 'drone': pan must be in [-1, 1] (got 2)")));
+        assert!(errors.contains(&String::from("backend: render duration must be > 0 (got 0)")));
+    }
+}
@@ -0,0 +1,607 @@
+#![allow(dead_code)]
+
+use std::sync::atomic::{ AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering };
+use std::sync::{ Arc, Mutex };
+
+use crate::qrouting::{ RouteTarget, RoutingError, RoutingHandle, SendLevels };
+use crate::qspaces::{ QSpace, SpaceError, SpaceMode, SpaceObject };
+
+/// Shortest release in sec: a source released without fade-out is ramped down over a few ms to avoid a click
+///
+pub(crate) const DECLICK_TIME: f32 = 0.005;
+
+/// Smoothing time in sec of the channel strip gains (see `StripRamp`)
+///
+pub(crate) const RAMP_TIME: f32 = 0.01;
+
+/// Pack the left and right pan gains into one word, so that the real-time thread never reads a torn pair
+///
+#[inline]
+fn pack_pan(left: f32, right: f32) -> u64 {
+    (left.to_bits() as u64) | ((right.to_bits() as u64) << 32)
+}
+
+#[inline]
+fn unpack_pan(gains: u64) -> [f32; 2] {
+    [f32::from_bits(gains as u32), f32::from_bits((gains >> 32) as u32)]
+}
+
+/// Channel strip values shared with the real-time thread
+///
+#[derive(Debug)]
+pub(crate) struct StripState {
+    gain: AtomicU32,
+    pan_gains: AtomicU64,
+    muted: AtomicBool,
+    solo: AtomicBool,
+    solo_count: Arc<AtomicUsize>,
+    output: AtomicUsize,
+    sends: SendLevels,
+    release_epoch: AtomicU64,
+    release_frames: AtomicUsize
+}
+
+impl StripState {
+    fn new(solo_count: Arc<AtomicUsize>) -> Self {
+        Self {
+            gain: AtomicU32::new(1.0f32.to_bits()),
+            pan_gains: AtomicU64::new(pack_pan(1.0, 1.0)),
+            muted: AtomicBool::new(false),
+            solo: AtomicBool::new(false),
+            solo_count,
+            output: AtomicUsize::new(usize::MAX),
+            sends: SendLevels::default(),
+            release_epoch: AtomicU64::new(0),
+            release_frames: AtomicUsize::new(usize::MAX)
+        }
+    }
+
+    /// Release the sources of the process that are in the mix (see `SourceFade`)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `frames`: fade-out length in frames (`None`: fade-out of each source)
+    ///
+    pub(crate) fn release(&self, frames: Option<usize>) {
+        self.release_frames.store(frames.unwrap_or(usize::MAX), Ordering::Release);
+        self.release_epoch.fetch_add(1, Ordering::AcqRel);
+    }
+
+    #[inline]
+    pub(crate) fn get_release_epoch(&self) -> u64 {
+        self.release_epoch.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    fn get_release_frames(&self) -> Option<usize> {
+        let frames = self.release_frames.load(Ordering::Acquire);
+        if frames == usize::MAX { None } else { Some(frames) }
+    }
+
+    /// Output bus index (`None` is the master streamout)
+    ///
+    #[inline]
+    pub(crate) fn get_output(&self) -> Option<usize> {
+        let output = self.output.load(Ordering::Acquire);
+        if output == usize::MAX { None } else { Some(output) }
+    }
+
+    #[inline]
+    pub(crate) fn get_send(&self, bus: usize) -> f32 {
+        self.sends.get(bus)
+    }
+
+    /// Target gain for each channel (pan is applied only on stereo streams)
+    ///
+    pub(crate) fn get_target(&self, nchannels: usize) -> [f32; 2] {
+        let silenced = self.muted.load(Ordering::Acquire)
+            || (self.solo_count.load(Ordering::Acquire) > 0 && !self.solo.load(Ordering::Acquire));
+        if silenced { return [0.0, 0.0] }
+
+        let gain = f32::from_bits(self.gain.load(Ordering::Acquire));
+        if nchannels == 2 {
+            unpack_pan(self.pan_gains.load(Ordering::Acquire)).map(|pan| gain * pan)
+        } else {
+            [gain, gain]
+        }
+    }
+}
+
+impl Drop for StripState {
+    fn drop(&mut self) {
+        if *self.solo.get_mut() {
+            self.solo_count.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+/// Smoothed gains of a single queue in the master mix. Gains move linearly toward the strip values over
+/// `ramp_frames` frames (see `RAMP_TIME`), whatever the block size, so that changes are click-free. A new value
+/// during a ramp starts a new ramp from the current gains
+///
+#[derive(Debug)]
+pub(crate) struct StripRamp {
+    state: Arc<StripState>,
+    current: [f32; 2],
+    target: [f32; 2],
+    step: [f32; 2],
+    remaining: usize,
+    ramp_frames: usize
+}
+
+impl StripRamp {
+    pub(crate) fn new(state: Arc<StripState>, nchannels: usize, ramp_frames: usize) -> Self {
+        let current = state.get_target(nchannels);
+        Self { state, current, target: current, step: [0.0, 0.0], remaining: 0, ramp_frames: ramp_frames.max(1) }
+    }
+
+    #[inline]
+    pub(crate) fn get_state(&self) -> &StripState {
+        &self.state
+    }
+
+    /// Read the strip values before the next block and start a new ramp if they have changed
+    ///
+    pub(crate) fn prepare(&mut self, nchannels: usize) {
+        let target = self.state.get_target(nchannels);
+        if target == self.target { return }
+        self.target = target;
+        self.remaining = self.ramp_frames;
+        let n = self.ramp_frames as f32;
+        for ((step, t), c) in self.step.iter_mut().zip(target.iter()).zip(self.current.iter()) {
+            *step = (t - c) / n;
+        }
+    }
+
+    /// Gain at frame `frame` of the current block for channel `channel`
+    ///
+    #[inline]
+    pub(crate) fn gain_at(&self, frame: usize, channel: usize) -> f32 {
+        let c = channel.min(1);
+        if frame < self.remaining {
+            self.current[c] + self.step[c] * (frame + 1) as f32
+        } else {
+            self.target[c]
+        }
+    }
+
+    /// Move to the end of the block
+    ///
+    pub(crate) fn advance(&mut self, block_frames: usize) {
+        let n = block_frames.min(self.remaining);
+        self.remaining -= n;
+        for ((c, step), t) in self.current.iter_mut().zip(self.step.iter()).zip(self.target.iter()) {
+            *c = if self.remaining == 0 { *t } else { *c + *step * n as f32 };
+        }
+    }
+}
+
+/// Fade-in, fade-out and release of a single queue in the master mix (linear, sample accurate). `length` is the number
+/// of frames of a one-shot source (without the padding of the last frame), streams have no length and fade out only
+/// on release. A released queue is silent at the end of the fade-out (at least `declick` frames) and can be removed
+///
+#[derive(Debug)]
+pub(crate) struct SourceFade {
+    fade_in: usize,
+    fade_out: usize,
+    declick: usize,
+    length: Option<usize>,
+    played: usize,
+    release_epoch: u64,
+    release: Option<(usize, usize)>,
+    done: bool
+}
+
+impl SourceFade {
+    /// Create the fades of a queue
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `fade_in`, `fade_out`: fade lengths in frames
+    /// `length`: frames of a one-shot source (`None` for streams)
+    /// `sr`: sample rate of the master streamout (shortest release, see `DECLICK_TIME`)
+    ///
+    pub(crate) fn new(fade_in: usize, fade_out: usize, length: Option<usize>, sr: f32) -> Self {
+        let declick = (DECLICK_TIME * sr).round() as usize;
+        Self { fade_in, fade_out, declick, length, played: 0, release_epoch: 0, release: None, done: false }
+    }
+
+    /// Releases requested before the queue was registered are ignored
+    ///
+    pub(crate) fn set_release_epoch(&mut self, epoch: u64) {
+        self.release_epoch = epoch;
+    }
+
+    /// Check for a new release of the strip (once per block)
+    ///
+    pub(crate) fn update_release(&mut self, state: &StripState) {
+        let epoch = state.get_release_epoch();
+        if epoch <= self.release_epoch || self.done { return }
+        self.release_epoch = epoch;
+
+        // a playing source is never cut in the middle of a block
+        let frames = state.get_release_frames().unwrap_or(self.fade_out).max(self.declick);
+        let start = self.played;
+        // a queue that has not started yet is removed at once
+        if start == 0 || frames == 0 {
+            self.done = true;
+            return
+        }
+        // a running release ends no later than before
+        let end = match self.release {
+            Some((s, n)) => (start + frames).min(s + n),
+            None => start + frames
+        };
+        self.release = Some((start, end - start));
+    }
+
+    #[inline]
+    pub(crate) fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Check if the gain is not 1 at the current position
+    ///
+    #[inline]
+    pub(crate) fn is_fading(&self, block_frames: usize) -> bool {
+        self.release.is_some()
+            || self.played < self.fade_in
+            || self.length.is_some_and(|length| self.played + block_frames + self.fade_out > length)
+    }
+
+    /// Gain at frame `frame` from the start of the source
+    ///
+    #[inline]
+    pub(crate) fn gain_at(&self, frame: usize) -> f32 {
+        let mut gain = 1.0;
+        if frame < self.fade_in {
+            gain *= frame as f32 / self.fade_in as f32;
+        }
+        if let Some(length) = self.length {
+            if self.fade_out > 0 && frame + self.fade_out > length {
+                gain *= length.saturating_sub(frame) as f32 / self.fade_out as f32;
+            }
+        }
+        if let Some((start, frames)) = self.release {
+            gain *= 1.0 - ((frame.saturating_sub(start) + 1) as f32 / frames as f32).min(1.0);
+        }
+        gain
+    }
+
+    /// Move forward by `frames` played frames
+    ///
+    #[inline]
+    pub(crate) fn advance(&mut self, frames: usize) {
+        self.played += frames;
+        if let Some((start, n)) = self.release {
+            if self.played >= start + n {
+                self.done = true;
+            }
+        }
+    }
+
+    #[inline]
+    pub(crate) fn get_played(&self) -> usize {
+        self.played
+    }
+}
+
+/// # Channel Strip
+///
+/// Control handle of a dsp process in the master mix: gain, pan, mute and solo. All values can be changed while
+/// the stream is running and are smoothed over 10 ms in the master callback (no zipper noise, see `RAMP_TIME`).
+/// Pan uses the `QSpace` stereo laws and it is applied only if the master streamout has two channels.
+/// If any strip of the same master streamout is soloed, all the other strips are silenced
+///
+/// Example:
+///
+/// ```ignore
+/// let strip = dsp_process.get_channel_strip();
+/// strip.set_gain_db(-6.0);
+/// strip.set_pan(-0.5).unwrap();
+/// strip.set_solo(true);
+/// ```
+///
+#[derive(Debug, Clone)]
+pub struct ChannelStrip {
+    state: Arc<StripState>,
+    pan_law: Arc<Mutex<SpaceMode>>,
+    pan: Arc<AtomicU32>,
+    pan_enabled: Arc<AtomicBool>,
+    routing: RoutingHandle
+}
+
+impl ChannelStrip {
+    pub(crate) fn new(solo_count: Arc<AtomicUsize>, routing: RoutingHandle) -> Self {
+        Self {
+            state: Arc::new(StripState::new(solo_count)),
+            pan_law: Arc::new(Mutex::new(SpaceMode::StereoCostantPower)),
+            pan: Arc::new(AtomicU32::new(0.0f32.to_bits())),
+            pan_enabled: Arc::new(AtomicBool::new(false)),
+            routing
+        }
+    }
+
+    pub(crate) fn get_state(&self) -> Arc<StripState> {
+        Arc::clone(&self.state)
+    }
+
+    /// Set gain
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `gain`: linear gain (>= 0)
+    ///
+    pub fn set_gain(&self, gain: f32) {
+        self.state.gain.store(gain.max(0.0).to_bits(), Ordering::Release);
+    }
+
+    /// Set gain in dB
+    ///
+    pub fn set_gain_db(&self, db: f32) {
+        self.set_gain(10.0f32.powf(db / 20.0));
+    }
+
+    pub fn get_gain(&self) -> f32 {
+        f32::from_bits(self.state.gain.load(Ordering::Acquire))
+    }
+
+    /// Set pan
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `pan`: position in [-1, 1] (-1 left, 0 center, 1 right)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), SpaceError>`
+    ///
+    pub fn set_pan(&self, pan: f32) -> Result<(), SpaceError> {
+        let pan = pan.clamp(-1.0, 1.0);
+        let angle = (pan as f64 + 1.0) * 45.0;
+
+        let mut law = self.pan_law.lock().unwrap();
+        let mode = std::mem::replace(&mut *law, SpaceMode::StereoCostantPower);
+        let mut space_object = SpaceObject::new(&[0.0, 90.0], mode)?;
+        let gains = QSpace::new(&mut space_object).stereo_pan(&angle);
+        *law = space_object.mode;
+        drop(law);
+
+        let gains = gains?;
+        self.state.pan_gains.store(pack_pan(gains[0], gains[1]), Ordering::Release);
+        self.pan.store(pan.to_bits(), Ordering::Release);
+        self.pan_enabled.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    pub fn get_pan(&self) -> f32 {
+        f32::from_bits(self.pan.load(Ordering::Acquire))
+    }
+
+    /// Remove pan: both channels are left untouched (default)
+    ///
+    pub fn reset_pan(&self) {
+        self.state.pan_gains.store(pack_pan(1.0, 1.0), Ordering::Release);
+        self.pan.store(0.0f32.to_bits(), Ordering::Release);
+        self.pan_enabled.store(false, Ordering::Release);
+    }
+
+    /// Set the stereo law used by `set_pan()` and apply it to the current pan position
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `law`: `SpaceMode::StereoLinear`, `SpaceMode::StereoCostantPower` (default) or `SpaceMode::StereoCompromise`
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), SpaceError>`
+    ///
+    pub fn set_pan_law(&self, law: SpaceMode) -> Result<(), SpaceError> {
+        match law {
+            SpaceMode::StereoLinear | SpaceMode::StereoCostantPower | SpaceMode::StereoCompromise => { },
+            _ => return Err(SpaceError::ModeNotAllowedInStereo)
+        }
+
+        *self.pan_law.lock().unwrap() = law;
+        if self.pan_enabled.load(Ordering::Acquire) {
+            self.set_pan(self.get_pan())?;
+        }
+        Ok(())
+    }
+
+    pub fn set_mute(&self, mute: bool) {
+        self.state.muted.store(mute, Ordering::Release);
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.state.muted.load(Ordering::Acquire)
+    }
+
+    pub fn set_solo(&self, solo: bool) {
+        let previous = self.state.solo.swap(solo, Ordering::AcqRel);
+        if previous != solo {
+            if solo {
+                self.state.solo_count.fetch_add(1, Ordering::AcqRel);
+            } else {
+                self.state.solo_count.fetch_sub(1, Ordering::AcqRel);
+            }
+        }
+    }
+
+    pub fn is_solo(&self) -> bool {
+        self.state.solo.load(Ordering::Acquire)
+    }
+
+    /// Set the output of the dsp process
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `target`: master streamout (default) or a bus of the same master streamout (see `QubxBus`)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), RoutingError>`
+    ///
+    pub fn set_output(&self, target: RouteTarget) -> Result<(), RoutingError> {
+        let output = self.routing.get_index(&target)?;
+        self.state.output.store(output.unwrap_or(usize::MAX), Ordering::Release);
+        Ok(())
+    }
+
+    /// Send the dsp process (post fader) to a bus
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `to_bus`: bus name
+    /// `level`: send level (linear, smoothed)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), RoutingError>`
+    ///
+    pub fn set_send(&self, to_bus: &str, level: f32) -> Result<(), RoutingError> {
+        let bus = self.routing.get_bus_index(to_bus)?;
+        self.state.sends.set(bus, level);
+        Ok(())
+    }
+
+    pub fn remove_send(&self, to_bus: &str) -> Result<(), RoutingError> {
+        self.set_send(to_bus, 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_strip() -> ChannelStrip {
+        ChannelStrip::new(Arc::new(AtomicUsize::new(0)), RoutingHandle::new(4, 2))
+    }
+
+    fn block_gains(ramp: &mut StripRamp, block_frames: usize) -> Vec<f32> {
+        ramp.prepare(1);
+        let gains = (0..block_frames).map(|frame| ramp.gain_at(frame, 0)).collect();
+        ramp.advance(block_frames);
+        gains
+    }
+
+    #[test]
+    fn strip_targets() {
+        let strip = create_strip();
+        let state = strip.get_state();
+        strip.set_gain(0.5);
+        strip.set_pan(-1.0).unwrap();
+        let [left, right] = state.get_target(2);
+        assert!((left - 0.5).abs() < 1e-6 && right.abs() < 1e-6);
+        assert_eq!(state.get_target(1), [0.5, 0.5]);
+
+        strip.reset_pan();
+        assert_eq!(state.get_target(2), [0.5, 0.5]);
+        strip.set_mute(true);
+        assert_eq!(state.get_target(2), [0.0, 0.0]);
+        strip.set_mute(false);
+
+        // a soloed strip silences the others
+        let other = ChannelStrip::new(Arc::clone(&state.solo_count), RoutingHandle::new(4, 2));
+        other.set_solo(true);
+        assert_eq!(state.get_target(2), [0.0, 0.0]);
+        drop(other);
+        assert_eq!(state.get_target(2), [0.5, 0.5]);
+    }
+
+    #[test]
+    fn ramp_length_independent_of_block() {
+        let strip = create_strip();
+        let mut ramp = StripRamp::new(strip.get_state(), 1, 8);
+        assert_eq!(block_gains(&mut ramp, 4), vec![1.0; 4]);
+
+        strip.set_gain(0.0);
+        assert_eq!(block_gains(&mut ramp, 4), vec![0.875, 0.75, 0.625, 0.5]);
+        assert_eq!(block_gains(&mut ramp, 4), vec![0.375, 0.25, 0.125, 0.0]);
+        assert_eq!(block_gains(&mut ramp, 4), vec![0.0; 4]);
+
+        // the same ramp inside one larger block
+        strip.set_gain(1.0);
+        let gains = block_gains(&mut ramp, 16);
+        assert_eq!(&gains[..8], &[0.125, 0.25, 0.375, 0.5, 0.625, 0.75, 0.875, 1.0]);
+        assert_eq!(&gains[8..], &[1.0; 8]);
+    }
+
+    #[test]
+    fn ramp_restarts_from_current_gain() {
+        let strip = create_strip();
+        let mut ramp = StripRamp::new(strip.get_state(), 1, 4);
+        strip.set_gain(0.0);
+        assert_eq!(block_gains(&mut ramp, 2), vec![0.75, 0.5]);
+
+        strip.set_gain(1.0);
+        assert_eq!(block_gains(&mut ramp, 2), vec![0.625, 0.75]);
+        assert_eq!(block_gains(&mut ramp, 4), vec![0.875, 1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn source_fades() {
+        let fade = SourceFade::new(4, 4, Some(16), 1000.0);
+        let gains = (0..16).map(|frame| fade.gain_at(frame)).collect::<Vec<f32>>();
+        assert_eq!(&gains[..4], &[0.0, 0.25, 0.5, 0.75]);
+        assert_eq!(&gains[4..12], &[1.0; 8]);
+        assert_eq!(&gains[12..], &[1.0, 0.75, 0.5, 0.25]);
+
+        // fading while the block overlaps a fade
+        assert!(fade.is_fading(4));
+        let mut fade = fade;
+        fade.advance(4);
+        assert!(!fade.is_fading(8));
+        fade.advance(1);
+        assert!(fade.is_fading(8));
+        assert!(!fade.is_done());
+    }
+
+    #[test]
+    fn release_source() {
+        let strip = create_strip();
+        let state = strip.get_state();
+
+        // released after 8 frames: linear ramp down over the fade-out, then done
+        let mut fade = SourceFade::new(0, 4, None, 0.0);
+        fade.advance(8);
+        state.release(None);
+        fade.update_release(&state);
+        assert_eq!((8..13).map(|frame| fade.gain_at(frame)).collect::<Vec<f32>>(), vec![0.75, 0.5, 0.25, 0.0, 0.0]);
+        fade.advance(3);
+        assert!(!fade.is_done());
+        fade.advance(1);
+        assert!(fade.is_done());
+
+        // releases before the registration are ignored, a source not yet started is removed at once
+        let mut fade = SourceFade::new(0, 4, None, 0.0);
+        fade.set_release_epoch(state.get_release_epoch());
+        fade.update_release(&state);
+        assert!(!fade.is_done());
+        state.release(Some(100));
+        fade.update_release(&state);
+        assert!(fade.is_done());
+
+        // a source without fade-out is ramped down over the declick time
+        let mut fade = SourceFade::new(0, 0, None, 1000.0);
+        fade.set_release_epoch(state.get_release_epoch());
+        fade.advance(1);
+        state.release(None);
+        fade.update_release(&state);
+        fade.advance(4);
+        assert!(!fade.is_done());
+        fade.advance(1);
+        assert!(fade.is_done());
+    }
+}
@@ -0,0 +1,303 @@
+#![allow(dead_code)]
+
+use std::sync::atomic::{ AtomicBool, AtomicU32, AtomicU64, Ordering };
+
+use crate::qubx_common::QubxError;
+
+const DEFAULT_TEMPO: f32 = 120.0;
+
+/// Musical position of the transport
+///
+/// `bar`: bar number (starting from 1)
+/// `beat`: beat in the bar (starting from 1)
+/// `fraction`: position inside the beat in [0, 1)
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BarBeat {
+    pub bar: u64,
+    pub beat: u32,
+    pub fraction: f64
+}
+
+/// # Transport
+///
+/// Musical clock shared across processes. It follows the sample clock of one master streamout
+/// (see `Qubx::set_transport_master()`) and exposes tempo, time signature, beat position and play/stop state.
+/// The transport is owned by `Qubx` (see `Qubx::get_transport()`) and can be read from any closure
+/// (clone the `Arc` into the closure). Beats are in `1 / denominator` note values.
+///
+/// Example:
+///
+/// ```ignore
+/// let transport = q.get_transport();
+/// transport.set_tempo(96.0)?;
+/// transport.play();
+///
+/// let t = Arc::clone(&transport);
+/// let master_clos: MasterPatchType = Box::new(move |frame| {
+///     let pos = t.get_bar_beat();
+///     if pos.beat == 1 { frame.iter_mut().for_each(|sample| *sample *= 1.2) }
+/// });
+/// ```
+///
+#[derive(Debug)]
+pub struct Transport {
+    sr: AtomicU32,
+    tempo: AtomicU32,
+    numerator: AtomicU32,
+    denominator: AtomicU32,
+    playing: AtomicBool,
+    position: AtomicU64,
+    beats: AtomicU64
+}
+
+impl Transport {
+    /// Create a new transport (120 bpm, 4/4, stopped)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `sr`: sample rate
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Transport`
+    ///
+    pub fn new(sr: f32) -> Self {
+        Self {
+            sr: AtomicU32::new(sr.to_bits()),
+            tempo: AtomicU32::new(DEFAULT_TEMPO.to_bits()),
+            numerator: AtomicU32::new(4),
+            denominator: AtomicU32::new(4),
+            playing: AtomicBool::new(false),
+            position: AtomicU64::new(0),
+            beats: AtomicU64::new(0.0f64.to_bits())
+        }
+    }
+
+    pub(crate) fn set_sample_rate(&self, sr: f32) {
+        self.sr.store(sr.to_bits(), Ordering::Release);
+    }
+
+    pub fn get_sample_rate(&self) -> f32 {
+        f32::from_bits(self.sr.load(Ordering::Acquire))
+    }
+
+    /// Set tempo
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `bpm`: beats per minute (must be > 0)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), QubxError>`: `QubxError::ParamsError` if the tempo is not > 0 (the tempo is not changed)
+    ///
+    pub fn set_tempo(&self, bpm: f32) -> Result<(), QubxError> {
+        if !bpm.is_finite() || bpm <= 0.0 {
+            return Err(QubxError::ParamsError(format!("tempo must be > 0 (found {})", bpm)))
+        }
+        self.tempo.store(bpm.to_bits(), Ordering::Release);
+        Ok(())
+    }
+
+    pub fn get_tempo(&self) -> f32 {
+        f32::from_bits(self.tempo.load(Ordering::Acquire))
+    }
+
+    /// Set time signature
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `numerator`: beats per bar
+    /// `denominator`: beat note value (4 = quarter note, 8 = eighth note...)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), QubxError>`: `QubxError::ParamsError` if a value is 0 (the time signature is not changed)
+    ///
+    pub fn set_time_signature(&self, numerator: u32, denominator: u32) -> Result<(), QubxError> {
+        if numerator == 0 || denominator == 0 {
+            return Err(QubxError::ParamsError(format!("invalid time signature {}/{}", numerator, denominator)))
+        }
+        self.numerator.store(numerator, Ordering::Release);
+        self.denominator.store(denominator, Ordering::Release);
+        Ok(())
+    }
+
+    /// Get time signature
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `(u32, u32)`: (numerator, denominator)
+    ///
+    pub fn get_time_signature(&self) -> (u32, u32) {
+        (self.numerator.load(Ordering::Acquire), self.denominator.load(Ordering::Acquire))
+    }
+
+    pub fn play(&self) {
+        self.playing.store(true, Ordering::Release);
+    }
+
+    pub fn stop(&self) {
+        self.playing.store(false, Ordering::Release);
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing.load(Ordering::Acquire)
+    }
+
+    /// Move the transport to a beat position. The sample position is set to the located beat converted at the current
+    /// tempo (see `get_sample_position()`)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `beat`: beat position (0 is the start)
+    ///
+    pub fn locate(&self, beat: f64) {
+        let beat = beat.max(0.0);
+        self.beats.store(beat.to_bits(), Ordering::Release);
+        self.position.store(self.beats_to_samples(beat) as u64, Ordering::Release);
+    }
+
+    pub fn rewind(&self) {
+        self.locate(0.0);
+    }
+
+    /// Get transport position in samples: sample frames played since the start. `locate()` sets it to the located beat
+    /// converted at the tempo of the locate, so after a locate that follows tempo changes it is no longer the number of
+    /// frames played (the beat position is tracked independently)
+    ///
+    pub fn get_sample_position(&self) -> u64 {
+        self.position.load(Ordering::Acquire)
+    }
+
+    /// Get transport position in sec
+    ///
+    pub fn get_seconds(&self) -> f64 {
+        self.get_sample_position() as f64 / self.get_sample_rate() as f64
+    }
+
+    /// Get transport position in beats
+    ///
+    pub fn get_beat_position(&self) -> f64 {
+        f64::from_bits(self.beats.load(Ordering::Acquire))
+    }
+
+    /// Get transport position in bars and beats
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `BarBeat`
+    ///
+    pub fn get_bar_beat(&self) -> BarBeat {
+        let beats = self.get_beat_position();
+        let numerator = self.numerator.load(Ordering::Acquire) as u64;
+        let whole = beats.floor() as u64;
+        BarBeat {
+            bar: whole / numerator + 1,
+            beat: (whole % numerator) as u32 + 1,
+            fraction: beats.fract()
+        }
+    }
+
+    /// Get the length of one beat in samples at the current tempo
+    ///
+    pub fn samples_per_beat(&self) -> f64 {
+        self.get_sample_rate() as f64 * 60.0 / self.get_tempo() as f64
+    }
+
+    /// Convert beats to sec at the current tempo
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `beats`: number of beats
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `f32`
+    ///
+    pub fn beats_to_seconds(&self, beats: f64) -> f32 {
+        (beats * 60.0 / self.get_tempo() as f64) as f32
+    }
+
+    /// Convert sec to beats at the current tempo
+    ///
+    pub fn seconds_to_beats(&self, seconds: f32) -> f64 {
+        seconds as f64 * self.get_tempo() as f64 / 60.0
+    }
+
+    /// Convert beats to samples at the current tempo
+    ///
+    pub fn beats_to_samples(&self, beats: f64) -> f64 {
+        beats * self.samples_per_beat()
+    }
+
+    /// Advance the transport (if playing). Called by the master streamout that drives the transport
+    ///
+    pub(crate) fn advance(&self, frames: u64) {
+        if !self.is_playing() { return }
+        let beats = self.get_beat_position() + frames as f64 / self.samples_per_beat();
+        self.beats.store(beats.to_bits(), Ordering::Release);
+        self.position.fetch_add(frames, Ordering::AcqRel);
+    }
+}
+
+impl Default for Transport {
+    fn default() -> Self {
+        Self::new(44100.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuse_invalid_values() {
+        let transport = Transport::new(1000.0);
+        assert!(matches!(transport.set_tempo(0.0), Err(QubxError::ParamsError(_))));
+        assert!(matches!(transport.set_tempo(f32::NAN), Err(QubxError::ParamsError(_))));
+        assert!(matches!(transport.set_time_signature(3, 0), Err(QubxError::ParamsError(_))));
+        assert_eq!(transport.get_tempo(), DEFAULT_TEMPO);
+        assert_eq!(transport.get_time_signature(), (4, 4));
+
+        transport.set_tempo(60.0).unwrap();
+        transport.set_time_signature(3, 4).unwrap();
+        assert_eq!(transport.get_tempo(), 60.0);
+        assert_eq!(transport.get_time_signature(), (3, 4));
+    }
+
+    #[test]
+    fn follow_tempo_changes() {
+        let transport = Transport::new(1000.0);
+        transport.set_tempo(60.0).unwrap();
+        transport.advance(1000);
+        assert_eq!(transport.get_beat_position(), 0.0);
+
+        transport.play();
+        transport.advance(2000);
+        transport.set_tempo(120.0).unwrap();
+        transport.advance(1500);
+        assert_eq!(transport.get_beat_position(), 5.0);
+        assert_eq!(transport.get_sample_position(), 3500);
+        assert_eq!(transport.get_bar_beat(), BarBeat { bar: 2, beat: 2, fraction: 0.0 });
+
+        // the located beat is converted at the current tempo
+        transport.locate(5.0);
+        assert_eq!(transport.get_beat_position(), 5.0);
+        assert_eq!(transport.get_sample_position(), 2500);
+        transport.rewind();
+        assert_eq!(transport.get_sample_position(), 0);
+    }
+}
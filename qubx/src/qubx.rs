@@ -6,9 +6,14 @@
     unused_imports
 )]
 
-use crate::qubx_common::{Process, ProcessState, StreamParameters};
-use crate::qubx_components::{DspProcess, DuplexProcess, MasterStreamoutProcess, MonitorProcess};
-use crate::qubx_pmanage::{QubxDspProcess, QubxDuplexProcess, QubxMasterProcess};
+use crate::qubx_backend::BackendType;
+use crate::qubx_common::{Process, ProcessState, QubxError, StreamParameters};
+use crate::qubx_components::{DspProcess, DuplexProcess, InputProcess, MasterStreamoutProcess, MonitorProcess};
+use crate::qubx_control::WAKEUP_INTERVAL;
+use crate::qubx_pmanage::{QubxDspProcess, QubxDuplexProcess, QubxInputProcess, QubxMasterProcess};
+use crate::qtransport::Transport;
+use crate::qdevices::{DeviceDescriptor, list_devices};
+use crate::qrouting::QubxBus;
 use portaudio as pa;
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
@@ -25,10 +30,14 @@ pub struct Qubx {
     pub verbose: bool,
     master_streamouts: HashMap<String, Arc<Mutex<MasterStreamoutProcess>>>,
     duplex_streams: Vec<Arc<Mutex<DuplexProcess>>>,
+    input_streams: Vec<Arc<Mutex<InputProcess>>>,
     pub processes_monitor_ptr: Arc<Mutex<MonitorProcess>>,
     run: Arc<AtomicBool>,
     dsp_latency_amount: Arc<Mutex<Duration>>,
-    count_dsp_iterations: Arc<Mutex<f32>>
+    count_dsp_iterations: Arc<Mutex<f32>>,
+    backend: BackendType,
+    transport: Arc<Transport>,
+    transport_master: Option<String>
 }
 
 impl Qubx {
@@ -38,10 +47,15 @@ impl Qubx {
     /// ------
     ///
     /// `verbose`: print out the state and the latency of the active processes
+    /// `backend`: audio backend used by master and duplex streams (see `BackendType`). With
+    /// `BackendType::FileSink` no audio device is opened and streams are rendered faster than real time.
+    /// In this case start dsp processes before starting the master streamout: the render stops as soon as
+    /// all queues are drained (or when the render duration is elapsed, if set)
     ///
-    pub fn new(verbose: bool) -> Self {
+    pub fn new(verbose: bool, backend: BackendType) -> Self {
         let master_streamouts: HashMap<String, Arc<Mutex<MasterStreamoutProcess>>> = HashMap::new();
         let duplex_streams: Vec<Arc<Mutex<DuplexProcess>>> = Vec::new();
+        let input_streams: Vec<Arc<Mutex<InputProcess>>> = Vec::new();
         let processes_monitor: MonitorProcess = MonitorProcess::new(verbose);
         let processes_monitor_ptr = Arc::new(Mutex::new(processes_monitor));
 
@@ -49,23 +63,101 @@ impl Qubx {
             verbose,
             master_streamouts,
             duplex_streams,
+            input_streams,
             processes_monitor_ptr,
             run: Arc::new(AtomicBool::new(true)),
             dsp_latency_amount: Arc::new(Mutex::new(Duration::new(0, 0))),
             count_dsp_iterations: Arc::new(Mutex::new(0.0)),
+            backend,
+            transport: Arc::new(Transport::default()),
+            transport_master: None
         }
     }
 
+    /// Get the audio backend type
+    ///
+    pub fn get_backend(&self) -> &BackendType {
+        &self.backend
+    }
+
+    /// Check if Qubx is in offline mode (`BackendType::FileSink`)
+    ///
+    pub fn is_offline(&self) -> bool {
+        self.backend.is_offline()
+    }
+
+    /// Get the transport shared across processes (see `Transport`)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Arc<Transport>`: clone it into master, duplex or dsp closures to read tempo and beat position
+    ///
+    pub fn get_transport(&self) -> Arc<Transport> {
+        Arc::clone(&self.transport)
+    }
+
+    /// Set the master streamout that drives the transport. By default the transport follows the
+    /// first master streamout created
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `master_streamout_name`: the name of the master streamout
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), QubxError>`
+    ///
+    pub fn set_transport_master(&mut self, master_streamout_name: String) -> Result<(), QubxError> {
+        let Some(master) = self.master_streamouts.get(&master_streamout_name) else {
+            return Err(QubxError::MasterNotFound(master_streamout_name))
+        };
+
+        for m in self.master_streamouts.values() {
+            m.lock().unwrap().drive_transport.store(false, Ordering::Release);
+        }
+
+        let m = master.lock().unwrap();
+        self.transport.set_sample_rate(m.params.sr as f32);
+        m.drive_transport.store(true, Ordering::Release);
+        drop(m);
+
+        self.transport_master = Some(master_streamout_name);
+        Ok(())
+    }
+
     /// # Get devices index and info
     ///
+    /// Print out all the audio devices (see `get_devices()`)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), QubxError>`
     ///
-    pub fn get_devices_info(&self) {
-        let port_audio = pa::PortAudio::new().unwrap();
-        let devices = port_audio.devices().unwrap();
-        for device in devices {
-            let d = &device.unwrap();
-            print!("\n[{:?}]: {:?}\n", d.0, d.1);
+    pub fn get_devices_info(&self) -> Result<(), QubxError> {
+        for d in self.get_devices()? {
+            print!("\n[{}]: {:?}\n", d.index, d);
         }
+        Ok(())
+    }
+
+    /// Get all the audio devices
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<Vec<DeviceDescriptor>, QubxError>`: name, host API, max number of channels, default sample rate and
+    /// latency of each device. Select a device by name with `StreamParameters::outdevice_name` and
+    /// `StreamParameters::indevice_name`
+    ///
+    pub fn get_devices(&self) -> Result<Vec<DeviceDescriptor>, QubxError> {
+        let port_audio = pa::PortAudio::new().map_err(|e| QubxError::DeviceError(e.to_string()))?;
+        let devices = list_devices(&port_audio);
+        let _ = port_audio.terminate();
+        Ok(devices?)
     }
 
     /// Create master streamout
@@ -79,21 +171,37 @@ impl Qubx {
     /// # Return
     /// --------
     ///
-    /// `QubxMasterProcess`
+    /// `Result<QubxMasterProcess, QubxError>`
     pub fn create_master_streamout(
         &mut self,
         name: String,
         params: StreamParameters,
-    ) -> QubxMasterProcess {
-        let master_process =
-            MasterStreamoutProcess::new(name.clone(), params, Arc::clone(&self.run), self.verbose);
+    ) -> Result<QubxMasterProcess, QubxError> {
+        if self.master_streamouts.contains_key(&name) {
+            return Err(QubxError::MasterAlreadyExists(name))
+        }
+        params.validate(false)?;
+
+        let master_process = MasterStreamoutProcess::new(
+            name.clone(),
+            params,
+            Arc::clone(&self.run),
+            self.verbose,
+            self.backend.clone(),
+            Arc::clone(&self.transport)
+        );
         let shared_master = Arc::new(Mutex::new(master_process));
         self.master_streamouts
             .insert(name.clone(), Arc::clone(&shared_master));
-        QubxMasterProcess::new(
+
+        if self.transport_master.is_none() {
+            self.set_transport_master(name)?;
+        }
+
+        Ok(QubxMasterProcess::new(
             Arc::clone(&self.processes_monitor_ptr),
             Arc::clone(&shared_master),
-        )
+        ))
     }
 
     /// Create duplex streamout
@@ -106,15 +214,61 @@ impl Qubx {
     /// # Return
     /// --------
     ///
-    /// `QubxDuplexProcess`
-    pub fn create_duplex_dsp_process(&mut self, params: StreamParameters) -> QubxDuplexProcess {
-        let duplex_process = DuplexProcess::new(params, Arc::clone(&self.run), self.verbose);
+    /// `Result<QubxDuplexProcess, QubxError>`
+    pub fn create_duplex_dsp_process(&mut self, params: StreamParameters) -> Result<QubxDuplexProcess, QubxError> {
+        params.validate(true)?;
+
+        let duplex_process = DuplexProcess::new(params, Arc::clone(&self.run), self.verbose, self.backend.clone());
         let shared_duplex = Arc::new(Mutex::new(duplex_process));
         self.duplex_streams.push(Arc::clone(&shared_duplex));
-        QubxDuplexProcess::new(
+        Ok(QubxDuplexProcess::new(
             Arc::clone(&self.processes_monitor_ptr),
             Arc::clone(&shared_duplex),
-        )
+        ))
+    }
+
+    /// Create input stream (capture only)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `params`: input stream params (`inchannels`, `indevice` or `indevice_name`, `outchannels` is ignored)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<QubxInputProcess, QubxError>`
+    pub fn create_input_process(&mut self, params: StreamParameters) -> Result<QubxInputProcess, QubxError> {
+        params.validate_input()?;
+
+        let input_process = InputProcess::new(params, Arc::clone(&self.run), self.verbose, self.backend.clone());
+        let shared_input = Arc::new(Mutex::new(input_process));
+        self.input_streams.push(Arc::clone(&shared_input));
+        Ok(QubxInputProcess::new(
+            Arc::clone(&self.processes_monitor_ptr),
+            Arc::clone(&shared_input),
+        ))
+    }
+
+    /// Create a named bus on a master streamout. Dsp processes and other buses of the same master streamout
+    /// can write into it (see `QubxBus` and `ChannelStrip`)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `master_streamout_name`: the name of the master streamout
+    /// `bus_name`: bus name (id)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<QubxBus, QubxError>`
+    pub fn create_bus(&self, master_streamout_name: String, bus_name: String) -> Result<QubxBus, QubxError> {
+        let Some(master_ptr) = self.master_streamouts.get(&master_streamout_name) else {
+            return Err(QubxError::MasterNotFound(master_streamout_name))
+        };
+        let master = master_ptr.lock().unwrap();
+        Ok(master.create_bus(&bus_name)?)
     }
 
     /// Create dsp process
@@ -128,14 +282,16 @@ impl Qubx {
     /// # Return
     /// --------
     ///
-    /// `QubxDspProcess`
-    pub fn create_parallel_dsp_process(&self, master_streamout_name: String, use_parallel: bool) -> QubxDspProcess {
+    /// `Result<QubxDspProcess, QubxError>`
+    pub fn create_parallel_dsp_process(&self, master_streamout_name: String, use_parallel: bool) -> Result<QubxDspProcess, QubxError> {
+        let Some(master_ptr) = self.master_streamouts.get(&master_streamout_name) else {
+            return Err(QubxError::MasterNotFound(master_streamout_name))
+        };
 
     	if use_parallel {
      		println!("[INFO] Parallel computation activated on DspProcess:::[{}]", master_streamout_name.clone());
      	}
 
-        let master_ptr = self.master_streamouts.get(&master_streamout_name).unwrap();
         let dsp_process = DspProcess::new(
             Arc::clone(&self.processes_monitor_ptr),
             Arc::clone(master_ptr),
@@ -144,10 +300,10 @@ impl Qubx {
             Arc::clone(&self.count_dsp_iterations),
             use_parallel
         );
-        QubxDspProcess::new(
+        Ok(QubxDspProcess::new(
             Arc::clone(&self.processes_monitor_ptr),
             Arc::new(Mutex::new(dsp_process)),
-        )
+        ))
     }
 
     /// Starts monitoring active processes
@@ -156,11 +312,18 @@ impl Qubx {
         let monitor_clone = Arc::clone(&self.processes_monitor_ptr);
         let local_run = Arc::clone(&self.run);
 
+        let events = self.processes_monitor_ptr.lock().unwrap().get_events();
+
+        // inactive processes are removed when a process changes its state (polling only while a stopped process has
+        // not exited yet)
         let t = thread::spawn(move || {
+            let mut seen = events.get_count();
             while local_run.load(Ordering::Acquire) {
                 let mut m = monitor_clone.lock().unwrap();
                 m.remove_inactive_processes();
+                let exiting = m.has_exiting_processes();
                 drop(m);
+                seen = if exiting { events.wait_timeout(seen, WAKEUP_INTERVAL) } else { events.wait(seen) };
             }
         });
 
@@ -179,14 +342,16 @@ impl Qubx {
     pub fn close_qubx(&mut self) {
         println!("[INFO] Closing QUBX System...");
         self.run.store(false, Ordering::Release);
+        self.processes_monitor_ptr.lock().unwrap().get_events().notify();
 
-        let count = self.count_dsp_iterations.lock().unwrap();
-        let lat_amount = self.dsp_latency_amount.lock().unwrap();
-        let fac = if *count > 0.0 { *count } else { 1.0 };
+        // copied: the dsp threads still running update the stats when they exit
+        let count = *self.count_dsp_iterations.lock().unwrap();
+        let lat_amount = *self.dsp_latency_amount.lock().unwrap();
+        let fac = if count > 0.0 { count } else { 1.0 };
         let lat_amount = lat_amount.as_secs_f32() / fac;
         print!(
             "\n[PROCESSES INFO]\n:::Process Name: \"DSP\"\n:::Number of started processes: {}\n:::Latency average: {:?}\n\n",
-            *count as i32,
+            count as i32,
             std::time::Duration::from_secs_f32(lat_amount),
         );
 
@@ -194,7 +359,21 @@ impl Qubx {
         println!("[PROCESS INFO] Terminating last active processes...");
         let pclone = Arc::clone(&self.processes_monitor_ptr);
         let mut p = pclone.lock().unwrap();
-        p.join_and_remove_all();
+        // the monitor is released before joining: the threads update their state when they exit
+        let mut all = p.take_processes();
+        drop(p);
+        all.join_and_remove_all();
+
+        // complete the recordings that were not stopped
+        for (name, master) in self.master_streamouts.iter() {
+            let master = master.lock().unwrap();
+            if master.is_recording() {
+                println!("[INFO] Closing recording of {} stream-out...", name);
+                if let Err(e) = master.stop_recording() {
+                    println!("[ERROR] {}", e);
+                }
+            }
+        }
 
         thread::sleep(std::time::Duration::from_secs_f32(0.5));
         println!("[INFO] Done!");
@@ -0,0 +1,810 @@
+#![allow(dead_code)]
+
+use crate::qubx_common::{ OfflineParams, QubxError, RenderTarget, StreamParameters };
+use crate::qmod::shared_tools::write_to_file;
+use crate::qmetrics::ProcessMetrics;
+use crate::qdevices::resolve_device;
+use portaudio as pa;
+use pa::stream_callback_flags::CallbackFlags;
+
+use std::path::Path;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::{ Arc, Condvar, Mutex };
+use std::thread::{ self, JoinHandle };
+use std::time::{ Duration, Instant };
+
+/// Value returned by a backend callback
+///
+/// `Continue`: keep on calling the callback
+/// `Complete`: the stream is finished (the backend becomes inactive)
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CallbackFlow {
+    Continue,
+    Complete
+}
+
+pub type OutputCallback = Box<dyn FnMut(&mut [f32]) -> CallbackFlow + Send>;
+pub type DuplexCallback = Box<dyn FnMut(&[f32], &mut [f32]) -> CallbackFlow + Send>;
+pub type InputCallback = Box<dyn FnMut(&[f32]) -> CallbackFlow + Send>;
+pub type BackendFactory = Arc<dyn Fn() -> Box<dyn AudioBackend> + Send + Sync>;
+
+#[derive(Debug)]
+pub enum BackendError {
+    DeviceError(String),
+    StreamOpenError(String),
+    StreamStartError(String),
+    StreamCloseError(String),
+    StreamNotOpen,
+    RenderDurationRequired
+}
+
+/// Audio Backend
+///
+/// A backend owns the device (or the device emulation): it opens the stream, drives the callback and tears
+/// everything down on `close()`. Each process creates its own backend inside its stream thread
+///
+pub trait AudioBackend {
+    fn open_output(&mut self, params: &StreamParameters, callback: OutputCallback) -> Result<(), BackendError>;
+    fn open_duplex(&mut self, params: &StreamParameters, callback: DuplexCallback) -> Result<(), BackendError>;
+    /// Open an input-only stream (capture). Backends without inputs refuse it
+    fn open_input(&mut self, _params: &StreamParameters, _callback: InputCallback) -> Result<(), BackendError> {
+        Err(BackendError::StreamOpenError(String::from("input streams are not supported by this backend")))
+    }
+    fn start(&mut self) -> Result<(), BackendError>;
+    fn is_active(&self) -> bool;
+    fn close(&mut self) -> Result<(), BackendError>;
+    fn get_input_latency(&self) -> f64 { 0.0 }
+    fn get_output_latency(&self) -> f64 { 0.0 }
+    /// Metrics where the backend reports device underruns and overruns (call it before opening the stream)
+    fn set_metrics(&mut self, _metrics: Arc<ProcessMetrics>) { }
+}
+
+/// Backend Type
+///
+/// `PortAudio`: real time streams on PortAudio devices
+/// `Null`: dummy device, the callback is clocked by a timer at the stream sample rate. No audio is output
+/// `FileSink(params)`: offline render, the callback is called as fast as possible and the output is written to
+/// memory or to a wav file (see `OfflineParams`)
+/// `Custom(factory)`: user defined backend
+///
+#[derive(Clone, Default)]
+pub enum BackendType {
+    #[default]
+    PortAudio,
+    Null,
+    FileSink(OfflineParams),
+    Custom(BackendFactory)
+}
+
+impl std::fmt::Debug for BackendType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::PortAudio => write!(f, "PortAudio"),
+            Self::Null => write!(f, "Null"),
+            Self::FileSink(params) => write!(f, "FileSink({:?})", params),
+            Self::Custom(_) => write!(f, "Custom")
+        }
+    }
+}
+
+impl BackendType {
+    /// Create a new backend
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `render`: where a `FileSink` backend writes the rendered audio
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Box<dyn AudioBackend>`
+    ///
+    pub fn create_backend(&self, render: &Arc<OfflineRender>) -> Box<dyn AudioBackend> {
+        match self {
+            Self::PortAudio => Box::new(PortAudioBackend::new()),
+            Self::Null => Box::new(NullBackend::new()),
+            Self::FileSink(params) => Box::new(FileSinkBackend::new(params.clone(), Arc::clone(render))),
+            Self::Custom(factory) => factory()
+        }
+    }
+
+    pub fn is_offline(&self) -> bool {
+        matches!(self, Self::FileSink(_))
+    }
+
+    pub fn get_offline_params(&self) -> Option<&OfflineParams> {
+        match self {
+            Self::FileSink(params) => Some(params),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+enum RenderState {
+    #[default]
+    Idle,
+    Running,
+    Done(Result<Vec<f32>, String>)
+}
+
+/// # Offline Render
+///
+/// Audio rendered by a process running on a `FileSink` backend. Each start (or restart) of the process begins a new
+/// render and clears the previous one
+///
+#[derive(Debug, Default)]
+pub struct OfflineRender {
+    state: Mutex<RenderState>,
+    done: Condvar
+}
+
+impl OfflineRender {
+    /// Begin a new render (new generation of the process)
+    ///
+    pub(crate) fn reset(&self) {
+        *self.state.lock().unwrap() = RenderState::Running;
+    }
+
+    pub(crate) fn finish(&self, audio_data: Vec<f32>) {
+        let mut state = self.state.lock().unwrap();
+        *state = RenderState::Done(Ok(audio_data));
+        self.done.notify_all();
+    }
+
+    /// Stop the render with an error (stream not opened or not started, file not written, render thread panicked).
+    /// A finished render is kept
+    ///
+    pub(crate) fn fail(&self, error: String) {
+        let mut state = self.state.lock().unwrap();
+        if !matches!(*state, RenderState::Done(_)) {
+            *state = RenderState::Done(Err(error));
+            self.done.notify_all();
+        }
+    }
+
+    /// Block until the render is done
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<Vec<f32>, QubxError>`: rendered audio (interleaved). `QubxError::RenderError` if the process has not
+    /// been started or the render failed
+    ///
+    pub fn wait(&self) -> Result<Vec<f32>, QubxError> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            match &*state {
+                RenderState::Idle => return Err(QubxError::RenderError(String::from("render not started"))),
+                RenderState::Running => state = self.done.wait(state).unwrap(),
+                RenderState::Done(Ok(audio_data)) => return Ok(audio_data.clone()),
+                RenderState::Done(Err(e)) => return Err(QubxError::RenderError(e.clone()))
+            }
+        }
+    }
+}
+
+/// Fail the render if the render thread exits before finishing it (e.g. the callback panicked)
+///
+struct RenderGuard(Arc<OfflineRender>);
+
+impl Drop for RenderGuard {
+    fn drop(&mut self) {
+        self.0.fail(String::from("render thread stopped before the end of the render"));
+    }
+}
+
+enum BackendCallback {
+    Output(OutputCallback),
+    Duplex(DuplexCallback),
+    Input(InputCallback)
+}
+
+impl BackendCallback {
+    fn call(&mut self, inblock: &[f32], outblock: &mut [f32]) -> CallbackFlow {
+        match self {
+            Self::Output(callback) => callback(outblock),
+            Self::Duplex(callback) => callback(inblock, outblock),
+            Self::Input(callback) => callback(inblock)
+        }
+    }
+}
+
+fn block_sizes(params: &StreamParameters, callback: &BackendCallback) -> (usize, usize) {
+    let inlen = (params.chunk * params.inchannels) as usize;
+    let outlen = (params.chunk * params.outchannels) as usize;
+    match callback {
+        BackendCallback::Output(_) => (0, outlen),
+        BackendCallback::Duplex(_) => (inlen, outlen),
+        BackendCallback::Input(_) => (inlen, 0)
+    }
+}
+
+// --- PORTAUDIO ---
+
+enum PaStream {
+    Output(pa::Stream<pa::NonBlocking, pa::Output<f32>>),
+    Duplex(pa::Stream<pa::NonBlocking, pa::Duplex<f32, f32>>),
+    Input(pa::Stream<pa::NonBlocking, pa::Input<f32>>)
+}
+
+/// # PortAudio Backend
+///
+///
+pub struct PortAudioBackend {
+    port_audio: Option<pa::PortAudio>,
+    stream: Option<PaStream>,
+    active: Arc<AtomicBool>,
+    input_latency: f64,
+    output_latency: f64,
+    metrics: Arc<ProcessMetrics>
+}
+
+impl Default for PortAudioBackend {
+    fn default() -> Self {
+        Self {
+            port_audio: None,
+            stream: None,
+            active: Arc::new(AtomicBool::new(false)),
+            input_latency: 0.0,
+            output_latency: 0.0,
+            metrics: Arc::new(ProcessMetrics::new())
+        }
+    }
+}
+
+/// Count the xruns reported by PortAudio
+///
+fn report_xruns(flags: pa::StreamCallbackFlags, metrics: &ProcessMetrics) {
+    if flags.intersects(CallbackFlags::OUTPUT_UNDERFLOW | CallbackFlags::INPUT_UNDERFLOW) {
+        metrics.add_underrun();
+    }
+    if flags.intersects(CallbackFlags::INPUT_OVERFLOW | CallbackFlags::OUTPUT_OVERFLOW) {
+        metrics.add_overrun();
+    }
+}
+
+impl PortAudioBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn init(&mut self) -> Result<(), BackendError> {
+        if self.port_audio.is_none() {
+            let p = pa::PortAudio::new().map_err(|e| BackendError::DeviceError(e.to_string()))?;
+            self.port_audio = Some(p);
+        }
+        Ok(())
+    }
+}
+
+impl AudioBackend for PortAudioBackend {
+    fn open_output(&mut self, params: &StreamParameters, mut callback: OutputCallback) -> Result<(), BackendError> {
+        self.init()?;
+        let p = self.port_audio.as_ref().unwrap();
+
+        let device = resolve_device(p, params.outdevice_name.as_deref(), params.outdevice, false, params.outchannels)?;
+
+        let device_info = p.device_info(device).map_err(|e| BackendError::DeviceError(e.to_string()))?;
+        self.output_latency = device_info.default_low_output_latency;
+
+        let output_params =
+            pa::StreamParameters::<f32>::new(device, params.outchannels as i32, true, self.output_latency);
+        let output_settings = pa::OutputStreamSettings::new(output_params, params.sr as f64, params.chunk);
+
+        let active = Arc::clone(&self.active);
+        let metrics = Arc::clone(&self.metrics);
+        let pa_callback = move |pa::OutputStreamCallbackArgs { buffer, flags, .. }| {
+            report_xruns(flags, &metrics);
+            match callback(buffer) {
+                CallbackFlow::Continue => pa::Continue,
+                CallbackFlow::Complete => {
+                    active.store(false, Ordering::Release);
+                    pa::Complete
+                }
+            }
+        };
+
+        let stream = p
+            .open_non_blocking_stream(output_settings, pa_callback)
+            .map_err(|e| BackendError::StreamOpenError(e.to_string()))?;
+
+        self.stream = Some(PaStream::Output(stream));
+        Ok(())
+    }
+
+    fn open_duplex(&mut self, params: &StreamParameters, mut callback: DuplexCallback) -> Result<(), BackendError> {
+        self.init()?;
+        let p = self.port_audio.as_ref().unwrap();
+
+        let indevice = resolve_device(p, params.indevice_name.as_deref(), params.indevice, true, params.inchannels)?;
+        let outdevice = resolve_device(p, params.outdevice_name.as_deref(), params.outdevice, false, params.outchannels)?;
+
+        let devin_info = p.device_info(indevice).map_err(|e| BackendError::DeviceError(e.to_string()))?;
+        self.input_latency = devin_info.default_low_input_latency;
+
+        let inparams =
+            pa::StreamParameters::<f32>::new(indevice, params.inchannels as i32, true, self.input_latency);
+
+        let devout_info = p.device_info(outdevice).map_err(|e| BackendError::DeviceError(e.to_string()))?;
+        self.output_latency = devout_info.default_low_output_latency;
+
+        let outparams =
+            pa::StreamParameters::<f32>::new(outdevice, params.outchannels as i32, true, self.output_latency);
+
+        let stream_settings = pa::DuplexStreamSettings::new(inparams, outparams, params.sr as f64, params.chunk);
+
+        let active = Arc::clone(&self.active);
+        let metrics = Arc::clone(&self.metrics);
+        let pa_callback = move |pa::DuplexStreamCallbackArgs { in_buffer, out_buffer, flags, .. }| {
+            report_xruns(flags, &metrics);
+            match callback(in_buffer, out_buffer) {
+                CallbackFlow::Continue => pa::Continue,
+                CallbackFlow::Complete => {
+                    active.store(false, Ordering::Release);
+                    pa::Complete
+                }
+            }
+        };
+
+        let stream = p
+            .open_non_blocking_stream(stream_settings, pa_callback)
+            .map_err(|e| BackendError::StreamOpenError(e.to_string()))?;
+
+        self.stream = Some(PaStream::Duplex(stream));
+        Ok(())
+    }
+
+    fn open_input(&mut self, params: &StreamParameters, mut callback: InputCallback) -> Result<(), BackendError> {
+        self.init()?;
+        let p = self.port_audio.as_ref().unwrap();
+
+        let device = resolve_device(p, params.indevice_name.as_deref(), params.indevice, true, params.inchannels)?;
+
+        let device_info = p.device_info(device).map_err(|e| BackendError::DeviceError(e.to_string()))?;
+        self.input_latency = device_info.default_low_input_latency;
+
+        let input_params =
+            pa::StreamParameters::<f32>::new(device, params.inchannels as i32, true, self.input_latency);
+        let input_settings = pa::InputStreamSettings::new(input_params, params.sr as f64, params.chunk);
+
+        let active = Arc::clone(&self.active);
+        let metrics = Arc::clone(&self.metrics);
+        let pa_callback = move |pa::InputStreamCallbackArgs { buffer, flags, .. }| {
+            report_xruns(flags, &metrics);
+            match callback(buffer) {
+                CallbackFlow::Continue => pa::Continue,
+                CallbackFlow::Complete => {
+                    active.store(false, Ordering::Release);
+                    pa::Complete
+                }
+            }
+        };
+
+        let stream = p
+            .open_non_blocking_stream(input_settings, pa_callback)
+            .map_err(|e| BackendError::StreamOpenError(e.to_string()))?;
+
+        self.stream = Some(PaStream::Input(stream));
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<(), BackendError> {
+        self.active.store(true, Ordering::Release);
+        let started = match self.stream {
+            Some(PaStream::Output(ref mut stream)) => stream.start(),
+            Some(PaStream::Duplex(ref mut stream)) => stream.start(),
+            Some(PaStream::Input(ref mut stream)) => stream.start(),
+            None => return Err(BackendError::StreamNotOpen)
+        };
+        started.map_err(|e| {
+            self.active.store(false, Ordering::Release);
+            BackendError::StreamStartError(e.to_string())
+        })
+    }
+
+    fn is_active(&self) -> bool {
+        self.active.load(Ordering::Acquire)
+    }
+
+    fn close(&mut self) -> Result<(), BackendError> {
+        self.active.store(false, Ordering::Release);
+        let closed = match self.stream.take() {
+            Some(PaStream::Output(mut stream)) => stream.stop().and_then(|_| stream.close()),
+            Some(PaStream::Duplex(mut stream)) => stream.stop().and_then(|_| stream.close()),
+            Some(PaStream::Input(mut stream)) => stream.stop().and_then(|_| stream.close()),
+            None => Ok(())
+        };
+        closed.map_err(|e| BackendError::StreamCloseError(e.to_string()))?;
+
+        if let Some(p) = self.port_audio.take() {
+            p.terminate().map_err(|e| BackendError::StreamCloseError(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    fn get_input_latency(&self) -> f64 {
+        self.input_latency
+    }
+
+    fn get_output_latency(&self) -> f64 {
+        self.output_latency
+    }
+
+    fn set_metrics(&mut self, metrics: Arc<ProcessMetrics>) {
+        self.metrics = metrics;
+    }
+}
+
+// --- NULL DEVICE ---
+
+/// # Null Backend
+///
+/// Dummy device clocked by a timer. The callback is called every `chunk / sr` sec., the input is silent
+/// and the output is discarded
+///
+pub struct NullBackend {
+    params: Option<StreamParameters>,
+    callback: Option<BackendCallback>,
+    active: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>
+}
+
+impl Default for NullBackend {
+    fn default() -> Self {
+        Self {
+            params: None,
+            callback: None,
+            active: Arc::new(AtomicBool::new(false)),
+            handle: None
+        }
+    }
+}
+
+impl NullBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl AudioBackend for NullBackend {
+    fn open_output(&mut self, params: &StreamParameters, callback: OutputCallback) -> Result<(), BackendError> {
+        self.params = Some(params.clone());
+        self.callback = Some(BackendCallback::Output(callback));
+        Ok(())
+    }
+
+    fn open_duplex(&mut self, params: &StreamParameters, callback: DuplexCallback) -> Result<(), BackendError> {
+        self.params = Some(params.clone());
+        self.callback = Some(BackendCallback::Duplex(callback));
+        Ok(())
+    }
+
+    fn open_input(&mut self, params: &StreamParameters, callback: InputCallback) -> Result<(), BackendError> {
+        self.params = Some(params.clone());
+        self.callback = Some(BackendCallback::Input(callback));
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<(), BackendError> {
+        let (params, mut callback) = match (self.params.take(), self.callback.take()) {
+            (Some(params), Some(callback)) => (params, callback),
+            _ => return Err(BackendError::StreamNotOpen)
+        };
+
+        let active = Arc::clone(&self.active);
+        active.store(true, Ordering::Release);
+
+        self.handle = Some(thread::spawn(move || {
+            let (inlen, outlen) = block_sizes(&params, &callback);
+            let inblock = vec![0.0; inlen];
+            let mut outblock = vec![0.0; outlen];
+            let period = Duration::from_secs_f64(params.chunk as f64 / params.sr as f64);
+            let mut next_tick = Instant::now();
+
+            while active.load(Ordering::Acquire) {
+                outblock.iter_mut().for_each(|sample| *sample = 0.0);
+                if callback.call(&inblock, &mut outblock) == CallbackFlow::Complete {
+                    active.store(false, Ordering::Release);
+                    break;
+                }
+
+                next_tick += period;
+                let now = Instant::now();
+                if next_tick > now {
+                    thread::sleep(next_tick - now);
+                }
+            }
+        }));
+        Ok(())
+    }
+
+    fn is_active(&self) -> bool {
+        self.active.load(Ordering::Acquire)
+    }
+
+    fn close(&mut self) -> Result<(), BackendError> {
+        self.active.store(false, Ordering::Release);
+        if let Some(handle) = self.handle.take() {
+            handle.join().map_err(|_| BackendError::StreamCloseError(String::from("null device thread panicked")))?;
+        }
+        Ok(())
+    }
+}
+
+// --- FILE SINK ---
+
+/// Write the rendered audio to `name`.wav
+///
+/// # Return
+/// --------
+///
+/// `Result<(), String>`: error message if the directory does not exist or the file can not be written
+///
+fn write_render(name: &str, audio_data: &[f32], channels: usize, sr: f32) -> Result<(), String> {
+    let directory = Path::new(name).parent().filter(|dir| !dir.as_os_str().is_empty());
+    if let Some(directory) = directory {
+        if !directory.is_dir() {
+            return Err(format!("offline render not written to file {}: directory {} not found", name, directory.display()))
+        }
+    }
+    write_to_file(name, audio_data, channels, sr).map_err(|e| format!("offline render not written to file {}: {:?}", name, e))
+}
+
+/// # File Sink Backend
+///
+/// Offline render. The callback is called as fast as possible with a silent input, until it returns
+/// `CallbackFlow::Complete` or the render duration is elapsed. The output is collected into an `OfflineRender`
+/// and written to file if required (see `RenderTarget`). Input streams render no output
+///
+pub struct FileSinkBackend {
+    offline: OfflineParams,
+    render: Arc<OfflineRender>,
+    params: Option<StreamParameters>,
+    callback: Option<BackendCallback>,
+    active: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>
+}
+
+impl FileSinkBackend {
+    pub fn new(offline: OfflineParams, render: Arc<OfflineRender>) -> Self {
+        Self {
+            offline,
+            render,
+            params: None,
+            callback: None,
+            active: Arc::new(AtomicBool::new(false)),
+            handle: None
+        }
+    }
+}
+
+impl AudioBackend for FileSinkBackend {
+    fn open_output(&mut self, params: &StreamParameters, callback: OutputCallback) -> Result<(), BackendError> {
+        self.params = Some(params.clone());
+        self.callback = Some(BackendCallback::Output(callback));
+        Ok(())
+    }
+
+    fn open_duplex(&mut self, params: &StreamParameters, callback: DuplexCallback) -> Result<(), BackendError> {
+        if self.offline.duration.is_none() { return Err(BackendError::RenderDurationRequired) }
+        self.params = Some(params.clone());
+        self.callback = Some(BackendCallback::Duplex(callback));
+        Ok(())
+    }
+
+    fn open_input(&mut self, params: &StreamParameters, callback: InputCallback) -> Result<(), BackendError> {
+        if self.offline.duration.is_none() { return Err(BackendError::RenderDurationRequired) }
+        self.params = Some(params.clone());
+        self.callback = Some(BackendCallback::Input(callback));
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<(), BackendError> {
+        let (params, mut callback) = match (self.params.take(), self.callback.take()) {
+            (Some(params), Some(callback)) => (params, callback),
+            _ => return Err(BackendError::StreamNotOpen)
+        };
+
+        let active = Arc::clone(&self.active);
+        let render = Arc::clone(&self.render);
+        let offline = self.offline.clone();
+        active.store(true, Ordering::Release);
+
+        self.handle = Some(thread::spawn(move || {
+            let guard = RenderGuard(render);
+            let (inlen, outlen) = block_sizes(&params, &callback);
+            let chunk = params.chunk as usize;
+            let channels = outlen / chunk.max(1); // input streams render no output
+            let sr = params.sr as f32;
+            let max_frames = offline.duration.map(|d| (d * sr).round() as usize);
+
+            let inblock = vec![0.0; inlen];
+            let mut outblock = vec![0.0; outlen];
+            let mut audio_data: Vec<f32> = Vec::new();
+            let mut n_frames: usize = 0;
+
+            while active.load(Ordering::Acquire) {
+                if let Some(max) = max_frames {
+                    if n_frames >= max { break }
+                }
+
+                outblock.iter_mut().for_each(|sample| *sample = 0.0);
+                if callback.call(&inblock, &mut outblock) == CallbackFlow::Complete { break }
+
+                let frames_to_write = match max_frames {
+                    Some(max) => chunk.min(max - n_frames),
+                    None => chunk
+                };
+                audio_data.extend_from_slice(&outblock[..frames_to_write * channels]);
+                n_frames += frames_to_write;
+            }
+
+            active.store(false, Ordering::Release);
+
+            match offline.target {
+                RenderTarget::File(ref name) if channels > 0 => {
+                    if let Err(e) = write_render(name, &audio_data, channels, sr) {
+                        guard.0.fail(e);
+                        return
+                    }
+                },
+                _ => { }
+            }
+            guard.0.finish(audio_data);
+        }));
+        Ok(())
+    }
+
+    fn is_active(&self) -> bool {
+        self.active.load(Ordering::Acquire)
+    }
+
+    fn close(&mut self) -> Result<(), BackendError> {
+        self.active.store(false, Ordering::Release);
+        if let Some(handle) = self.handle.take() {
+            handle.join().map_err(|_| BackendError::StreamCloseError(String::from("file sink thread panicked")))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qubx::Qubx;
+    use crate::qubx_common::ProcessArg;
+    use crate::qubx_types::MasterPatchType;
+
+    fn stream_params() -> StreamParameters {
+        StreamParameters { chunk: 16, sr: 16000, outchannels: 2, inchannels: 1, ..Default::default() }
+    }
+
+    fn wait_until<F: Fn() -> bool>(f: F) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !f() {
+            if Instant::now() > deadline { return false }
+            thread::sleep(Duration::from_millis(1));
+        }
+        true
+    }
+
+    #[test]
+    fn null_backend_output() {
+        let blocks = Arc::new(Mutex::new(Vec::new()));
+        let b = Arc::clone(&blocks);
+        let callback: OutputCallback = Box::new(move |outblock| {
+            let mut blocks = b.lock().unwrap();
+            blocks.push(outblock.len());
+            if blocks.len() == 4 { CallbackFlow::Complete } else { CallbackFlow::Continue }
+        });
+
+        let mut backend = NullBackend::new();
+        backend.open_output(&stream_params(), callback).unwrap();
+        backend.start().unwrap();
+        assert!(wait_until(|| !backend.is_active()));
+        backend.close().unwrap();
+        assert_eq!(*blocks.lock().unwrap(), vec![32; 4]);
+    }
+
+    #[test]
+    fn null_backend_duplex_silent_input() {
+        let inputs = Arc::new(Mutex::new(Vec::new()));
+        let i = Arc::clone(&inputs);
+        let callback: DuplexCallback = Box::new(move |inblock, outblock| {
+            assert_eq!(outblock.len(), 32);
+            i.lock().unwrap().extend_from_slice(inblock);
+            CallbackFlow::Complete
+        });
+
+        let mut backend = NullBackend::new();
+        backend.open_duplex(&stream_params(), callback).unwrap();
+        backend.start().unwrap();
+        assert!(wait_until(|| !backend.is_active()));
+        backend.close().unwrap();
+        assert_eq!(*inputs.lock().unwrap(), vec![0.0; 16]);
+    }
+
+    #[test]
+    fn start_without_open() {
+        assert!(matches!(NullBackend::new().start(), Err(BackendError::StreamNotOpen)));
+        let mut backend = FileSinkBackend::new(OfflineParams::default(), Arc::new(OfflineRender::default()));
+        assert!(matches!(backend.start(), Err(BackendError::StreamNotOpen)));
+    }
+
+    /// Custom backend calling the callback a fixed number of times and keeping the output
+    ///
+    struct BlockBackend {
+        blocks: Arc<Mutex<Vec<Vec<f32>>>>,
+        n_blocks: usize,
+        outlen: usize,
+        callback: Option<OutputCallback>,
+        active: Arc<AtomicBool>,
+        handle: Option<JoinHandle<()>>
+    }
+
+    impl AudioBackend for BlockBackend {
+        fn open_output(&mut self, params: &StreamParameters, callback: OutputCallback) -> Result<(), BackendError> {
+            self.outlen = (params.chunk * params.outchannels) as usize;
+            self.callback = Some(callback);
+            Ok(())
+        }
+
+        fn open_duplex(&mut self, _params: &StreamParameters, _callback: DuplexCallback) -> Result<(), BackendError> {
+            Err(BackendError::StreamOpenError(String::from("output only")))
+        }
+
+        fn start(&mut self) -> Result<(), BackendError> {
+            let mut callback = self.callback.take().ok_or(BackendError::StreamNotOpen)?;
+            let (blocks, n_blocks, outlen) = (Arc::clone(&self.blocks), self.n_blocks, self.outlen);
+            let active = Arc::clone(&self.active);
+            active.store(true, Ordering::Release);
+            self.handle = Some(thread::spawn(move || {
+                for _ in 0..n_blocks {
+                    let mut outblock = vec![0.0; outlen];
+                    callback(&mut outblock);
+                    blocks.lock().unwrap().push(outblock);
+                }
+                active.store(false, Ordering::Release);
+            }));
+            Ok(())
+        }
+
+        fn is_active(&self) -> bool {
+            self.active.load(Ordering::Acquire)
+        }
+
+        fn close(&mut self) -> Result<(), BackendError> {
+            if let Some(handle) = self.handle.take() {
+                handle.join().map_err(|_| BackendError::StreamCloseError(String::from("block backend panicked")))?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn custom_backend_drives_master() {
+        let blocks = Arc::new(Mutex::new(Vec::new()));
+        let b = Arc::clone(&blocks);
+        let factory: BackendFactory = Arc::new(move || Box::new(BlockBackend {
+            blocks: Arc::clone(&b),
+            n_blocks: 3,
+            outlen: 0,
+            callback: None,
+            active: Arc::new(AtomicBool::new(false)),
+            handle: None
+        }));
+
+        let mut qubx = Qubx::new(false, BackendType::Custom(factory));
+        let master = qubx.create_master_streamout(String::from("master"), stream_params()).unwrap();
+        let patch: MasterPatchType = Box::new(|frame| frame.fill(0.5));
+        master.start(ProcessArg::PatchSpace(patch)).unwrap();
+
+        assert!(wait_until(|| blocks.lock().unwrap().len() == 3));
+        qubx.close_qubx();
+        let blocks = blocks.lock().unwrap();
+        assert!(blocks.iter().all(|block| block.len() == 32 && block.iter().all(|sample| *sample == 0.5)));
+    }
+}
@@ -3,27 +3,109 @@
 use std::thread::JoinHandle;
 use std::default::Default;
 
-use crate::{ qinterp::Interp, qsignals::{ SignalMode, SignalObject }, qtable::TableParams };
+use crate::{ qbuffers::AudioObject, qinterp::Interp, qsignals::{ SignalMode, SignalObject }, qtable::TableParams, qwindow::QWindow };
+use crate::qubx_backend::BackendError;
+use crate::qrouting::RoutingError;
+use crate::qgraph::GraphError;
+use crate::qsession::SessionError;
+use crate::qubx_types::ProcessorType;
+use serde::{ Deserialize, Serialize };
 
 
-pub enum QubxExceptions {
-    ParamsError,
-    FuncArgsError
+/// # Qubx Error
+///
+/// Errors returned by the engine API (`Qubx::create_*()` and `start()` methods)
+///
+/// `DeviceError`: audio device not available (or PortAudio not initialized)
+/// `MasterNotFound`: no master streamout with this name
+/// `MasterAlreadyExists`: a master streamout with this name already exists
+/// `MasterAlreadyStarted`: the master streamout has already been started (use `ProcessHandle::restart()`)
+/// `StreamOpenError`: the backend failed to open the stream
+/// `StreamStartError`: the backend failed to start the stream
+/// `StreamCloseError`: the backend failed to close the stream
+/// `ChannelMismatch`: number of channels of a frame or of a stream does not match
+/// `ParamsError`: invalid stream parameters
+/// `QueueFull`: the queue of a streaming dsp process is full and its policy is `QueueFullPolicy::Error`
+/// `RecordError`: the recording could not be written (see `RecordTarget`)
+/// `RenderError`: the offline render has not been started or has failed (see `OfflineRender`)
+/// `Routing`: bus routing error (see `RoutingError`)
+/// `Graph`: processing graph error (see `GraphError`)
+/// `Session`: session file error (see `SessionError`)
+///
+#[derive(Debug)]
+pub enum QubxError {
+    DeviceError(String),
+    MasterNotFound(String),
+    MasterAlreadyExists(String),
+    MasterAlreadyStarted(String),
+    StreamOpenError(String),
+    StreamStartError(String),
+    StreamCloseError(String),
+    ChannelMismatch { expected: usize, found: usize },
+    ParamsError(String),
+    QueueFull(String),
+    RecordError(String),
+    RenderError(String),
+    Routing(RoutingError),
+    Graph(GraphError),
+    Session(SessionError)
 }
 
-impl QubxExceptions {
-    pub fn get_error(error: QubxExceptions) {
+impl std::fmt::Display for QubxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DeviceError(e) => write!(f, "device error: {}", e),
+            Self::MasterNotFound(name) => write!(f, "master streamout {} not found", name),
+            Self::MasterAlreadyExists(name) => write!(f, "master streamout {} already exists", name),
+            Self::MasterAlreadyStarted(name) => write!(f, "master streamout {} already started", name),
+            Self::StreamOpenError(e) => write!(f, "failed to open stream: {}", e),
+            Self::StreamStartError(e) => write!(f, "failed to start stream: {}", e),
+            Self::StreamCloseError(e) => write!(f, "failed to close stream: {}", e),
+            Self::ChannelMismatch { expected, found } => write!(f, "channel mismatch: expected {}, found {}", expected, found),
+            Self::ParamsError(e) => write!(f, "invalid parameters: {}", e),
+            Self::QueueFull(name) => write!(f, "queue of {} is full", name),
+            Self::RecordError(e) => write!(f, "recording error: {}", e),
+            Self::RenderError(e) => write!(f, "offline render error: {}", e),
+            Self::Routing(e) => write!(f, "routing error: {}", e),
+            Self::Graph(e) => write!(f, "graph error: {}", e),
+            Self::Session(e) => write!(f, "session error: {}", e)
+        }
+    }
+}
+
+impl std::error::Error for QubxError { }
+
+impl From<BackendError> for QubxError {
+    fn from(error: BackendError) -> Self {
         match error {
-            Self::ParamsError => {
-                println!("[ERROR] Streaming parameters not found!")
-            },
-            Self::FuncArgsError => {
-                println!("[ERROR] Missing function argument!")
-            }
+            BackendError::DeviceError(e) => Self::DeviceError(e),
+            BackendError::StreamOpenError(e) => Self::StreamOpenError(e),
+            BackendError::StreamStartError(e) => Self::StreamStartError(e),
+            BackendError::StreamCloseError(e) => Self::StreamCloseError(e),
+            BackendError::StreamNotOpen => Self::StreamOpenError(String::from("stream not open")),
+            BackendError::RenderDurationRequired => Self::ParamsError(String::from("render duration required"))
         }
     }
 }
 
+impl From<RoutingError> for QubxError {
+    fn from(error: RoutingError) -> Self {
+        Self::Routing(error)
+    }
+}
+
+impl From<GraphError> for QubxError {
+    fn from(error: GraphError) -> Self {
+        Self::Graph(error)
+    }
+}
+
+impl From<SessionError> for QubxError {
+    fn from(error: SessionError) -> Self {
+        Self::Session(error)
+    }
+}
+
 /// Stream Parameters struct
 ///
 /// # Args
@@ -35,16 +117,30 @@ impl QubxExceptions {
 /// `outdevice`: index of output device
 /// `inchannels`: number of channels (input device)
 /// `indevice`: index of input device
+/// `outdevice_name`: name (or part of the name) of output device. It takes precedence over `outdevice`
+/// `indevice_name`: name (or part of the name) of input device. It takes precedence over `indevice`
+///
+/// Names are matched case insensitive: exact matches come first, then substrings. A substring matching more than one
+/// device is refused (see `Qubx::get_devices()`)
+///
+/// Missing fields take the default values in a session file (see `SessionConfig`)
 ///
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct StreamParameters {
     pub chunk: u32,
     pub sr: i32,
     pub outchannels: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub outdevice: Option<u32>,
     pub inchannels: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub indevice: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outdevice_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub indevice_name: Option<String>,
 }
 
 impl Default for StreamParameters {
@@ -56,7 +152,9 @@ impl Default for StreamParameters {
             outchannels: 1,
             outdevice: None,
             inchannels: 1,
-            indevice: None
+            indevice: None,
+            outdevice_name: None,
+            indevice_name: None
 
         }
     }
@@ -71,15 +169,116 @@ impl Clone for StreamParameters {
             outchannels: self.outchannels,
             outdevice: self.outdevice,
             inchannels: self.inchannels,
-            indevice: self.indevice
+            indevice: self.indevice,
+            outdevice_name: self.outdevice_name.clone(),
+            indevice_name: self.indevice_name.clone()
+
+        }
+    }
+}
+
+impl StreamParameters {
+    /// Check the stream parameters
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `duplex`: check the input channels too
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), QubxError>`
+    ///
+    pub(crate) fn validate(&self, duplex: bool) -> Result<(), QubxError> {
+        if self.chunk == 0 {
+            return Err(QubxError::ParamsError(String::from("chunk must be > 0")))
+        }
+        if self.sr <= 0 {
+            return Err(QubxError::ParamsError(String::from("sample rate must be > 0")))
+        }
+        if self.outchannels == 0 {
+            return Err(QubxError::ParamsError(String::from("number of output channels must be > 0")))
+        }
+        if duplex && self.inchannels == 0 {
+            return Err(QubxError::ParamsError(String::from("number of input channels must be > 0")))
+        }
+        Ok(())
+    }
+
+    /// Check the parameters of an input-only stream (output channels are ignored)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), QubxError>`
+    ///
+    pub(crate) fn validate_input(&self) -> Result<(), QubxError> {
+        if self.chunk == 0 {
+            return Err(QubxError::ParamsError(String::from("chunk must be > 0")))
+        }
+        if self.sr <= 0 {
+            return Err(QubxError::ParamsError(String::from("sample rate must be > 0")))
+        }
+        if self.inchannels == 0 {
+            return Err(QubxError::ParamsError(String::from("number of input channels must be > 0")))
+        }
+        Ok(())
+    }
+}
+
+/// Offline render target
+///
+/// `Memory`: the rendered mix is kept in memory only
+/// `File(name)`: the rendered mix is kept in memory and written to `name`.wav at the end of the render
+///
+#[derive(Debug, Clone)]
+pub enum RenderTarget {
+    Memory,
+    File(String)
+}
+
+/// Recording target of an input stream
+///
+/// `Memory`: the input is appended to a growing `AudioObject` (see `QubxInputProcess::get_recording()`)
+/// `File(name)`: the input is streamed to `name`.wav (32 bit float) while recording, nothing is kept in memory
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecordTarget {
+    Memory,
+    File(String)
+}
+
+/// Offline Parameters struct
+///
+/// # Args
+/// ------
+///
+/// `target`: where to write the rendered mix (see `RenderTarget`)
+/// `duration`: render duration in sec. If `None`, the render stops as soon as all queues are drained
+///
+
+#[derive(Debug, Clone)]
+pub struct OfflineParams {
+    pub target: RenderTarget,
+    pub duration: Option<f32>
+}
+
+impl Default for OfflineParams {
+    fn default() -> Self {
+        Self {
+
+            target: RenderTarget::Memory,
+            duration: None
 
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ProcessState {
     On,
+    Paused,
     Off
 }
 
@@ -104,7 +303,314 @@ where
 {
     Source(Vec<f32>),
     PatchSpace(F1),
-    HybridSpace(Vec<f32>, F2)
+    HybridSpace(Vec<f32>, F2),
+    Processor(Vec<f32>, ProcessorType)
+}
+
+/// Dsp Options struct
+///
+/// Options of a dsp process, passed next to the `DspProcessArg` (see `QubxDspProcess::start_with()`)
+///
+/// # Args
+/// ------
+///
+/// `start`: when the output starts on the master timeline (see `StartTime`)
+/// `frame`: frames passed to the `HybridSpace` closure, block size of a `Processor` (see `FrameParams`)
+/// `format`: channel numbers and sample rate of the audio (`None`: format of the master streamout, see `SourceFormat`)
+/// `fade`: fade-in and fade-out of the output in the master mix (see `FadeParams`)
+///
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DspOptions {
+    pub start: StartTime,
+    pub frame: FrameParams,
+    pub format: Option<SourceFormat>,
+    pub fade: FadeParams
+}
+
+impl DspOptions {
+    /// Schedule the dsp output on the master timeline
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `start`: when the output starts (see `StartTime`)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `DspOptions`
+    ///
+    pub fn at(self, start: StartTime) -> Self {
+        Self { start, ..self }
+    }
+
+    /// Set the frames passed to the `HybridSpace` closure (see `FrameParams`). Other args are not processed per frame
+    /// and ignore it
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `frame`: frame size, hop size and analysis window
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `DspOptions`
+    ///
+    pub fn with_frames(self, frame: FrameParams) -> Self {
+        Self { frame, ..self }
+    }
+
+    /// Tag the audio with its channel numbers and sample rate (see `SourceFormat`). The audio (`Source`, output of
+    /// `PatchSpace`, input of `HybridSpace`) is up/downmixed and resampled to the master streamout before queuing
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `format`: channel numbers and sample rate of the audio
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `DspOptions`
+    ///
+    pub fn with_format(self, format: SourceFormat) -> Self {
+        Self { format: Some(format), ..self }
+    }
+
+    /// Fade the output in and out in the master mix (see `FadeParams`)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `fade`: fade-in and fade-out time
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `DspOptions`
+    ///
+    pub fn with_fades(self, fade: FadeParams) -> Self {
+        Self { fade, ..self }
+    }
+}
+
+impl From<&SignalObject> for DspOptions {
+    /// Options with the channel numbers and sample rate of the signal
+    ///
+    fn from(signal: &SignalObject) -> Self {
+        Self::default().with_format(SourceFormat::from(signal))
+    }
+}
+
+impl From<&AudioObject> for DspOptions {
+    /// Options with the channel numbers and sample rate of the audio
+    ///
+    fn from(audio: &AudioObject) -> Self {
+        Self::default().with_format(SourceFormat::from(audio))
+    }
+}
+
+/// Source Format struct
+///
+/// Channel numbers and sample rate of the audio of a dsp process (see `DspOptions::with_format()`). If they do not
+/// match the master streamout, the audio is up/downmixed (see `qoperations::channel_matrix()`) and resampled
+/// (see `qoperations::resample()`) before queuing
+///
+/// # Args
+/// ------
+///
+/// `n_channels`: number of interleaved channels
+/// `sr`: sample rate
+///
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SourceFormat {
+    pub n_channels: usize,
+    pub sr: f32
+}
+
+impl SourceFormat {
+    pub fn new(n_channels: usize, sr: f32) -> Self {
+        Self { n_channels, sr }
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), QubxError> {
+        if self.n_channels == 0 {
+            return Err(QubxError::ParamsError(String::from("source must have at least 1 channel")))
+        }
+        if self.sr.is_nan() || self.sr <= 0.0 {
+            return Err(QubxError::ParamsError(format!("invalid source sample rate {}", self.sr)))
+        }
+        Ok(())
+    }
+}
+
+impl From<&SignalObject> for SourceFormat {
+    fn from(signal: &SignalObject) -> Self {
+        Self::new(signal.n_channels, signal.sr)
+    }
+}
+
+impl From<&AudioObject> for SourceFormat {
+    fn from(audio: &AudioObject) -> Self {
+        Self::new(audio.n_channels, audio.sr)
+    }
+}
+
+/// Frame Parameters struct
+///
+/// Frames passed to a `DspProcessArg::HybridSpace` closure. The frame size is independent of the master chunk: the
+/// results are re-blocked into chunks of the master streamout
+///
+/// # Args
+/// ------
+///
+/// `frame_size`: samples per channel in each frame (`None`: chunk of the master streamout). It can be larger than the
+/// device buffer (FFT-based processing)
+/// `hop_size`: samples per channel between the start of two frames (`None`: `frame_size`, no overlap). If it is
+/// smaller than `frame_size` the closure outputs are overlap-added
+/// `window`: analysis window applied to each frame before the closure (`None`: no window). The overlap-added output is
+/// normalized by the sum of the overlapping windows, so that an identity closure gives back the input
+///
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FrameParams {
+    pub frame_size: Option<usize>,
+    pub hop_size: Option<usize>,
+    pub window: Option<QWindow>
+}
+
+impl FrameParams {
+    /// Check if frames are the master chunks (no re-blocking)
+    ///
+    pub(crate) fn is_chunk(&self) -> bool {
+        self.frame_size.is_none() && self.hop_size.is_none() && self.window.is_none()
+    }
+
+    /// Frame and hop size in samples per channel
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `chunk`: chunk of the master streamout
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(usize, usize), QubxError>`: `ParamsError` if a size is 0 or the hop is larger than the frame
+    ///
+    pub(crate) fn get_sizes(&self, chunk: usize) -> Result<(usize, usize), QubxError> {
+        let frame_size = self.frame_size.unwrap_or(chunk);
+        let hop_size = self.hop_size.unwrap_or(frame_size);
+        if frame_size == 0 || hop_size == 0 {
+            return Err(QubxError::ParamsError(String::from("frame and hop size must be at least 1 sample")))
+        }
+        if hop_size > frame_size {
+            return Err(QubxError::ParamsError(format!("hop size {} is larger than frame size {}", hop_size, frame_size)))
+        }
+        Ok((frame_size, hop_size))
+    }
+}
+
+/// Fade Parameters struct
+///
+/// Fades of a dsp process output in the master mix (see `DspOptions::with_fades()`), applied by the master
+/// streamout with sample accuracy
+///
+/// # Args
+/// ------
+///
+/// `fade_in`: fade-in time in sec from the first sample
+/// `fade_out`: fade-out time in sec to the last sample (streams fade out only on release). It is also the default
+/// release time (see `QubxDspProcess::release()`)
+///
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FadeParams {
+    pub fade_in: f32,
+    pub fade_out: f32
+}
+
+impl FadeParams {
+    pub fn new(fade_in: f32, fade_out: f32) -> Self {
+        Self { fade_in, fade_out }
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), QubxError> {
+        if !(self.fade_in >= 0.0 && self.fade_out >= 0.0) {
+            return Err(QubxError::ParamsError(format!("invalid fade times {} {}", self.fade_in, self.fade_out)))
+        }
+        Ok(())
+    }
+
+    /// Fade-in and fade-out in frames
+    ///
+    pub(crate) fn get_frames(&self, sr: f32) -> (usize, usize) {
+        ((self.fade_in * sr).round() as usize, (self.fade_out * sr).round() as usize)
+    }
+}
+
+/// Start time of a dsp process output on the master timeline
+///
+/// `Now`: as soon as the master reads the queue
+/// `AtSample(n)`: at sample `n` of the master sample clock (sample frames since the master started)
+/// `InMillis(ms)`: `ms` milliseconds after the dsp process has been started
+///
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum StartTime {
+    #[default]
+    Now,
+    AtSample(u64),
+    InMillis(f32)
+}
+
+/// Policy of a streaming dsp queue when the master does not read it fast enough (see `StreamQueueParams`)
+///
+/// `Block`: the producer waits until the master reads a frame (default)
+/// `DropOldest`: the oldest frame in the queue is dropped and counted as overrun (the producer never waits)
+/// `Error`: the process stops with `QubxError::QueueFull` (see `ProcessHandle::take_error()`)
+///
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum QueueFullPolicy {
+    #[default]
+    Block,
+    DropOldest,
+    Error
+}
+
+/// Stream Queue Parameters struct
+///
+/// # Args
+/// ------
+///
+/// `capacity`: max number of frames (chunk buffers) in the queue. Memory is bounded and the latency of the stream is at
+/// most `capacity` * chunk samples
+/// `policy`: what to do when the queue is full (see `QueueFullPolicy`)
+/// `start`: when the output starts on the master timeline (see `StartTime`)
+/// `fade`: fade-in of the stream and release time (see `FadeParams`)
+///
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamQueueParams {
+    pub capacity: usize,
+    pub policy: QueueFullPolicy,
+    pub start: StartTime,
+    pub fade: FadeParams
+}
+
+impl Default for StreamQueueParams {
+    fn default() -> Self {
+        Self {
+
+            capacity: 4,
+            policy: QueueFullPolicy::Block,
+            start: StartTime::Now,
+            fade: FadeParams::default()
+
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -112,6 +618,20 @@ pub enum ProcessArg<T>
 {
     NoArgs,
     PatchSpace(T),
+    Processor(ProcessorType)
+}
+
+impl<T> ProcessArg<T> {
+    /// Prepare a `Processor` arg for a new stream generation (the processor is reset on restart)
+    ///
+    pub(crate) fn prepare(&mut self, generation: usize, sr: f32, block_size: usize, n_channels: usize) {
+        if let Self::Processor(processor) = self {
+            if generation > 0 {
+                processor.reset();
+            }
+            processor.prepare(sr, block_size, n_channels);
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -1,26 +1,340 @@
 #![allow(unused_variables, dead_code)]
 
-use crate::qlist::QList;
-use crate::qubx_common::{ DspProcessArg, Process, ProcessArg, ProcessState, StreamParameters };
-use pa::PortAudio;
-use portaudio as pa;
-
+use crate::qlist::{ FrameProducer, QList, QListHandle, StreamProducer, SOURCE_QUEUE_FRAMES };
+use crate::qrecorder::{ MasterTap, RecordSink, RecordWriter, RECORD_PREALLOCATED_FRAMES, RECORD_QUEUE_CAPACITY };
+use crate::qring::ring_channel;
+use crate::qpool::{ FramePool, PoolFrame };
+use crate::qubx_backend::{ BackendType, CallbackFlow, DuplexCallback, InputCallback, OfflineRender, OutputCallback };
+use crate::qrouting::{ QubxBus, RoutingError, RoutingHandle, RoutingRender };
+use crate::qstrip::{ ChannelStrip, SourceFade };
+use crate::qtransport::Transport;
+use crate::qmetrics::ProcessMetrics;
+use crate::qcommand::{ apply_commands, Crossfade, ProcessCommand, ProcessCommandSender, ProcessCommands, COMMANDS_CAPACITY };
+use crate::qprocessor::{ InPlacePatch, MappedPatch };
+use crate::qubx_control::{ ProcessControl, ProcessEvents, ProcessHandle, ProcessSpawner, WAKEUP_INTERVAL };
+use crate::qubx_common::{
+    DspOptions, DspProcessArg, FrameParams, Process, ProcessArg, ProcessState, QubxError, QueueFullPolicy, RecordTarget, SourceFormat,
+    StartTime, StreamParameters, StreamQueueParams
+};
+use crate::qubx_types::ProcessorType;
+use crate::qinterp::Interp;
+use crate::qoperations::{ remix_channels, resample };
+
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::sync::atomic::{ AtomicBool, Ordering };
-use std::sync::{ Arc, Mutex };
+use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+use std::sync::{ mpsc, Arc, Mutex };
 use std::thread::{ self, JoinHandle, ThreadId };
 use rayon::prelude::*;
+use concurrent_queue::ConcurrentQueue;
+
+/// Adapt the audio of a dsp process to the master streamout: up/downmix and resample (see `SourceFormat`)
+///
+/// # Args
+/// ------
+///
+/// `audio_data`: interleaved audio
+/// `format`: channel numbers and sample rate of the audio (`None`: already in the master format)
+/// `nchannels`, `sr`: channel numbers and sample rate of the master streamout
+///
+/// # Return
+/// --------
+///
+/// `Cow<[f32]>`: audio in the master format (borrowed if nothing changes)
+///
+fn adapt_source<'a>(audio_data: &'a [f32], format: Option<SourceFormat>, nchannels: usize, sr: f32) -> Cow<'a, [f32]> {
+    let Some(format) = format else { return Cow::Borrowed(audio_data) };
+    if audio_data.is_empty() || (format.n_channels == nchannels && format.sr == sr) {
+        return Cow::Borrowed(audio_data)
+    }
+
+    // fewer channels to resample first
+    let mut y = Cow::Borrowed(audio_data);
+    let mut channels = format.n_channels;
+    if channels > nchannels {
+        y = Cow::Owned(remix_channels(&y, channels, nchannels).unwrap_or_default());
+        channels = nchannels;
+    }
+    if format.sr != sr {
+        y = Cow::Owned(resample(&y, channels, format.sr, sr, Interp::Cubic));
+    }
+    if channels != nchannels {
+        y = Cow::Owned(remix_channels(&y, channels, nchannels).unwrap_or_default());
+    }
+    y
+}
+
+/// Process interleaved audio frame by frame with overlap-add (see `FrameParams`)
+///
+/// # Args
+/// ------
+///
+/// `audio_data`: interleaved audio
+/// `nchannels`: number of channels
+/// `frame_size`, `hop_size`: samples per channel
+/// `frame`: frame params (analysis window)
+/// `dsp_function`: closure called on each frame. Its output is truncated or zero padded to the frame length
+/// `parallel`: process the frames in parallel
+///
+/// # Return
+/// --------
+///
+/// `Vec<f32>`: processed audio (same length as `audio_data`)
+///
+fn process_frames<F>(
+    audio_data: &[f32],
+    nchannels: usize,
+    frame_size: usize,
+    hop_size: usize,
+    frame: &FrameParams,
+    dsp_function: &F,
+    parallel: bool
+) -> Vec<f32>
+where
+    F: for<'a> Fn(&'a [f32]) -> Vec<f32> + Send + Sync,
+{
+    let frame_len = frame_size * nchannels;
+    let hop_len = hop_size * nchannels;
+    let window = frame.window.map(|w| w.get_window(frame_size));
+    let starts: Vec<usize> = (0..audio_data.len()).step_by(hop_len).collect();
+
+    let process = |&start: &usize| -> Vec<f32> {
+        let mut x = vec![0.0; frame_len];
+        let end = (start + frame_len).min(audio_data.len());
+        x[..end - start].copy_from_slice(&audio_data[start..end]);
+        if let Some(ref w) = window {
+            x.iter_mut().enumerate().for_each(|(i, sample)| *sample *= w[i / nchannels]);
+        }
+        dsp_function(&x)
+    };
+
+    let outputs: Vec<Vec<f32>> = if parallel {
+        starts.par_iter().map(process).collect()
+    } else {
+        starts.iter().map(process).collect()
+    };
+
+    let mut y = vec![0.0; audio_data.len()];
+    let mut norm = vec![0.0; audio_data.len()];
+    for (start, output) in starts.iter().zip(outputs.iter()) {
+        let end = (start + frame_len).min(audio_data.len());
+        for (i, k) in (*start..end).enumerate() {
+            y[k] += output.get(i).copied().unwrap_or(0.0);
+            norm[k] += window.as_ref().map_or(1.0, |w| w[i / nchannels]);
+        }
+    }
+
+    y.iter_mut().zip(norm.iter()).for_each(|(sample, n)| {
+        *sample = if *n > f32::EPSILON { *sample / n } else { 0.0 }
+    });
+    y
+}
+
+/// Process interleaved audio chunk by chunk (frames of the master streamout, see `FrameParams::chunk()`)
+///
+/// # Args
+/// ------
+///
+/// `audio_data`: interleaved audio
+/// `chunk_len`: samples of each chunk (chunk * number of channels). The last chunk is zero padded
+/// `dsp_function`: closure called on each chunk. Its output is truncated or zero padded to the chunk length
+/// `parallel`: process the chunks in parallel
+///
+/// # Return
+/// --------
+///
+/// `Vec<f32>`: processed audio (length: multiple of `chunk_len`)
+///
+fn process_chunks<F>(audio_data: &[f32], chunk_len: usize, dsp_function: &F, parallel: bool) -> Vec<f32>
+where
+    F: for<'a> Fn(&'a [f32]) -> Vec<f32> + Send + Sync,
+{
+    let chunk_len = chunk_len.max(1);
+    let mut y = vec![0.0; audio_data.len().div_ceil(chunk_len) * chunk_len];
+    y[..audio_data.len()].copy_from_slice(audio_data);
+
+    let process = |chunk: &mut [f32]| {
+        let out = dsp_function(chunk);
+        let n = out.len().min(chunk.len());
+        chunk[..n].copy_from_slice(&out[..n]);
+        chunk[n..].fill(0.0);
+    };
+
+    if parallel {
+        y.par_chunks_mut(chunk_len).for_each(process);
+    } else {
+        y.chunks_mut(chunk_len).for_each(process);
+    }
+    y
+}
+
+/// Process interleaved audio block by block with a `Processor`. The processor latency is compensated: the input is
+/// zero padded and the first `latency` frames of the output are dropped
+///
+/// # Args
+/// ------
+///
+/// `audio_data`: interleaved audio
+/// `nchannels`: number of channels
+/// `block_size`: samples per channel of each block
+/// `processor`: processor (already prepared)
+///
+/// # Return
+/// --------
+///
+/// `Vec<f32>`: processed audio (same length as `audio_data`)
+///
+fn process_blocks(audio_data: &[f32], nchannels: usize, block_size: usize, processor: &mut ProcessorType) -> Vec<f32> {
+    let delay = processor.latency() * nchannels;
+    let block_len = block_size.max(1) * nchannels;
+
+    let mut x = audio_data.to_vec();
+    x.resize(audio_data.len() + delay, 0.0);
+    let mut y = vec![0.0; x.len()];
+    for (input, output) in x.chunks(block_len).zip(y.chunks_mut(block_len)) {
+        processor.process(input, output);
+    }
+
+    y.drain(..delay);
+    y
+}
+
+/// Push the output of a one-shot dsp process into its queue. A frame is borrowed from the pool only when the queue
+/// has room for it, so the process never holds more frames than the queue capacity (see `SOURCE_QUEUE_FRAMES`)
+///
+/// # Args
+/// ------
+///
+/// `audio_data`: interleaved audio in the master format
+/// `q`: queue of the process
+/// `pool`: frame pool of the master streamout
+/// `control`: control of the process (stop and restart end the push)
+/// `generation`: generation of the dsp thread
+/// `run`: run flag of Qubx (the master is not reading anymore when Qubx is closed)
+/// `period`: duration of a frame (`None`: offline rendering, the master reads as soon as the frames are ready)
+///
+fn push_source_frames(
+    audio_data: &[f32],
+    q: &mut FrameProducer,
+    pool: &FramePool,
+    control: &ProcessControl,
+    generation: usize,
+    run: &AtomicBool,
+    period: Option<std::time::Duration>
+) {
+    let events = control.get_events();
+    for chunk in audio_data.chunks(pool.frame_size()) {
+        loop {
+            let seen = events.get_count();
+            if !control.is_alive(generation) || !run.load(Ordering::Acquire) { return }
+
+            if !q.is_full() {
+                let mut frame = pool.acquire();
+                frame.fill_from(chunk);
+                // single producer: the queue cannot be filled in the meantime
+                let _ = q.push(frame);
+                break
+            }
+            match period {
+                // woken up early by stop and restart
+                Some(period) => { events.wait_timeout(seen, period / 2); },
+                None => thread::yield_now()
+            }
+        }
+    }
+}
+
+/// Push a frame into a streaming queue according to the queue policy
+///
+/// # Return
+/// --------
+///
+/// `Result<(), QubxError>`: `QubxError::QueueFull` if the queue is full and the policy is `QueueFullPolicy::Error`
+///
+fn push_stream_frame(
+    q: &mut StreamProducer,
+    mut frame: PoolFrame,
+    policy: QueueFullPolicy,
+    control: &ProcessControl,
+    generation: usize,
+    period: std::time::Duration
+) -> Result<(), QubxError> {
+    // released by the master (see `QubxDspProcess::release()`)
+    if q.is_closed() { return Ok(()) }
+
+    match policy {
+        QueueFullPolicy::Block => {
+            let events = control.get_events();
+            loop {
+                let seen = events.get_count();
+                match q.push(frame) {
+                    Ok(()) => return Ok(()),
+                    Err(back) => frame = back
+                }
+                if !control.is_alive(generation) || q.is_closed() { return Ok(()) }
+                // woken up early by stop and restart
+                events.wait_timeout(seen, period / 2);
+            }
+        },
+
+        QueueFullPolicy::DropOldest => {
+            if q.force_push(frame).is_some() {
+                control.get_metrics().add_overrun();
+            }
+            Ok(())
+        },
+
+        QueueFullPolicy::Error => {
+            q.push(frame).map_err(|_| {
+                control.get_metrics().add_overrun();
+                QubxError::QueueFull(control.get_name().to_string())
+            })
+        }
+    }
+}
+
+fn mix_queues(q: &mut QList, block: &mut [f32], routing: &mut RoutingRender, metrics: &ProcessMetrics) {
+    routing.prepare(block.len());
+    q.update_queues();
+    q.mix_into(block, routing, metrics);
+    routing.process(block);
+}
+
+/// Apply the master arg to the mix (in place)
+///
+#[inline]
+fn render_master_arg<F>(arg: &mut ProcessArg<F>, block: &mut Vec<f32>, processed: &mut Vec<f32>)
+where
+    F: for<'a> FnMut(&'a mut [f32]),
+{
+    match arg {
+        ProcessArg::NoArgs => { },
+        ProcessArg::PatchSpace(ref mut dsp_function) => dsp_function(block),
+        ProcessArg::Processor(ref mut processor) => {
+            processed.resize(block.len(), 0.0);
+            processor.process(block, processed);
+            std::mem::swap(block, processed);
+        }
+    };
+}
 
-fn get_chunks(audio_data: &[f32], chunk_size: usize) -> Vec<Vec<f32>> {
-    let frames :Vec<Vec<f32>> = audio_data
-    .chunks(chunk_size)
-    .map(|chunk| {
-        let mut frame_padded = vec![0.0; chunk_size];
-        frame_padded[0..chunk.len()].copy_from_slice(chunk);
-        frame_padded
-    })
-    .collect();
-    frames
+/// Apply the duplex arg to the input block. The output is written into `outblock` (preallocated, its length is the
+/// length of the output of the arg)
+///
+#[inline]
+fn render_duplex_arg<F>(arg: &mut ProcessArg<F>, inblock: &[f32], outblock: &mut Vec<f32>, out_len: usize)
+where
+    F: for<'a> FnMut(&'a [f32]) -> Vec<f32>,
+{
+    outblock.clear();
+    match arg {
+        ProcessArg::NoArgs => outblock.extend_from_slice(inblock),
+        ProcessArg::PatchSpace(ref mut dsp_function) => outblock.extend_from_slice(&dsp_function(inblock)),
+        ProcessArg::Processor(ref mut processor) => {
+            outblock.resize(out_len, 0.0);
+            processor.process(inblock, outblock);
+        }
+    }
 }
 
 /// # Master Stream-out
@@ -30,9 +344,17 @@ fn get_chunks(audio_data: &[f32], chunk_size: usize) -> Vec<Vec<f32>> {
 pub struct MasterStreamoutProcess {
     pub name: String,
     pub params: Arc<StreamParameters>,
-    pub qlist: Arc<Mutex<QList>>,
+    pub qlist: QListHandle,
     pub verbose: Arc<AtomicBool>,
     pub run: Arc<AtomicBool>,
+    pub backend: BackendType,
+    pub render: Arc<OfflineRender>,
+    pub(crate) pending_dsp: Arc<AtomicUsize>,
+    pub(crate) transport: Arc<Transport>,
+    pub(crate) drive_transport: Arc<AtomicBool>,
+    pub(crate) routing: RoutingHandle,
+    pub(crate) tap: Arc<MasterTap>,
+    pub(crate) commands: ProcessCommands,
 }
 
 impl MasterStreamoutProcess {
@@ -41,18 +363,133 @@ impl MasterStreamoutProcess {
         params: StreamParameters,
         run: Arc<AtomicBool>,
         verbose: bool,
+        backend: BackendType,
+        transport: Arc<Transport>,
     ) -> Self {
-        let qlist = QList::default();
-
         Self {
             name,
-            qlist: Arc::new(Mutex::new(qlist)),
+            qlist: QListHandle::new(params.chunk as usize, params.outchannels as usize, params.sr as f32),
+            routing: RoutingHandle::new(params.chunk as usize, params.outchannels as usize),
             params: Arc::new(params),
             verbose: Arc::new(AtomicBool::new(verbose)),
             run,
+            backend,
+            render: Arc::new(OfflineRender::default()),
+            pending_dsp: Arc::new(AtomicUsize::new(0)),
+            transport,
+            drive_transport: Arc::new(AtomicBool::new(false)),
+            tap: Arc::new(MasterTap::default()),
+            commands: ProcessCommands::default(),
         }
     }
 
+    /// Get the master sample clock
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `u64`: number of sample frames (samples per channel) mixed since the master started
+    ///
+    pub fn get_sample_clock(&self) -> u64 {
+        self.qlist.get_sample_clock()
+    }
+
+    /// Create a named bus (see `QubxBus`)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `name`: bus name
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<QubxBus, RoutingError>`
+    ///
+    pub fn create_bus(&self, name: &str) -> Result<QubxBus, RoutingError> {
+        self.routing.create_bus(name)
+    }
+
+    /// Start recording the mix (after the master closure) while it plays (see `MasterTap`)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `target`: `RecordTarget::Memory` or `RecordTarget::File(name)` (32 bit float wav)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), QubxError>`
+    ///
+    pub fn start_recording(&self, target: RecordTarget) -> Result<(), QubxError> {
+        self.tap.start(target, self.params.outchannels as usize, self.params.sr as u32, self.params.chunk as usize)
+    }
+
+    /// Stop recording the mix
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<Option<Vec<f32>>, QubxError>`: interleaved samples for `RecordTarget::Memory`, `None` for a file
+    ///
+    pub fn stop_recording(&self) -> Result<Option<Vec<f32>>, QubxError> {
+        self.tap.stop()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.tap.is_recording()
+    }
+
+    /// Get a sender of commands to the running master (see `ProcessCommand`). It can be cloned and moved to other
+    /// threads. Commands are applied at the top of the next callback
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `ProcessCommandSender`
+    ///
+    pub fn get_command_sender(&self) -> ProcessCommandSender {
+        self.commands.get_sender(&self.name, self.params.sr as f32, self.params.chunk as usize, self.params.outchannels.max(1) as usize)
+    }
+
+    /// Send a command to the running master (see `ProcessCommand`)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `command`: `ProcessCommand`
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), QubxError>`
+    ///
+    pub fn send_command(&self, command: ProcessCommand) -> Result<(), QubxError> {
+        self.get_command_sender().send(command)
+    }
+
+    /// Hot-swap the patch of the running master without closing the stream. The old and the new patch run in parallel
+    /// for the crossfade, then the old one is dropped outside the audio thread (see `ProcessCommand::Crossfade`)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `patch`: new master closure (see `MasterPatchType`)
+    /// `crossfade`: crossfade time in sec (0 swaps immediately)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), QubxError>`
+    ///
+    pub fn swap_patch<F>(&self, patch: F, crossfade: f32) -> Result<(), QubxError>
+    where
+        F: for<'a> FnMut(&'a mut [f32]) + Send + 'static,
+    {
+        self.send_command(ProcessCommand::Crossfade(Box::new(InPlacePatch(patch)), crossfade))
+    }
+
     /// Starting master streamout process
     ///
     /// # Args
@@ -61,135 +498,263 @@ impl MasterStreamoutProcess {
     /// `arg`: can be `ProcessArg::NoArgs` (means take no argumets) or `ProcessArg::Closure::<MasterPatchType>(closure)`.
     /// Closure that processes the summation of audio streams from all processes associated with the stream output. Take one arg
     /// `frame`: `&mut [f32]` (frame to be processed)
+    /// `ProcessArg::Processor(processor)` processes the mix with a `Processor` (prepared with the sample rate, the chunk and
+    /// the output channels, reset on restart)
     ///
+    /// Pending commands (see `send_command()`) are applied at the top of each callback
     /// Example:
     ///
-    /// ```rust
-    /// let mut master_out = q.create_master_streamout(String::from("M1"), stream_params);
+    /// ```ignore
+    /// let mut master_out = q.create_master_streamout(String::from("M1"), stream_params)?;
     /// let master_clos: MasterPatchType = Box::new(|frame| {
     ///    frame.iter_mut().for_each(|sample| { *sample *= 0.7 }) 
     /// });
-    /// master_out.start(ProcessArg::Closure::<MasterPatchType>(master_clos));
+    /// master_out.start(ProcessArg::Closure::<MasterPatchType>(master_clos))?;
     /// ```
     ///
+    /// On a `FileSink` backend (see `BackendType`) the mix is rendered as fast as possible into `render`
+    /// (see `OfflineRender`). The offline clock holds while a dsp process is still processing its audio (or has not pushed
+    /// the next frames into its queue yet)
+    ///
     /// # Return
     /// --------
     ///
-    /// `JoinHandle<()>`
+    /// `Result<ProcessHandle, QubxError>`
     ///
-    pub fn start<F>(&self, mut arg: ProcessArg<F>) -> JoinHandle<()>
+    pub fn start<F>(&self, arg: ProcessArg<F>, control: Arc<ProcessControl>) -> Result<ProcessHandle, QubxError>
     where
         F: for<'a> FnMut(&'a mut [f32]) + Send + Sync + 'static,
     {
-        let qlist_clone = Arc::clone(&self.qlist);
-        let params_clone = Arc::clone(&self.params);
-        let sr = params_clone.sr as f64;
-
-        let p = PortAudio::new().unwrap();
-
-        let device = match params_clone.outdevice {
-            Some(devout) => pa::DeviceIndex(devout),
-            None => p.default_output_device().unwrap(),
+        let Some(routing) = self.routing.take_render() else {
+            return Err(QubxError::MasterAlreadyStarted(self.name.clone()))
         };
 
-        let channels = params_clone.outchannels;
-        let chunk = params_clone.chunk;
+        // real-time state survives across restarts. It is locked only by the running stream (try_lock)
+        let state = Arc::new(Mutex::new(MasterRender {
+            qlist: QList::new(&self.qlist),
+            routing,
+            block: Vec::with_capacity((self.params.chunk * self.params.outchannels) as usize),
+            processed: Vec::with_capacity((self.params.chunk * self.params.outchannels) as usize),
+            faded: Vec::with_capacity((self.params.chunk * self.params.outchannels) as usize),
+            fade: Crossfade::new(self.params.sr as f32),
+            arg
+        }));
+
+        let qlist_handle = self.qlist.clone();
+        let routing_handle = self.routing.clone();
+        let params = Arc::clone(&self.params);
+        let backend_type = self.backend.clone();
+        let render = Arc::clone(&self.render);
+        let pending_dsp = Arc::clone(&self.pending_dsp);
+        let transport = Arc::clone(&self.transport);
+        let drive_transport = Arc::clone(&self.drive_transport);
+        let tap = Arc::clone(&self.tap);
+        let commands = self.commands.get_queue();
+        let retired: Arc<ConcurrentQueue<ProcessArg<F>>> = Arc::new(ConcurrentQueue::bounded(COMMANDS_CAPACITY));
         let run = Arc::clone(&self.run);
+        let name = self.name.clone();
+        let verbose = Arc::clone(&self.verbose);
+        let control_clone = Arc::clone(&control);
+        let state_clone = Arc::clone(&state);
+
+        let spawner: ProcessSpawner = Arc::new(move |generation: usize| {
+            let state = Arc::clone(&state);
+            let qlist_handle = qlist_handle.clone();
+            let routing_handle = routing_handle.clone();
+            let params_clone = Arc::clone(&params);
+            let backend_type = backend_type.clone();
+            let render = Arc::clone(&render);
+            render.reset();
+            let pending_dsp = Arc::clone(&pending_dsp);
+            let transport = Arc::clone(&transport);
+            let drive_transport = Arc::clone(&drive_transport);
+            let tap = Arc::clone(&tap);
+            let commands = Arc::clone(&commands);
+            let retired = Arc::clone(&retired);
+            let verbose = Arc::clone(&verbose);
+            let nchannels = params_clone.outchannels.max(1) as usize;
+
+            {
+                let mut state = state.lock().unwrap();
+                state.fade.reset();
+                state.arg.prepare(generation, params_clone.sr as f32, params_clone.chunk as usize, nchannels);
+            }
+
+            let is_offline = backend_type.is_offline();
+            let stop_when_drained = backend_type
+                .get_offline_params()
+                .is_some_and(|offline| offline.duration.is_none());
+
+            let run = Arc::clone(&run);
+            let run_clone = Arc::clone(&run);
+            let control = Arc::clone(&control_clone);
+            let control_clone = Arc::clone(&control_clone);
 
-        let name1 = self.name.clone();
-        let name2 = self.name.clone();
-        let verb1 = Arc::clone(&self.verbose);
+            let name = name.clone();
+            let sr = params_clone.sr as f64;
+            let metrics = Arc::clone(control.get_metrics());
+            let metrics_clone = Arc::clone(&metrics);
 
-        let count_latency_thread: Arc<Mutex<f32>> = Arc::new(Mutex::new(0.0));
-        let count_clone = Arc::clone(&count_latency_thread);
+            let (ready_tx, ready_rx) = mpsc::channel();
 
-        let latency_amount = Arc::new(Mutex::new(std::time::Duration::new(0, 0)));
-        let latency_amount_clone = Arc::clone(&latency_amount);
+            let t = thread::spawn(move || {
+                let retired_render = Arc::clone(&retired);
+                let callback: OutputCallback = Box::new(move |buffer: &mut [f32]| {
+                    let Ok(mut state) = state.try_lock() else {
+                        buffer.fill(0.0);
+                        return CallbackFlow::Continue
+                    };
 
-        thread::spawn(move || {
-            let callback = move |pa::OutputStreamCallbackArgs { buffer, .. }| {
-                let mut q = qlist_clone.lock().unwrap();
-                let mut block = vec![0.0; buffer.len()];
+                    let MasterRender { qlist, routing, block, processed, faded, fade, arg } = &mut *state;
 
-                let start_time = std::time::Instant::now();
+                    apply_commands(&commands, arg, fade, &retired_render);
 
-                for i in 0..q.length as usize {
-                    if !q.is_empty_at_index(i) {
-                        let frame = q.get_frame(i);
-                        for (b, f) in block.iter_mut().zip(frame.iter()) {
-                            *b += f;
+                    if control_clone.is_paused() {
+                        buffer.fill(0.0);
+                        return CallbackFlow::Continue
+                    }
+
+                    if is_offline {
+                        // offline clock holds while a dsp process is still processing or has not pushed the next frames
+                        loop {
+                            // registered before the count is decremented: read the count before the queues
+                            let pending = pending_dsp.load(Ordering::Acquire) > 0;
+                            qlist.update_queues();
+                            let pending = pending || qlist.is_starved(buffer.len());
+                            if !pending || !run_clone.load(Ordering::Acquire) { break }
+                            thread::yield_now();
+                        }
+
+                        if stop_when_drained && qlist.is_drained() {
+                            return CallbackFlow::Complete
                         }
                     }
-                }
 
-                drop(q);
+                    block.clear();
+                    block.resize(buffer.len(), 0.0);
 
-                // APPLY DSP TO MULTICHANNEL AUDIO OUT -> ON BUFFER VECTOR OR PASS DSP FUNCTION
-                // .
+                    let start_time = std::time::Instant::now();
 
-                match arg {
-                    ProcessArg::NoArgs => { },
-                    ProcessArg::PatchSpace(ref mut dsp_function) => dsp_function(&mut block),
-                };
-                
-                // .
+                    mix_queues(qlist, block, routing, &metrics_clone);
 
-                for (i, sample) in buffer.iter_mut().enumerate() {
-                    *sample = block[i];
-                }
+                    // APPLY DSP TO MULTICHANNEL AUDIO OUT -> ON BUFFER VECTOR OR PASS DSP FUNCTION
+                    // .
 
-                let elapsed_time = start_time.elapsed();
-                let mut lat_amount = latency_amount_clone.lock().unwrap();
-                *lat_amount += elapsed_time;
-                drop(lat_amount);
-                let mut count = count_clone.lock().unwrap();
-                *count += 1.0;
-                drop(count);
+                    if let Some(old) = fade.get_old_mut() {
+                        faded.clear();
+                        faded.extend_from_slice(block);
+                        render_master_arg(old, faded, processed);
+                    }
 
-                if verb1.load(Ordering::Acquire) {
-                    println!("[PROCESS INFO] Thread:::[Name: MASTER OUTPUT {}]:::[ID: {:?}]:::[READ FROM QUEUES, PROCESS AND OUTPUT LATENCY: {:?}]", name1, thread::current().id(), elapsed_time);
-                }
+                    render_master_arg(arg, block, processed);
 
-                pa::Continue
-            };
+                    if fade.is_active() {
+                        fade.mix(block, faded, nchannels, &retired_render);
+                    }
 
-            let device_info = p.device_info(device).unwrap();
-            let latency = device_info.default_low_output_latency;
+                    // .
 
-            let output_params =
-                pa::StreamParameters::<f32>::new(device, channels as i32, true, latency);
-            let output_settings = pa::OutputStreamSettings::new(output_params, sr, chunk);
-            let mut stream = p
-                .open_non_blocking_stream(output_settings, callback)
-                .unwrap();
+                    // the offline render waits for the recorder
+                    tap.write(block, is_offline);
 
-            stream.start().unwrap();
+                    for (i, sample) in buffer.iter_mut().enumerate() {
+                        *sample = block[i];
+                    }
 
-            while run.load(Ordering::Acquire) {
-                continue;
-            }
+                    if drive_transport.load(Ordering::Acquire) {
+                        transport.advance((buffer.len() / nchannels) as u64);
+                    }
+
+                    let period = std::time::Duration::from_secs_f64((buffer.len() / nchannels) as f64 / sr);
+                    metrics_clone.record_callback(start_time.elapsed(), period);
+
+                    CallbackFlow::Continue
+                });
+
+                let mut backend = backend_type.create_backend(&render);
+                backend.set_metrics(Arc::clone(&metrics));
+                if let Err(e) = backend.open_output(&params_clone, callback) {
+                    let e = QubxError::from(e);
+                    render.fail(e.to_string());
+                    let _ = ready_tx.send(Err(e));
+                    return
+                }
+                let latency = backend.get_output_latency();
+
+                if let Err(e) = backend.start() {
+                    let _ = backend.close();
+                    let e = QubxError::from(e);
+                    render.fail(e.to_string());
+                    let _ = ready_tx.send(Err(e));
+                    return
+                }
+                let _ = ready_tx.send(Ok(()));
+
+                // sleep until shutdown or a state change of the process. Retired queues are freed on each wakeup
+                let mut seen = control.get_events().get_count();
+                while run.load(Ordering::Acquire) && control.is_alive(generation) && backend.is_active() {
+                    qlist_handle.collect_garbage();
+                    routing_handle.collect_garbage();
+                    while retired.pop().is_ok() { }
+                    seen = control.get_events().wait_timeout(seen, WAKEUP_INTERVAL);
+                }
+
+                if verbose.load(Ordering::Acquire) {
+                    print!(
+                        "\n[PROCESSES INFO]\n:::Process Name: Master streamout {}\n:::Process Id: {:?}\n:::Output device latency: {:?}\n{}\n\n",
+                        name,
+                        thread::current().id(),
+                        std::time::Duration::from_secs_f32(latency as f32),
+                        metrics.snapshot()
+                    );
+                }
 
-            let lat_amount_sec = latency_amount.lock().unwrap();
-            let count = count_latency_thread.lock().unwrap();
-            let fac = if *count > 0.0 { *count } else { 1.0 };
-            let lat_amount = lat_amount_sec.as_secs_f32() / fac;
-            print!(
-                "\n[PROCESSES INFO]\n:::Process Name: Master streamout {}\n:::Process Id: {:?}\n:::Output device latency: {:?}\n:::Number of iterations: {}\n:::Latency average: {:?}\n\n",
-                name2,
-                thread::current().id(),
-                std::time::Duration::from_secs_f32(latency as f32),
-                *count as i32,
-                std::time::Duration::from_secs_f32(lat_amount)
-            );
-
-            println!("[INFO] Closing stream-out PortAudio...");
-            stream.stop().unwrap();
-            stream.close().unwrap();
-            p.terminate().unwrap();
-        })
+                println!("[INFO] Closing stream-out {:?} backend...", backend_type);
+                if let Err(e) = backend.close() {
+                    println!("[ERROR] {}", QubxError::from(e));
+                }
+                qlist_handle.collect_garbage();
+                while retired.pop().is_ok() { }
+                control.exit(generation);
+            });
+
+            wait_stream_ready(t, ready_rx)
+        });
+
+        let handle = ProcessHandle::spawn(control, spawner);
+        if handle.is_err() {
+            // give the buses back, so that the master streamout can be started again
+            if let Ok(state) = Arc::try_unwrap(state_clone) {
+                let state = state.into_inner().unwrap_or_else(|e| e.into_inner());
+                self.routing.restore_render(state.routing);
+            }
+        }
+        handle
     }
 }
 
+/// Real-time state of a master streamout
+///
+struct MasterRender<F> {
+    qlist: QList,
+    routing: RoutingRender,
+    block: Vec<f32>,
+    processed: Vec<f32>,
+    faded: Vec<f32>,
+    fade: Crossfade<F>,
+    arg: ProcessArg<F>
+}
+
+/// Real-time state of a duplex stream
+///
+struct DuplexRender<F> {
+    inblock: Vec<f32>,
+    outblock: Vec<f32>,
+    faded: Vec<f32>,
+    fade: Crossfade<F>,
+    arg: ProcessArg<F>
+}
+
 /// # Dsp Duplex Stream
 ///
 ///
@@ -198,17 +763,76 @@ pub struct DuplexProcess {
     params: Arc<StreamParameters>,
     verbose: Arc<AtomicBool>,
     run: Arc<AtomicBool>,
+    pub backend: BackendType,
+    pub render: Arc<OfflineRender>,
+    commands: ProcessCommands,
 }
 
 impl DuplexProcess {
-    pub fn new(params: StreamParameters, run: Arc<AtomicBool>, verbose: bool) -> Self {
+    pub fn new(params: StreamParameters, run: Arc<AtomicBool>, verbose: bool, backend: BackendType) -> Self {
         Self {
             params: Arc::new(params),
             verbose: Arc::new(AtomicBool::new(verbose)),
             run,
+            backend,
+            render: Arc::new(OfflineRender::default()),
+            commands: ProcessCommands::default(),
         }
     }
 
+    pub fn get_params(&self) -> &StreamParameters {
+        &self.params
+    }
+
+    /// Get a sender of commands to the running duplex stream (see `ProcessCommand`). It can be cloned and moved to
+    /// other threads
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `ProcessCommandSender`
+    ///
+    pub fn get_command_sender(&self) -> ProcessCommandSender {
+        self.commands.get_sender("duplex stream", self.params.sr as f32, self.params.chunk as usize, self.params.outchannels as usize)
+    }
+
+    /// Send a command to the running duplex stream (see `ProcessCommand`)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `command`: `ProcessCommand`
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), QubxError>`
+    ///
+    pub fn send_command(&self, command: ProcessCommand) -> Result<(), QubxError> {
+        self.get_command_sender().send(command)
+    }
+
+    /// Hot-swap the patch of the running duplex stream without closing the stream. The old and the new patch run in
+    /// parallel for the crossfade, then the old one is dropped outside the audio thread (see `ProcessCommand::Crossfade`)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `patch`: new duplex closure (see `DuplexPatchType`)
+    /// `crossfade`: crossfade time in sec (0 swaps immediately)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), QubxError>`
+    ///
+    pub fn swap_patch<F>(&self, patch: F, crossfade: f32) -> Result<(), QubxError>
+    where
+        F: for<'a> FnMut(&'a [f32]) -> Vec<f32> + Send + 'static,
+    {
+        self.send_command(ProcessCommand::Crossfade(Box::new(MappedPatch(patch)), crossfade))
+    }
+
     /// Starting duplex dsp stream
     ///
     /// # Args
@@ -217,143 +841,422 @@ impl DuplexProcess {
     /// `arg`: can be `ProcessArg::NoArgs` (means take no argumets) or `ProcessArg::Closure::<DuplexPatchType>(closure)`.  
     /// Closure that processes the audio streams. Take one arg frame: `&[f32]` (frame to be processed) and must be return
     /// a `Vec<f32>` (frame to output)
+    /// `ProcessArg::Processor(processor)` processes the input with a `Processor` (prepared with the output channels, the
+    /// input can have other channel numbers)
     ///
+    /// Pending commands (see `send_command()`) are applied at the top of each callback
     /// Example:
-    /// ```rust
-    /// let mut duplex = q.create_duplex_dsp_process(stream_params);
+    /// ```ignore
+    /// let mut duplex = q.create_duplex_dsp_process(stream_params)?;
     /// let clos: DuplexPatchType = Box::new(|frame| frame.to_vec());
-    /// duplex.start(ProcessArg::Closure::<DuplexPatchType>(clos));
+    /// duplex.start(ProcessArg::Closure::<DuplexPatchType>(clos))?;
     /// ```
     ///
+    /// On a `FileSink` backend (see `BackendType`) the closure processes a silent input for the render duration
+    /// as fast as possible and the output is written into `render` (see `OfflineRender`)
+    ///
     /// # Return
     /// --------
     ///
-    /// `JoinHandle<()>`
+    /// `Result<ProcessHandle, QubxError>`
     ///
-    pub fn start<F>(&self, mut arg: ProcessArg<F>) -> JoinHandle<()>
+    pub fn start<F>(&self, arg: ProcessArg<F>, control: Arc<ProcessControl>) -> Result<ProcessHandle, QubxError>
     where
         F: for<'a> FnMut(&'a [f32]) -> Vec<f32> + Send + Sync + 'static,
     {
-        let params_clone = Arc::clone(&self.params);
-        let sr = params_clone.sr as f64;
-
-        let p = pa::PortAudio::new().unwrap();
+        // without a closure the input is copied to the output
+        if matches!(arg, ProcessArg::NoArgs) && self.params.inchannels != self.params.outchannels {
+            return Err(QubxError::ChannelMismatch {
+                expected: self.params.outchannels as usize,
+                found: self.params.inchannels as usize
+            })
+        }
 
-        let indevice = match params_clone.indevice {
-            Some(dev) => pa::DeviceIndex(dev),
-            None => p.default_input_device().unwrap(),
-        };
+        // the closure survives across restarts. It is locked only by the running stream (try_lock)
+        let state = Arc::new(Mutex::new(DuplexRender {
+            inblock: Vec::with_capacity((self.params.chunk * self.params.inchannels) as usize),
+            outblock: Vec::with_capacity((self.params.chunk * self.params.outchannels) as usize),
+            faded: Vec::with_capacity((self.params.chunk * self.params.outchannels) as usize),
+            fade: Crossfade::new(self.params.sr as f32),
+            arg
+        }));
+        let commands = self.commands.get_queue();
+        let retired: Arc<ConcurrentQueue<ProcessArg<F>>> = Arc::new(ConcurrentQueue::bounded(COMMANDS_CAPACITY));
+        let params = Arc::clone(&self.params);
+        let backend_type = self.backend.clone();
+        let render = Arc::clone(&self.render);
+        let run = Arc::clone(&self.run);
+        let verbose = Arc::clone(&self.verbose);
+        let control_clone = Arc::clone(&control);
+
+        let spawner: ProcessSpawner = Arc::new(move |generation: usize| {
+            let state = Arc::clone(&state);
+            let commands = Arc::clone(&commands);
+            let retired = Arc::clone(&retired);
+            let params_clone = Arc::clone(&params);
+            let backend_type = backend_type.clone();
+            let render = Arc::clone(&render);
+            render.reset();
+
+            let inchannels = params_clone.inchannels;
+            let chunk = params_clone.chunk;
+            let period = std::time::Duration::from_secs_f64(chunk as f64 / params_clone.sr as f64);
+
+            let outchannels = params_clone.outchannels as usize;
+            {
+                let mut state = state.lock().unwrap();
+                state.fade.reset();
+                state.arg.prepare(generation, params_clone.sr as f32, chunk as usize, outchannels);
+            }
+            let run = Arc::clone(&run);
+            let control = Arc::clone(&control_clone);
+            let control_clone = Arc::clone(&control_clone);
+
+            let metrics = Arc::clone(control.get_metrics());
+            let metrics_clone = Arc::clone(&metrics);
+            let verbose = Arc::clone(&verbose);
+
+            // length of the last closure output with a wrong length, reported by the stream thread
+            let expected_len = chunk as usize * outchannels;
+            let mismatch = Arc::new(AtomicUsize::new(expected_len));
+            let mismatch_clone = Arc::clone(&mismatch);
+            let (ready_tx, ready_rx) = mpsc::channel();
+
+            let t = thread::spawn(move || {
+                let retired_render = Arc::clone(&retired);
+                let callback: DuplexCallback = Box::new(move |in_buffer: &[f32], out_buffer: &mut [f32]| {
+                    let Ok(mut state) = state.try_lock() else {
+                        out_buffer.fill(0.0);
+                        return CallbackFlow::Continue
+                    };
+                    let DuplexRender { inblock, outblock, faded, fade, arg } = &mut *state;
+
+                    apply_commands(&commands, arg, fade, &retired_render);
+
+                    if control_clone.is_paused() {
+                        out_buffer.fill(0.0);
+                        return CallbackFlow::Continue
+                    }
 
-        let inchannels = params_clone.inchannels;
+                    let start_time = std::time::Instant::now();
 
-        let outdevice = match params_clone.outdevice {
-            Some(dev) => pa::DeviceIndex(dev),
-            None => p.default_output_device().unwrap(),
-        };
+                    let inlen = (chunk * inchannels) as usize;
+                    inblock.clear();
+                    inblock.extend_from_slice(&in_buffer[..in_buffer.len().min(inlen)]);
+                    inblock.resize(inlen, 0.0);
 
-        let outchannels = params_clone.outchannels;
+                    // ATTENTION: is interleaved format! length of inblock is chunk * chnls
 
-        let chunk = params_clone.chunk;
-        let run = Arc::clone(&self.run);
-        let verb1 = Arc::clone(&self.verbose);
+                    if let Some(old) = fade.get_old_mut() {
+                        render_duplex_arg(old, inblock, faded, out_buffer.len());
+                    }
 
-        let count_latency_thread: Arc<Mutex<f32>> = Arc::new(Mutex::new(0.0));
-        let count_clone = Arc::clone(&count_latency_thread);
+                    render_duplex_arg(arg, inblock, outblock, out_buffer.len());
 
-        let latency_amount = Arc::new(Mutex::new(std::time::Duration::new(0, 0)));
-        let latency_amount_clone = Arc::clone(&latency_amount);
+                    if fade.is_active() {
+                        fade.mix(outblock, faded, outchannels, &retired_render);
+                    }
 
-        thread::spawn(move || {
-            let callback = move |pa::DuplexStreamCallbackArgs {
-                                     in_buffer,
-                                     out_buffer,
-                                     ..
-                                 }| {
-                let mut inblock = vec![0.0; (chunk * inchannels) as usize];
+                    if outblock.len() != out_buffer.len() {
+                        mismatch_clone.store(outblock.len(), Ordering::Release);
+                        out_buffer.fill(0.0);
+                        return CallbackFlow::Continue
+                    }
 
-                let start_time = std::time::Instant::now();
+                    out_buffer.copy_from_slice(outblock);
 
-                for (insample, outsample) in in_buffer.iter().zip(inblock.iter_mut()) {
-                    *outsample = *insample;
-                }
+                    metrics_clone.record_callback(start_time.elapsed(), period);
 
-                // ATTENTION: is interleaved format! length of inblock is chunk * chnls
+                    CallbackFlow::Continue
+                });
 
-                let dsp_inblock = match arg {
-                    ProcessArg::NoArgs => inblock,
-                    ProcessArg::PatchSpace(ref mut dsp_function) => dsp_function(&inblock)
+                let mut backend = backend_type.create_backend(&render);
+                backend.set_metrics(Arc::clone(&metrics));
+                if let Err(e) = backend.open_duplex(&params_clone, callback) {
+                    let e = QubxError::from(e);
+                    render.fail(e.to_string());
+                    let _ = ready_tx.send(Err(e));
+                    return
+                }
+                let inlatency = backend.get_input_latency();
+                let outlatency = backend.get_output_latency();
+
+                if let Err(e) = backend.start() {
+                    let _ = backend.close();
+                    let e = QubxError::from(e);
+                    render.fail(e.to_string());
+                    let _ = ready_tx.send(Err(e));
+                    return
+                }
+                let _ = ready_tx.send(Ok(()));
+
+                // a wrong closure output is reported once (the callback only mutes the output)
+                let mut mismatch_reported = false;
+                let mut report_mismatch = || {
+                    let found = mismatch.load(Ordering::Acquire);
+                    if found != expected_len && !mismatch_reported {
+                        let e = QubxError::ChannelMismatch { expected: expected_len, found };
+                        println!("[ERROR] The frame returned by the closure must have the same length as the out frame ({})! Output is muted", e);
+                        control.set_error(e);
+                        mismatch_reported = true;
+                    }
                 };
-                
-                assert_eq!(dsp_inblock.len(), out_buffer.len(), "[ERROR] The frame returned by the closure must have the same number of channels as the out frame!");
 
-                for (i, sample) in dsp_inblock.iter().enumerate() {
-                    out_buffer[i] = *sample
+                // sleep until shutdown or a state change of the process. Replaced args are freed on each wakeup
+                let mut seen = control.get_events().get_count();
+                while run.load(Ordering::Acquire) && control.is_alive(generation) && backend.is_active() {
+                    while retired.pop().is_ok() { }
+                    report_mismatch();
+                    seen = control.get_events().wait_timeout(seen, WAKEUP_INTERVAL);
+                }
+                while retired.pop().is_ok() { }
+                report_mismatch();
+
+                if verbose.load(Ordering::Acquire) {
+                    print!(
+                        "\n[PROCESSES INFO]\n:::Process Name: Duplex Stream\n:::Process Id: {:?}\n:::Input device latency: {:?}\n:::Output device latency: {:?}\n{}\n\n",
+                        thread::current().id(),
+                        std::time::Duration::from_secs_f32(inlatency as f32),
+                        std::time::Duration::from_secs_f32(outlatency as f32),
+                        metrics.snapshot()
+                    );
                 }
 
-                let end_time = start_time.elapsed();
+                println!("[INFO] Closing duplex stream {:?} backend...", backend_type);
+                if let Err(e) = backend.close() {
+                    println!("[ERROR] {}", QubxError::from(e));
+                }
+                control.exit(generation);
+            });
 
-                let mut lat_amount = latency_amount_clone.lock().unwrap();
-                *lat_amount += end_time;
-                drop(lat_amount);
-                let mut count = count_clone.lock().unwrap();
-                *count += 1.0;
-                drop(count);
+            wait_stream_ready(t, ready_rx)
+        });
 
-                if verb1.load(Ordering::Acquire) {
-                    println!("[PROCESS INFO] Thread:::[Name: \"DUPLEX STREAM\"]:::[ID: {:?}]:::[READ, PROCESS AND OUTPUT LATENCY: {:?}]", thread::current().id(), end_time);
-                }
+        ProcessHandle::spawn(control, spawner)
+    }
+}
+
+/// # Input Process
+///
+/// Input-only stream (capture). Each interleaved input block is delivered to a closure and/or recorded to memory or
+/// to disk (see `RecordTarget`)
+///
+#[derive(Debug)]
+pub struct InputProcess {
+    params: Arc<StreamParameters>,
+    verbose: Arc<AtomicBool>,
+    run: Arc<AtomicBool>,
+    pub backend: BackendType,
+    pub render: Arc<OfflineRender>,
+    recording: Arc<Mutex<Vec<f32>>>,
+}
 
+impl InputProcess {
+    pub fn new(params: StreamParameters, run: Arc<AtomicBool>, verbose: bool, backend: BackendType) -> Self {
+        Self {
+            params: Arc::new(params),
+            verbose: Arc::new(AtomicBool::new(verbose)),
+            run,
+            backend,
+            render: Arc::new(OfflineRender::default()),
+            recording: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn get_params(&self) -> &StreamParameters {
+        &self.params
+    }
+
+    /// Samples recorded in memory so far (interleaved, see `RecordTarget::Memory`)
+    ///
+    pub fn get_recording(&self) -> Vec<f32> {
+        self.recording.lock().unwrap().clone()
+    }
+
+    /// Starting input stream
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `arg`: `ProcessArg::NoArgs` or `ProcessArg::PatchSpace::<InputPatchType>(closure)`. The closure is called in the
+    /// audio callback with each interleaved input block (`&[f32]` of length chunk * inchannels).
+    /// `ProcessArg::Processor(processor)` processes the input before recording (the processed input is recorded)
+    /// `target`: record the input to memory or to disk (see `RecordTarget`). Blocks are handed to a writer thread
+    /// through a wait-free queue, the callback never touches the file system. `None`: no recording
+    /// `control`: control of the process
+    ///
+    /// Example:
+    /// ```ignore
+    /// let input = q.create_input_process(stream_params)?;
+    /// let handle = input.record(ProcessArg::NoArgs::<InputPatchType>, RecordTarget::File(String::from("take1")))?;
+    /// std::thread::sleep(std::time::Duration::from_secs(10));
+    /// handle.stop();
+    /// ```
+    ///
+    /// On a `FileSink` backend (see `BackendType`) a silent input is recorded for the render duration as fast as
+    /// possible
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<ProcessHandle, QubxError>`: pause drops the input while paused, restart starts a new take (the memory
+    /// recording is cleared and the file is rewritten)
+    ///
+    pub fn start<F>(&self, arg: ProcessArg<F>, target: Option<RecordTarget>, control: Arc<ProcessControl>) -> Result<ProcessHandle, QubxError>
+    where
+        F: for<'a> FnMut(&'a [f32]) + Send + Sync + 'static,
+    {
+        // the closure survives across restarts. It is locked only by the running stream (try_lock)
+        let arg = Arc::new(Mutex::new(arg));
+        let params = Arc::clone(&self.params);
+        let backend_type = self.backend.clone();
+        let render = Arc::clone(&self.render);
+        let recording = Arc::clone(&self.recording);
+        let run = Arc::clone(&self.run);
+        let verbose = Arc::clone(&self.verbose);
+        let control_clone = Arc::clone(&control);
+
+        let spawner: ProcessSpawner = Arc::new(move |generation: usize| {
+            let arg = Arc::clone(&arg);
+            let params_clone = Arc::clone(&params);
+            let backend_type = backend_type.clone();
+            let render = Arc::clone(&render);
+            render.reset();
+            let verbose = Arc::clone(&verbose);
+
+            let inchannels = params_clone.inchannels as usize;
+            let chunk = params_clone.chunk as usize;
+            let period = std::time::Duration::from_secs_f64(chunk as f64 / params_clone.sr as f64);
+
+            arg.lock().unwrap().prepare(generation, params_clone.sr as f32, chunk, inchannels);
+            let mut processed = vec![0.0; chunk * inchannels];
+            let run = Arc::clone(&run);
+            let control = Arc::clone(&control_clone);
+            let control_clone = Arc::clone(&control_clone);
+
+            let metrics = Arc::clone(control.get_metrics());
+            let metrics_clone = Arc::clone(&metrics);
+
+            // the sink is opened here, so that file errors are returned by start()
+            let sink = match target {
+                Some(ref target) => Some(RecordSink::open(target, &recording, inchannels, params_clone.sr as u32)?),
+                None => None
+            };
 
-                pa::Continue
+            let pool = FramePool::new(chunk * inchannels, RECORD_PREALLOCATED_FRAMES, RECORD_PREALLOCATED_FRAMES);
+            let (mut producer, writer): (Option<FrameProducer>, Option<RecordWriter>) = match sink {
+                Some(sink) => {
+                    let (producer, consumer) = ring_channel(RECORD_QUEUE_CAPACITY);
+                    (Some(producer), Some(RecordWriter::spawn(consumer, sink, period)))
+                },
+                None => (None, None)
             };
 
-            let devin_info = p.device_info(indevice).unwrap();
-            let inlatency = devin_info.default_low_input_latency;
+            let (ready_tx, ready_rx) = mpsc::channel();
 
-            let inparams =
-                pa::StreamParameters::<f32>::new(indevice, inchannels as i32, true, inlatency);
+            let t = thread::spawn(move || {
+                let callback: InputCallback = Box::new(move |in_buffer: &[f32]| {
+                    let Ok(mut arg) = arg.try_lock() else {
+                        return CallbackFlow::Continue
+                    };
 
-            let devout_info = p.device_info(outdevice).unwrap();
-            let outlatency = devout_info.default_low_output_latency;
+                    if control_clone.is_paused() {
+                        return CallbackFlow::Continue
+                    }
 
-            let outparams =
-                pa::StreamParameters::<f32>::new(outdevice, outchannels as i32, true, outlatency);
+                    let start_time = std::time::Instant::now();
+
+                    // ATTENTION: is interleaved format! length of in_buffer is chunk * chnls
+
+                    let block = match *arg {
+                        ProcessArg::NoArgs => in_buffer,
+                        ProcessArg::PatchSpace(ref mut dsp_function) => {
+                            dsp_function(in_buffer);
+                            in_buffer
+                        },
+                        ProcessArg::Processor(ref mut processor) => {
+                            // the processed input is recorded
+                            processed.resize(in_buffer.len(), 0.0);
+                            processor.process(in_buffer, &mut processed);
+                            &processed[..]
+                        }
+                    };
+
+                    if let Some(ref mut producer) = producer {
+                        // the writer is late: the block is lost (never allocated in the audio thread)
+                        match pool.try_acquire() {
+                            Some(mut frame) => {
+                                frame.fill_from(block);
+                                if producer.push(frame).is_err() {
+                                    metrics_clone.add_overrun();
+                                }
+                            },
+                            None => metrics_clone.add_overrun()
+                        }
+                    }
 
-            let stream_settings = pa::DuplexStreamSettings::new(inparams, outparams, sr, chunk);
+                    metrics_clone.record_callback(start_time.elapsed(), period);
 
-            let mut stream = p
-                .open_non_blocking_stream(stream_settings, callback)
-                .unwrap();
+                    CallbackFlow::Continue
+                });
 
-            stream.start().unwrap();
+                let mut backend = backend_type.create_backend(&render);
+                backend.set_metrics(Arc::clone(&metrics));
+                let opened = backend.open_input(&params_clone, callback).and_then(|_| backend.start());
+                if let Err(e) = opened {
+                    let _ = backend.close();
+                    if let Some(writer) = writer {
+                        let _ = writer.finish();
+                    }
+                    let e = QubxError::from(e);
+                    render.fail(e.to_string());
+                    let _ = ready_tx.send(Err(e));
+                    return
+                }
+                let inlatency = backend.get_input_latency();
+                let _ = ready_tx.send(Ok(()));
 
-            while run.load(Ordering::Acquire) {
-                continue;
-            }
+                // sleep until shutdown or a state change of the process
+                let mut seen = control.get_events().get_count();
+                while run.load(Ordering::Acquire) && control.is_alive(generation) && backend.is_active() {
+                    seen = control.get_events().wait_timeout(seen, WAKEUP_INTERVAL);
+                }
 
-            let lat_amount_sec = latency_amount.lock().unwrap();
-            let count = count_latency_thread.lock().unwrap();
-            let fac = if *count > 0.0 { *count } else { 1.0 };
-            let lat_amount = lat_amount_sec.as_secs_f32() / fac;
-            print!(
-                "\n[PROCESSES INFO]\n:::Process Name: Duplex Stream\n:::Process Id: {:?}\n:::Input device latency: {:?}\n:::Output device latency: {:?}\n:::Number of iterations: {}\n:::Latency average: {:?}\n\n",
-                thread::current().id(),
-                std::time::Duration::from_secs_f32(inlatency as f32),
-                std::time::Duration::from_secs_f32(outlatency as f32),
-                *count as i32,
-                std::time::Duration::from_secs_f32(lat_amount)
-            );
-
-            println!("[INFO] Closing PortaAudio duplex stream...");
-            stream.stop().unwrap();
-            stream.close().unwrap();
-            p.terminate().unwrap();
-        })
+                println!("[INFO] Closing input stream {:?} backend...", backend_type);
+                if let Err(e) = backend.close() {
+                    println!("[ERROR] {}", QubxError::from(e));
+                }
+
+                // the callback is done: write the last blocks
+                if let Some(writer) = writer {
+                    if let Err(e) = writer.finish() {
+                        println!("[ERROR] {}", e);
+                        control.set_error(e);
+                    }
+                }
+
+                if verbose.load(Ordering::Acquire) {
+                    print!(
+                        "\n[PROCESSES INFO]\n:::Process Name: Input Stream\n:::Process Id: {:?}\n:::Input device latency: {:?}\n{}\n\n",
+                        thread::current().id(),
+                        std::time::Duration::from_secs_f32(inlatency as f32),
+                        metrics.snapshot()
+                    );
+                }
+                control.exit(generation);
+            });
+
+            wait_stream_ready(t, ready_rx)
+        });
+
+        ProcessHandle::spawn(control, spawner)
     }
 }
 
-/// # Dsp Process (TODO: implement frame size for elab != chunk)
+/// # Dsp Process
 ///
+/// Write the output of a dsp arg into its own queue of the master streamout. `HybridSpace` closures process frames of
+/// any size (see `FrameParams`), the results are re-blocked into master chunks
 ///
 pub struct DspProcess {
     monitor_processes: Arc<Mutex<MonitorProcess>>,
@@ -361,7 +1264,8 @@ pub struct DspProcess {
     verbose: Arc<AtomicBool>,
     dsp_latency_amount: Arc<Mutex<std::time::Duration>>,
     count_dsp_iterations: Arc<Mutex<f32>>,
-    use_parallel_computation: bool
+    use_parallel_computation: bool,
+    strip: ChannelStrip
 }
 
 impl DspProcess {
@@ -373,16 +1277,40 @@ impl DspProcess {
         count_dsp_iterations: Arc<Mutex<f32>>,
         use_parallel: bool
     ) -> Self {
+        let master = master_streamout.lock().unwrap();
+        let strip = ChannelStrip::new(master.qlist.get_solo_count(), master.routing.clone());
+        drop(master);
         Self {
             monitor_processes,
             master_streamout,
             verbose,
             dsp_latency_amount,
             count_dsp_iterations,
-            use_parallel_computation: use_parallel
+            use_parallel_computation: use_parallel,
+            strip
         }
     }
 
+    /// Get the channel strip of the dsp process in the master mix (see `ChannelStrip`)
+    ///
+    pub fn get_channel_strip(&self) -> ChannelStrip {
+        self.strip.clone()
+    }
+
+    /// Release the output of the dsp process: the sources in the mix fade out and their queues are removed from the
+    /// master streamout (streams stop at the end of the fade-out). Sources started after the call are not released
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `fade_out`: fade-out time in sec (`None`: fade-out of each source, see `FadeParams`). Sources without fade-out
+    /// are ramped down in 5 ms
+    ///
+    pub fn release(&self, fade_out: Option<f32>) {
+        let sr = self.master_streamout.lock().unwrap().params.sr as f32;
+        self.strip.get_state().release(fade_out.map(|time| (time.max(0.0) * sr).round() as usize));
+    }
+
     /// Starting dsp process
     ///
     /// # Args
@@ -392,109 +1320,368 @@ impl DspProcess {
     /// DspProcessArg::Closure::<DspPatchType, DspHybridType> (pass a closure thare take no arguments and return `Vec<f32>` as audio data) or 
     /// DspProcessArg::AudioAndClosure::<DspPatchType, DspHybridType> (pass audio data as `Vec<f32>` and closure. 
     /// Closure take one argument `&[f32]` and return a `Vec<f32>`).
+    /// `DspProcessArg::Processor(audio_data, processor)` processes the audio with a `Processor` in blocks of frame size
+    /// (see `FrameParams`, the master chunk by default). Its latency is compensated. Generators take a silent source
+    /// of the output length (e.g. `vec![0.0; n_frames * outchannels]`).
+    /// `options`: options of the arg (see `DspOptions`).
+    /// The output can be scheduled on the master timeline with `DspOptions::at(StartTime)` (see `StartTime`): it
+    /// starts exactly at the requested sample, also inside a block.
+    /// `HybridSpace` frames can be set with `DspOptions::with_frames(FrameParams)`: frame size independent of the
+    /// master chunk, hop size and analysis window (overlap-add).
+    /// Audio with other channel numbers or sample rate than the master streamout must be tagged with
+    /// `DspOptions::with_format(SourceFormat)` (or `DspOptions::from()` a `SignalObject`/`AudioObject`): it is
+    /// up/downmixed and resampled before queuing.
+    /// The output can fade in and out in the mix with `DspOptions::with_fades(FadeParams)` (see `release()`).
     ///
     /// Example:
-    /// ```rust
+    /// ```ignore
     /// let dsp_clos = Box::new(|_audio_data| {
     /// let y = _audio_data.iter().map(|sample| sample * 0.7).collect();
     /// y
     /// });
     ///
-    /// dsp_process1.start(DspProcessArg::AudioDataAndClosure::<DspPatchType, DspHybridType>(audio_data1, dsp_clos));
-    /// dsp_process2.start(DspProcessArg::AudioData::<DspPatchType, DspHybridType>(audio_data2));
+    /// dsp_process1.start(DspProcessArg::AudioDataAndClosure::<DspPatchType, DspHybridType>(audio_data1, dsp_clos))?;
+    /// dsp_process2.start(DspProcessArg::AudioData::<DspPatchType, DspHybridType>(audio_data2))?;
+    /// dsp_process3.start_with(
+    ///     DspProcessArg::Source::<DspPatchType, DspHybridType>(audio_data3),
+    ///     DspOptions::default().at(StartTime::InMillis(250.0))
+    /// )?;
+    ///
+    /// let frames = FrameParams { frame_size: Some(4096), hop_size: Some(1024), window: Some(QWindow::Hanning) };
+    /// dsp_process4.start_with(
+    ///     DspProcessArg::HybridSpace::<DspPatchType, DspHybridType>(audio_data4, fft_clos),
+    ///     DspOptions::default().with_frames(frames)
+    /// )?;
+    ///
+    /// let audio = buffer.to_audio_object("mono_22050.wav")?;
+    /// let options = DspOptions::from(&audio);
+    /// dsp_process5.start_with(DspProcessArg::Source::<DspPatchType, DspHybridType>(audio.vector_signal), options)?;
+    ///
+    /// let delay = PerChannel::new(|| DelayBuffer::new(11025));
+    /// dsp_process6.start(DspProcessArg::Processor::<DspPatchType, DspHybridType>(audio_data6, Box::new(delay)))?;
     ///
     /// ```
     ///
     /// # Return
     /// --------
     ///
-    /// `JoinHandle<()>`
+    /// `Result<ProcessHandle, QubxError>`: pause holds the output of the process in the master mix, restart processes the args again
+    /// from the beginning (with the same `StartTime`)
     ///
-    pub fn start<F1, F2>(&self, args: DspProcessArg<F1, F2>) -> JoinHandle<()>
+    pub fn start<F1, F2>(
+        &self,
+        args: DspProcessArg<F1, F2>,
+        options: DspOptions,
+        control: Arc<ProcessControl>
+    ) -> Result<ProcessHandle, QubxError>
     where
         F1: Fn() -> Vec<f32> + Send + Sync + 'static,
         F2: for<'a> Fn(&'a [f32]) -> Vec<f32> + Send + Sync + 'static,
     {
-        let pclone = Arc::clone(&self.monitor_processes);
-        let mclone = Arc::clone(&self.master_streamout);
         let verbose = Arc::clone(&self.verbose);
-        let dsp_lat_amount_clone = Arc::clone(&self.dsp_latency_amount);
-        let count_iter = Arc::clone(&self.count_dsp_iterations);
-
-        // let dsp_ptr = Arc::new(Mutex::new(dsp_function));
-        // let dsp_ptr_clone = Arc::clone(&dsp_ptr);
+        let dsp_lat_amount = Arc::clone(&self.dsp_latency_amount);
+        let count_dsp_iter = Arc::clone(&self.count_dsp_iterations);
 
         let use_par_ptr = Arc::new(self.use_parallel_computation);
 
         let params = self.master_streamout.lock().unwrap();
-        let chunk_size = (params.params.chunk * params.params.outchannels) as usize; // Frame length must be chunk size * nchnls out -> streamout
         let ms_name = params.name.to_string();
+        let sr = params.params.sr;
+        let nchannels = params.params.outchannels.max(1) as usize;
+        let chunk = params.params.chunk as usize;
+        let pending_dsp = Arc::clone(&params.pending_dsp);
+        let run = Arc::clone(&params.run);
+        let is_offline = params.backend.is_offline();
+        let strip_state = self.strip.get_state();
+        let qlist_handle = params.qlist.clone(); // Queue frames are chunk size * nchnls out -> streamout (see FramePool)
         drop(params);
 
-        thread::spawn(move || {
-            let start = std::time::Instant::now();
+        let (frame_size, hop_size) = options.frame.get_sizes(chunk)?;
+        if let Some(format) = options.format {
+            format.validate()?;
+        }
+        options.fade.validate()?;
+        let (fade_in, fade_out) = options.fade.get_frames(sr as f32);
+        let (start_time, frame, format) = (options.start, options.frame, options.format);
+        // a processor is moved from one generation to the next (restart)
+        let args = Arc::new(Mutex::new(args));
+        let control_clone = Arc::clone(&control);
+
+        let spawner: ProcessSpawner = Arc::new(move |generation: usize| {
+            let start_sample = match start_time {
+                StartTime::Now => 0,
+                StartTime::AtSample(n) => n,
+                StartTime::InMillis(ms) => qlist_handle.get_sample_clock() + (ms.max(0.0) * sr as f32 / 1000.0).round() as u64
+            };
 
-            let frames: Vec<Vec<f32>> = match args {
+            pending_dsp.fetch_add(1, Ordering::AcqRel);
+
+            let args = Arc::clone(&args);
+            let use_par_ptr = Arc::clone(&use_par_ptr);
+            let verbose = Arc::clone(&verbose);
+            let dsp_lat_amount_clone = Arc::clone(&dsp_lat_amount);
+            let count_iter = Arc::clone(&count_dsp_iter);
+            let pending_dsp = Arc::clone(&pending_dsp);
+            let run = Arc::clone(&run);
+            let strip_state = Arc::clone(&strip_state);
+            let qlist_handle = qlist_handle.clone();
+            let ms_name = ms_name.clone();
+            let control = Arc::clone(&control_clone);
+
+            Ok(thread::spawn(move || {
+                let start = std::time::Instant::now();
+
+                let pool = qlist_handle.get_pool();
+                let period = std::time::Duration::from_secs_f64((pool.frame_size() / nchannels) as f64 / sr as f64);
+                let period = if is_offline { None } else { Some(period) };
+                let mut args = args.lock().unwrap_or_else(|e| e.into_inner());
+                // length: samples of the output without the padding of the last frame (see `SourceFade`)
+                let (audio_data, length): (Cow<[f32]>, usize) = match *args {
+
+                    DspProcessArg::Source(ref audio_data) => {
+                        let audio_data = adapt_source(audio_data, format, nchannels, sr as f32);
+                        let length = audio_data.len();
+                        (audio_data, length)
+                    },
+
+                    DspProcessArg::PatchSpace(ref dsp_function) => {
+                        let audio_data = dsp_function();
+                        let audio_data = adapt_source(&audio_data, format, nchannels, sr as f32).into_owned();
+                        let length = audio_data.len();
+                        (Cow::Owned(audio_data), length)
+                    },
+
+                    DspProcessArg::HybridSpace(ref audio_data, ref dsp_function) if !frame.is_chunk() => {
+                        // frames independent of the chunk: overlap-add and re-block into master chunks
+                        let audio_data = adapt_source(audio_data, format, nchannels, sr as f32);
+                        let y = process_frames(&audio_data, nchannels, frame_size, hop_size, &frame, dsp_function, *use_par_ptr);
+                        let length = y.len();
+                        (Cow::Owned(y), length)
+                    },
+
+                    DspProcessArg::HybridSpace(ref audio_data, ref dsp_function) => {
+                        let audio_data = adapt_source(audio_data, format, nchannels, sr as f32);
+                        let y = process_chunks(&audio_data, pool.frame_size(), dsp_function, *use_par_ptr);
+                        (Cow::Owned(y), audio_data.len())
+                    },
+
+                    DspProcessArg::Processor(ref audio_data, ref mut processor) => {
+                        // block size: frame size (see `FrameParams`), the hop size and the window are ignored
+                        if generation > 0 {
+                            processor.reset();
+                        }
+                        processor.prepare(sr as f32, frame_size, nchannels);
+                        let audio_data = adapt_source(audio_data, format, nchannels, sr as f32);
+                        let y = process_blocks(&audio_data, nchannels, frame_size, processor);
+                        let length = y.len();
+                        (Cow::Owned(y), length)
+                    }
+                };
 
-                DspProcessArg::Source(ref audio_data) => {
-                    get_chunks(audio_data, chunk_size)
-                },
+                // processing time against the duration of the processed audio
+                let end = start.elapsed();
+                let audio_duration = std::time::Duration::from_secs_f64((audio_data.len() / nchannels) as f64 / sr as f64);
+                control.get_metrics().record_callback(end, audio_duration);
+
+                if control.is_alive(generation) {
+                    let fade = SourceFade::new(fade_in, fade_out, Some(length / nchannels), sr as f32);
+                    let n_frames = audio_data.len().div_ceil(pool.frame_size());
+                    let capacity = n_frames.clamp(1, SOURCE_QUEUE_FRAMES);
+                    let mut q = qlist_handle.register_queue(capacity, start_sample, strip_state, Arc::clone(&control), generation, fade);
+                    // the master holds the offline clock while the queue is starved (see `QList::is_starved()`)
+                    pending_dsp.fetch_sub(1, Ordering::AcqRel);
+
+                    push_source_frames(&audio_data, &mut q, pool, &control, generation, &run, period);
+                    drop(q);
+                } else {
+                    pending_dsp.fetch_sub(1, Ordering::AcqRel);
+                }
+                drop(audio_data);
+                drop(args);
 
-                DspProcessArg::PatchSpace(dsp_function) => {
-                    let audio_data = dsp_function(); 
-                    get_chunks(&audio_data, chunk_size)
-                },
+                let mut lat_amount = dsp_lat_amount_clone.lock().unwrap();
+                *lat_amount += end;
+                drop(lat_amount);
 
-                DspProcessArg::HybridSpace(ref audio_data, dsp_function) => {
-                    let mut f: Vec<Vec<f32>> = get_chunks(audio_data, chunk_size);
-                    if *use_par_ptr {
-                        f = f.par_iter().map(|frame| dsp_function(frame)).collect();
-                    } else {
-                        f = f.iter().map(|frame| dsp_function(frame)).collect();
-                    }
+                let mut count = count_iter.lock().unwrap();
+                *count += 1.0;
+                drop(count);
 
-                    f
+                if verbose.load(Ordering::Acquire) {
+                    println!(
+                        "[PROCESS INFO] Thread:::[Name: \"DSP\" >>> Master streamout {}]:::[ID: {:?}]:::[PROCESS AND WRITE TO QUEUE LATENCY: {:?}]",
+                        ms_name,
+                        thread::current().id(),
+                        end
+                    )
                 }
+
+                control.exit(generation);
+            }))
+        });
+
+        ProcessHandle::spawn(control, spawner)
+    }
+
+    /// Start a streaming dsp process. The closure is called repeatedly to fill the next frame (chunk * outchannels
+    /// samples of the master streamout, interleaved and cleared) until it returns `false` or the process is stopped.
+    /// Frames are written into a bounded queue read by the master streamout (see `StreamQueueParams`), so memory is
+    /// bounded and latency is at most `capacity` frames
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `closure`: fill the frame and return `true` to keep streaming
+    /// `params`: queue capacity, policy when the queue is full and start time (see `StreamQueueParams`)
+    /// `control`: control of the process
+    ///
+    /// Example:
+    /// ```ignore
+    /// let mut phase = 0.0f32;
+    /// let sine: DspStreamType = Box::new(move |frame| {
+    ///     frame.iter_mut().for_each(|sample| { *sample = phase.sin() * 0.5; phase += 0.05 });
+    ///     true
+    /// });
+    ///
+    /// let params = StreamQueueParams { capacity: 4, policy: QueueFullPolicy::Block, ..Default::default() };
+    /// dsp_process.start_stream(sine, params)?;
+    /// ```
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<ProcessHandle, QubxError>`: pause stops calling the closure, restart resumes the stream from the current
+    /// state of the closure
+    ///
+    pub fn start_stream<F>(&self, closure: F, params: StreamQueueParams, control: Arc<ProcessControl>) -> Result<ProcessHandle, QubxError>
+    where
+        F: for<'a> FnMut(&'a mut [f32]) -> bool + Send + 'static,
+    {
+        if params.capacity == 0 {
+            return Err(QubxError::ParamsError(String::from("stream queue capacity must be at least 1 frame")))
+        }
+        params.fade.validate()?;
+
+        let verbose = Arc::clone(&self.verbose);
+
+        let master = self.master_streamout.lock().unwrap();
+        let ms_name = master.name.to_string();
+        let sr = master.params.sr;
+        let nchannels = master.params.outchannels.max(1) as usize;
+        let pending_dsp = Arc::clone(&master.pending_dsp);
+        let strip_state = self.strip.get_state();
+        let qlist_handle = master.qlist.clone();
+        drop(master);
+
+        // the closure is moved from one generation to the next (restart)
+        let closure = Arc::new(Mutex::new(closure));
+        let control_clone = Arc::clone(&control);
+
+        let spawner: ProcessSpawner = Arc::new(move |generation: usize| {
+            let start_sample = match params.start {
+                StartTime::Now => 0,
+                StartTime::AtSample(n) => n,
+                StartTime::InMillis(ms) => qlist_handle.get_sample_clock() + (ms.max(0.0) * sr as f32 / 1000.0).round() as u64
             };
 
-            let m = mclone.lock().unwrap();
-            let qclone = Arc::clone(&m.qlist);
-            let mut q = qclone.lock().unwrap();
-            for f in frames.iter() {
-            	q.put_frame(f.clone());
-            }
+            pending_dsp.fetch_add(1, Ordering::AcqRel);
+
+            let closure = Arc::clone(&closure);
+            let verbose = Arc::clone(&verbose);
+            let pending_dsp = Arc::clone(&pending_dsp);
+            let strip_state = Arc::clone(&strip_state);
+            let qlist_handle = qlist_handle.clone();
+            let ms_name = ms_name.clone();
+            let control = Arc::clone(&control_clone);
+
+            Ok(thread::spawn(move || {
+                let mut closure = closure.lock().unwrap_or_else(|e| e.into_inner());
+
+                let pool = qlist_handle.get_pool();
+                let period = std::time::Duration::from_secs_f64((pool.frame_size() / nchannels) as f64 / sr as f64);
+                let events = control.get_events();
+                let metrics = control.get_metrics();
+
+                let q = if control.is_alive(generation) {
+                    let (fade_in, fade_out) = params.fade.get_frames(sr as f32);
+                    let fade = SourceFade::new(fade_in, fade_out, None, sr as f32);
+                    Some(qlist_handle.register_stream_queue(params.capacity, start_sample, strip_state, Arc::clone(&control), generation, fade))
+                } else {
+                    None
+                };
 
-            q.get_next_empty_queue();
+                // a stream never holds the offline clock, the master waits for its frames instead
+                pending_dsp.fetch_sub(1, Ordering::AcqRel);
 
-            drop(q);
-            drop(m);
+                if let Some(mut q) = q {
+                    while control.is_alive(generation) {
+                        if control.is_paused() {
+                            let seen = events.get_count();
+                            events.wait_timeout(seen, period);
+                            continue
+                        }
 
-            let end = start.elapsed();
-            let id = thread::current().id();
+                        let mut frame = pool.acquire();
+                        frame.fill(0.0);
 
-            let mut lat_amount = dsp_lat_amount_clone.lock().unwrap();
-            *lat_amount += end;
-            drop(lat_amount);
+                        let start = std::time::Instant::now();
+                        let more = closure(&mut frame);
+                        metrics.record_callback(start.elapsed(), period);
 
-            let mut count = count_iter.lock().unwrap();
-            *count += 1.0;
-            drop(count);
+                        if let Err(e) = push_stream_frame(&mut q, frame, params.policy, &control, generation, period) {
+                            println!("[ERROR] {} >>> Master streamout {}", e, ms_name);
+                            control.set_error(e);
+                            break
+                        }
 
-            if verbose.load(Ordering::Acquire) {
-                println!(
-                    "[PROCESS INFO] Thread:::[Name: \"DSP\" >>> Master streamout {}]:::[ID: {:?}]:::[PROCESS AND WRITE TO QUEUE LATENCY: {:?}]",
-                    ms_name,
-                    thread::current().id(),
-                    end
-                )
-            }
+                        // released: the master has faded out the stream
+                        if !more || q.is_closed() { break }
+                    }
 
-            let mut pm = pclone.lock().unwrap();
-            if let Some(p) = pm.processes.get_mut(&id) {
-                p.state = ProcessState::Off
-            };
-            drop(pm);
-        })
+                    drop(q);
+                }
+
+                if verbose.load(Ordering::Acquire) {
+                    println!(
+                        "[PROCESS INFO] Thread:::[Name: \"DSP STREAM\" >>> Master streamout {}]:::[ID: {:?}]:::[FRAMES: {}]",
+                        ms_name,
+                        thread::current().id(),
+                        metrics.snapshot().callbacks
+                    )
+                }
+
+                control.exit(generation);
+            }))
+        });
+
+        ProcessHandle::spawn(control, spawner)
+    }
+}
+
+/// Wait until a stream thread has opened and started its backend
+///
+/// # Args
+/// ------
+///
+/// `t`: stream thread
+/// `ready`: result sent by the thread after starting the backend
+///
+/// # Return
+/// --------
+///
+/// `Result<JoinHandle<()>, QubxError>`: on error the thread is already joined
+///
+fn wait_stream_ready(t: JoinHandle<()>, ready: mpsc::Receiver<Result<(), QubxError>>) -> Result<JoinHandle<()>, QubxError> {
+    match ready.recv() {
+        Ok(Ok(())) => Ok(t),
+        Ok(Err(e)) => {
+            let _ = t.join();
+            Err(e)
+        },
+        Err(_) => {
+            let _ = t.join();
+            Err(QubxError::StreamOpenError(String::from("stream thread terminated before opening the stream")))
+        }
     }
 }
 
@@ -503,6 +1690,7 @@ impl DspProcess {
 pub struct MonitorProcess {
     pub processes: HashMap<ThreadId, Process>,
     verbose: Arc<AtomicBool>,
+    events: Arc<ProcessEvents>,
 }
 
 impl MonitorProcess {
@@ -511,9 +1699,16 @@ impl MonitorProcess {
         Self {
             processes,
             verbose: Arc::new(AtomicBool::new(verbose)),
+            events: Arc::new(ProcessEvents::default()),
         }
     }
 
+    /// Events notified on shutdown and on process state changes (see `ProcessEvents`)
+    ///
+    pub(crate) fn get_events(&self) -> Arc<ProcessEvents> {
+        Arc::clone(&self.events)
+    }
+
     pub fn add_process(&mut self, process: Process) {
         if Arc::clone(&self.verbose).load(Ordering::Acquire) {
             println!(
@@ -527,10 +1722,31 @@ impl MonitorProcess {
         self.processes.insert(process.handle.thread().id(), process);
     }
 
+    /// Update the state of a process
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `id`: thread id of the process
+    /// `state`: new state
+    ///
+    pub fn set_state(&mut self, id: &ThreadId, state: ProcessState) {
+        if let Some(p) = self.processes.get_mut(id) {
+            if p.state != state && self.verbose.load(Ordering::Acquire) {
+                println!("[PROCESS INFO] Thread:::[Name: {:?}]:::[ID: {:?}]:::[State: {:?}]", p.name, id, state)
+            }
+            p.state = state;
+            self.events.notify();
+        }
+    }
+
+    /// Remove and join the processes that are `Off` and whose thread has exited (a stopped process is reported `Off`
+    /// before its thread exits, joining it here would block the monitor)
+    ///
     pub fn remove_inactive_processes(&mut self) {
         let mut inactive_processes: Vec<ThreadId> = Vec::new();
         for (id, process) in self.processes.iter() {
-            if process.state == ProcessState::Off {
+            if process.state == ProcessState::Off && process.handle.is_finished() {
                 inactive_processes.push(*id);
             }
         }
@@ -546,11 +1762,36 @@ impl MonitorProcess {
                     )
                 }
 
-                p.handle.join().unwrap()
+                if p.handle.join().is_err() {
+                    println!("[ERROR] Thread {:?} panicked!", p.name);
+                }
             }
         }
     }
 
+    /// Check if some process is `Off` but its thread has not exited yet
+    ///
+    pub(crate) fn has_exiting_processes(&self) -> bool {
+        self.processes.values().any(|p| p.state == ProcessState::Off && !p.handle.is_finished())
+    }
+
+    /// Remove a process without joining it (the caller joins it with the monitor unlocked)
+    ///
+    pub(crate) fn take_process(&mut self, id: &ThreadId) -> Option<Process> {
+        self.processes.remove(id)
+    }
+
+    /// Move all processes into a new monitor sharing the same events, so that they can be joined while this monitor
+    /// stays unlocked (the threads update their state when they exit)
+    ///
+    pub(crate) fn take_processes(&mut self) -> Self {
+        Self {
+            processes: std::mem::take(&mut self.processes),
+            verbose: Arc::clone(&self.verbose),
+            events: Arc::clone(&self.events),
+        }
+    }
+
     pub fn join_and_remove_all(&mut self) {
         let mut to_remove: Vec<ThreadId> = Vec::new();
         for (id, process) in self.processes.iter_mut() {
@@ -569,9 +1810,96 @@ impl MonitorProcess {
                     )
                 }
 
-                process.handle.join().unwrap();
+                if process.handle.join().is_err() {
+                    println!("[ERROR] Thread {:?} panicked!", process.name);
+                }
             }
         }
         self.processes.clear();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qwindow::QWindow;
+
+    fn ramp(n: usize) -> Vec<f32> {
+        (0..n).map(|i| i as f32 / n as f32).collect()
+    }
+
+    fn frames(frame_size: usize, hop_size: usize, window: Option<QWindow>) -> FrameParams {
+        FrameParams { frame_size: Some(frame_size), hop_size: Some(hop_size), window }
+    }
+
+    #[test]
+    fn frames_larger_than_chunk() {
+        // 20 stereo samples in frames of 8 (chunk of the test: 4), the last frame is zero padded
+        let x = ramp(40);
+        let lengths = Mutex::new(Vec::new());
+        let gain = |frame: &[f32]| {
+            lengths.lock().unwrap().push(frame.len());
+            frame.iter().map(|sample| sample * 2.0).collect::<Vec<f32>>()
+        };
+        let y = process_frames(&x, 2, 8, 8, &frames(8, 8, None), &gain, false);
+
+        assert_eq!(*lengths.lock().unwrap(), vec![16, 16, 16]);
+        assert_eq!(y.len(), x.len());
+        assert!(x.iter().zip(y.iter()).all(|(x, y)| *y == x * 2.0));
+    }
+
+    #[test]
+    fn frames_smaller_than_chunk() {
+        // frames of 3 samples, closure outputs of the wrong length are truncated or zero padded
+        let x = ramp(10);
+        let y = process_frames(&x, 1, 3, 3, &frames(3, 3, None), &|frame: &[f32]| frame.to_vec(), true);
+        assert_eq!(y, x);
+
+        let y = process_frames(&x, 1, 3, 3, &frames(3, 3, None), &|frame: &[f32]| frame[..2].to_vec(), false);
+        let expected = x.iter().enumerate().map(|(i, x)| if i % 3 == 2 { 0.0 } else { *x }).collect::<Vec<f32>>();
+        assert_eq!(y, expected);
+    }
+
+    #[test]
+    fn overlap_add_constant() {
+        let x = vec![0.5; 64];
+        let identity = |frame: &[f32]| frame.to_vec();
+
+        // overlapping frames without window are averaged
+        let y = process_frames(&x, 1, 8, 2, &frames(8, 2, None), &identity, false);
+        assert!(y.iter().all(|sample| (sample - 0.5).abs() < 1e-6));
+
+        // windowed frames are normalized by the sum of the windows (the first sample has only a zero weight)
+        let y = process_frames(&x, 1, 16, 4, &frames(16, 4, Some(QWindow::Hanning)), &identity, true);
+        assert_eq!(y[0], 0.0);
+        assert!(y[1..].iter().all(|sample| (sample - 0.5).abs() < 1e-5));
+
+        let x = vec![0.5; 32];
+        let y = process_frames(&x, 2, 8, 4, &frames(8, 4, Some(QWindow::Hanning)), &identity, false);
+        assert_eq!(&y[..2], &[0.0, 0.0]);
+        assert!(y[2..].iter().all(|sample| (sample - 0.5).abs() < 1e-5));
+    }
+
+    #[test]
+    fn keep_exiting_processes() {
+        let (tx, rx) = mpsc::channel::<()>();
+        let t = thread::spawn(move || { let _ = rx.recv(); });
+        let id = t.thread().id();
+        let mut monitor = MonitorProcess::new(false);
+        monitor.add_process(Process::new(t, String::from("TEST"), ProcessState::On));
+
+        // stopped but still running: it is not joined
+        monitor.set_state(&id, ProcessState::Off);
+        monitor.remove_inactive_processes();
+        assert!(monitor.processes.contains_key(&id));
+        assert!(monitor.has_exiting_processes());
+
+        drop(tx);
+        while !monitor.processes[&id].handle.is_finished() {
+            thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert!(!monitor.has_exiting_processes());
+        monitor.remove_inactive_processes();
+        assert!(monitor.processes.is_empty());
+    }
+}
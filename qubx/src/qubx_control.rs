@@ -0,0 +1,350 @@
+#![allow(dead_code)]
+
+use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+use std::sync::{ Arc, Condvar, Mutex };
+use std::thread::{ self, JoinHandle, ThreadId };
+use std::time::Duration;
+
+use crate::qubx_common::{ Process, ProcessState, QubxError };
+use crate::qubx_components::MonitorProcess;
+use crate::qmetrics::{ MetricsSnapshot, ProcessMetrics };
+
+/// Max time a stream thread sleeps between two housekeeping passes (free retired queues, check the device)
+///
+pub(crate) const WAKEUP_INTERVAL: Duration = Duration::from_millis(50);
+
+/// # Process Events
+///
+/// Wakeup shared by the monitor and the stream threads. It is notified on shutdown and on every process state change,
+/// so that the waiting threads block instead of spinning. Each notification increments a counter: a thread waits until
+/// the counter differs from the last value it has seen (no lost wakeups)
+///
+#[derive(Debug, Default)]
+pub(crate) struct ProcessEvents {
+    count: Mutex<u64>,
+    cvar: Condvar
+}
+
+impl ProcessEvents {
+    pub(crate) fn notify(&self) {
+        let mut count = self.count.lock().unwrap();
+        *count = count.wrapping_add(1);
+        self.cvar.notify_all();
+    }
+
+    pub(crate) fn get_count(&self) -> u64 {
+        *self.count.lock().unwrap()
+    }
+
+    /// Block until a new event
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `seen`: last event count seen by the caller
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `u64`: current event count
+    ///
+    pub(crate) fn wait(&self, seen: u64) -> u64 {
+        let count = self.count.lock().unwrap();
+        let count = self.cvar.wait_while(count, |count| *count == seen).unwrap();
+        *count
+    }
+
+    /// Block until a new event or until `timeout` is elapsed
+    ///
+    pub(crate) fn wait_timeout(&self, seen: u64, timeout: Duration) -> u64 {
+        let count = self.count.lock().unwrap();
+        let (count, _) = self.cvar.wait_timeout_while(count, timeout, |count| *count == seen).unwrap();
+        *count
+    }
+}
+
+/// Spawn a new thread of a process. It takes the generation of the new thread (see `ProcessControl`) and returns
+/// once the stream is running (or failed to open)
+///
+pub(crate) type ProcessSpawner = Arc<dyn Fn(usize) -> Result<JoinHandle<()>, QubxError> + Send + Sync>;
+
+/// # Process Control
+///
+/// State shared between a process handle and the threads of the process. Each (re)start creates a new generation:
+/// threads and queues of an older generation are terminated
+///
+#[derive(Debug)]
+pub struct ProcessControl {
+    name: String,
+    generation: AtomicUsize,
+    stopped: AtomicBool,
+    paused: AtomicBool,
+    finished: AtomicBool,
+    thread_id: Mutex<Option<ThreadId>>,
+    monitor: Arc<Mutex<MonitorProcess>>,
+    events: Arc<ProcessEvents>,
+    metrics: Arc<ProcessMetrics>,
+    error: Mutex<Option<QubxError>>
+}
+
+impl ProcessControl {
+    pub(crate) fn new(name: String, monitor: Arc<Mutex<MonitorProcess>>) -> Self {
+        let events = monitor.lock().unwrap().get_events();
+        Self {
+            name,
+            generation: AtomicUsize::new(0),
+            stopped: AtomicBool::new(false),
+            paused: AtomicBool::new(false),
+            finished: AtomicBool::new(false),
+            thread_id: Mutex::new(None),
+            monitor,
+            events,
+            metrics: Arc::new(ProcessMetrics::new()),
+            error: Mutex::new(None)
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    pub(crate) fn get_generation(&self) -> usize {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Check if the threads of generation `generation` must keep running
+    ///
+    #[inline]
+    pub(crate) fn is_alive(&self, generation: usize) -> bool {
+        !self.stopped.load(Ordering::Acquire) && self.get_generation() == generation
+    }
+
+    #[inline]
+    pub(crate) fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Acquire)
+    }
+
+    /// Runtime metrics of the process (see `ProcessMetrics`)
+    ///
+    pub(crate) fn get_metrics(&self) -> &Arc<ProcessMetrics> {
+        &self.metrics
+    }
+
+    /// Events of the process (see `ProcessEvents`)
+    ///
+    pub(crate) fn get_events(&self) -> &ProcessEvents {
+        &self.events
+    }
+
+    /// Record the error that terminated the process (see `ProcessHandle::take_error()`)
+    ///
+    pub(crate) fn set_error(&self, error: QubxError) {
+        *self.error.lock().unwrap() = Some(error);
+    }
+
+    /// Get the process state
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `ProcessState`: `On`, `Paused` or `Off` (stopped or done)
+    ///
+    pub fn get_state(&self) -> ProcessState {
+        if self.stopped.load(Ordering::Acquire) {
+            ProcessState::Off
+        } else if self.is_paused() {
+            ProcessState::Paused
+        } else if self.finished.load(Ordering::Acquire) {
+            ProcessState::Off
+        } else {
+            ProcessState::On
+        }
+    }
+
+    fn report_state(&self) {
+        let state = self.get_state();
+        let id = *self.thread_id.lock().unwrap();
+        if let Some(id) = id {
+            let mut pm = self.monitor.lock().unwrap();
+            pm.set_state(&id, state);
+        }
+    }
+
+    /// Called by the thread of generation `generation` when it ends
+    ///
+    pub(crate) fn exit(&self, generation: usize) {
+        if self.get_generation() == generation {
+            self.finished.store(true, Ordering::Release);
+        }
+
+        let mut pm = self.monitor.lock().unwrap();
+        pm.set_state(&thread::current().id(), ProcessState::Off);
+    }
+}
+
+/// # Process Handle
+///
+/// Handle of a single master, duplex or dsp process (returned by `start()`). The process can be stopped, paused,
+/// resumed or restarted without affecting the other processes. State changes are reported to `MonitorProcess`.
+///
+/// `stop()`: terminate the process (master and duplex streams close their device)
+/// `pause()`: master and duplex streams output silence, the output of a dsp process is held in the master mix
+/// `resume()`: resume a paused process
+/// `restart()`: terminate the process and start it again with the same args (master and duplex streams reopen
+/// their device, dsp processes are processed again from the beginning)
+///
+/// Example:
+///
+/// ```ignore
+/// let master_handle = master_out.start(ProcessArg::NoArgs::<MasterPatchType>)?;
+/// let dsp_handle = dsp_process.start(DspProcessArg::Source::<DspPatchType, DspHybridType>(audio_data))?;
+/// dsp_handle.pause();
+/// dsp_handle.resume();
+/// master_handle.stop();
+/// ```
+///
+#[derive(Clone)]
+pub struct ProcessHandle {
+    control: Arc<ProcessControl>,
+    spawner: ProcessSpawner
+}
+
+impl std::fmt::Debug for ProcessHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProcessHandle").field("control", &self.control).finish()
+    }
+}
+
+impl ProcessHandle {
+    /// Spawn the first thread of the process and register it in the monitor
+    ///
+    pub(crate) fn spawn(control: Arc<ProcessControl>, spawner: ProcessSpawner) -> Result<Self, QubxError> {
+        let handle = Self { control, spawner };
+        handle.spawn_generation(handle.control.get_generation())?;
+        Ok(handle)
+    }
+
+    fn spawn_generation(&self, generation: usize) -> Result<(), QubxError> {
+        // the monitor is locked until the process is registered, so that the thread can not exit before
+        let mut pm = self.control.monitor.lock().unwrap();
+        let t = match (self.spawner)(generation) {
+            Ok(t) => t,
+            Err(e) => {
+                self.control.finished.store(true, Ordering::Release);
+                return Err(e)
+            }
+        };
+        *self.control.thread_id.lock().unwrap() = Some(t.thread().id());
+        pm.add_process(Process::new(t, self.control.name.clone(), ProcessState::On));
+        Ok(())
+    }
+
+    pub fn get_name(&self) -> &str {
+        self.control.get_name()
+    }
+
+    /// Get the process state
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `ProcessState`: `On`, `Paused` or `Off` (stopped or done)
+    ///
+    pub fn get_state(&self) -> ProcessState {
+        self.control.get_state()
+    }
+
+    /// Get the runtime metrics of the process. It never blocks the audio thread
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `MetricsSnapshot`: callback duration percentiles, cpu load, underruns, overruns and queue depth
+    ///
+    pub fn get_metrics(&self) -> MetricsSnapshot {
+        self.control.metrics.snapshot()
+    }
+
+    /// Clear the runtime metrics of the process
+    ///
+    pub fn reset_metrics(&self) {
+        self.control.metrics.reset();
+    }
+
+    /// Take the error reported by the process, if any
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Option<QubxError>`: e.g. `QubxError::QueueFull` that terminated a streaming dsp process (see
+    /// `QueueFullPolicy`) or `QubxError::ChannelMismatch` of a duplex closure returning frames of the wrong length
+    ///
+    pub fn take_error(&self) -> Option<QubxError> {
+        self.control.error.lock().unwrap().take()
+    }
+
+    pub(crate) fn get_control(&self) -> Arc<ProcessControl> {
+        Arc::clone(&self.control)
+    }
+
+    /// Stop the process. The monitor reports it `Off` at once, the thread is joined when it has exited
+    ///
+    pub fn stop(&self) {
+        self.control.paused.store(false, Ordering::Release);
+        self.control.stopped.store(true, Ordering::Release);
+        self.control.report_state();
+        self.control.events.notify();
+    }
+
+    /// Pause the process
+    ///
+    pub fn pause(&self) {
+        if self.control.stopped.load(Ordering::Acquire) { return }
+        self.control.paused.store(true, Ordering::Release);
+        self.control.report_state();
+    }
+
+    /// Resume the process
+    ///
+    pub fn resume(&self) {
+        if !self.control.is_paused() { return }
+        self.control.paused.store(false, Ordering::Release);
+        self.control.report_state();
+    }
+
+    /// Wait for the thread of the previous generation to exit (the device is closed before the new generation opens it)
+    ///
+    fn join_previous(&self) {
+        let Some(id) = self.control.thread_id.lock().unwrap().take() else { return };
+        if id == thread::current().id() { return }
+
+        // already joined by the monitor if it is not registered anymore
+        let previous = self.control.monitor.lock().unwrap().take_process(&id);
+        if let Some(p) = previous {
+            if p.handle.join().is_err() {
+                println!("[ERROR] Thread {:?} panicked!", p.name);
+            }
+        }
+    }
+
+    /// Terminate the process and start it again. The new generation starts once the thread of the previous one has
+    /// exited (master and duplex streams have closed their device)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), QubxError>`: the stream could not be reopened (the process is `Off`)
+    ///
+    pub fn restart(&self) -> Result<(), QubxError> {
+        let generation = self.control.generation.fetch_add(1, Ordering::AcqRel) + 1;
+        self.control.paused.store(false, Ordering::Release);
+        self.control.finished.store(false, Ordering::Release);
+        self.control.stopped.store(false, Ordering::Release);
+        *self.control.error.lock().unwrap() = None;
+        self.control.events.notify(); // wake up the threads of the old generation
+        println!("[PROCESS INFO] Restarting {}...", self.control.name);
+        self.join_previous();
+        self.spawn_generation(generation)
+    }
+}
@@ -1,5 +1,9 @@
-use crate::qubx_common::{ Process, ProcessState, DspProcessArg, ProcessArg };
-use crate::qubx_components::{ DspProcess, DuplexProcess, MasterStreamoutProcess, MonitorProcess };
+use crate::qubx_common::{ DspOptions, DspProcessArg, ProcessArg, QubxError, RecordTarget, StreamQueueParams };
+use crate::qbuffers::AudioObject;
+use crate::qcommand::{ ProcessCommand, ProcessCommandSender };
+use crate::qstrip::ChannelStrip;
+use crate::qubx_components::{ DspProcess, DuplexProcess, InputProcess, MasterStreamoutProcess, MonitorProcess };
+use crate::qubx_control::{ ProcessControl, ProcessHandle };
 use std::sync::{ Arc, Mutex };
 
 pub struct QubxMasterProcess {
@@ -18,20 +22,144 @@ impl QubxMasterProcess {
         }
     }
 
-    pub fn start<F>(&self, arg: ProcessArg<F>)
+    /// Start the master streamout
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<ProcessHandle, QubxError>`: stop, pause, resume or restart the master streamout (see `ProcessHandle`)
+    ///
+    pub fn start<F>(&self, arg: ProcessArg<F>) -> Result<ProcessHandle, QubxError>
     where
         F: for<'a> FnMut(&'a mut [f32]) + Send + Sync + 'static,
     {
         let pclone = Arc::clone(&self.process);
         let p = pclone.lock().unwrap();
         println!("[PROCESS INFO] Starting {} stream-out...", p.name);
-        let t = p.start(arg);
-        let pmonitor = Arc::clone(&self.processes_monitor);
-        let mut pm = pmonitor.lock().unwrap();
-        pm.add_process(Process::new(t, p.name.clone(), ProcessState::On));
+        let control = Arc::new(ProcessControl::new(p.name.clone(), Arc::clone(&self.processes_monitor)));
+        let handle = p.start(arg, control);
+        drop(p);
+
+        handle
+    }
+
+    /// Get the master sample clock
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `u64`: number of sample frames (samples per channel) mixed since the master started
+    ///
+    pub fn get_sample_clock(&self) -> u64 {
+        self.process.lock().unwrap().get_sample_clock()
+    }
+
+    /// Start recording the output of the master streamout (after the master closure). Blocks are handed to a writer
+    /// thread through a wait-free queue, so the recording can run while the master plays
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `target`: `RecordTarget::Memory` or `RecordTarget::File(name)` (32 bit float wav)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), QubxError>`: `QubxError::RecordError` if the master is already recording or the file can not be
+    /// created
+    ///
+    pub fn start_recording(&self, target: RecordTarget) -> Result<(), QubxError> {
+        self.process.lock().unwrap().start_recording(target)
+    }
+
+    /// Stop recording the output of the master streamout
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<Option<AudioObject>, QubxError>`: the recorded mix for `RecordTarget::Memory`, `None` for a file
+    ///
+    pub fn stop_recording(&self) -> Result<Option<AudioObject>, QubxError> {
+        let p = self.process.lock().unwrap();
+        let channels = p.params.outchannels as usize;
+        let sr = p.params.sr as f32;
+        let tap = Arc::clone(&p.tap);
+        drop(p);
+
+        Ok(tap.stop()?.map(|data| AudioObject::new(data, channels, sr)))
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.process.lock().unwrap().is_recording()
+    }
+
+    /// Send a command to the running master streamout: set a param, trigger an event or swap the processor (see
+    /// `ProcessCommand`). It never blocks the audio thread
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `command`: `ProcessCommand`
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), QubxError>`: `QubxError::QueueFull` if the master is not draining its commands
+    ///
+    pub fn send_command(&self, command: ProcessCommand) -> Result<(), QubxError> {
+        let sender = self.process.lock().unwrap().get_command_sender();
+        sender.send(command)
+    }
+
+    /// Get a sender of commands to the master streamout, that can be cloned and moved to control threads
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `ProcessCommandSender`
+    ///
+    pub fn get_command_sender(&self) -> ProcessCommandSender {
+        self.process.lock().unwrap().get_command_sender()
+    }
+
+    /// Hot-swap the patch of the running master streamout with a crossfade, without closing the stream. The old patch is
+    /// dropped outside the audio thread at the end of the crossfade
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `patch`: new master closure (see `MasterPatchType`)
+    /// `crossfade`: crossfade time in sec (0 swaps immediately)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), QubxError>`
+    ///
+    pub fn swap_patch<F>(&self, patch: F, crossfade: f32) -> Result<(), QubxError>
+    where
+        F: for<'a> FnMut(&'a mut [f32]) + Send + 'static,
+    {
+        self.process.lock().unwrap().swap_patch(patch, crossfade)
+    }
 
+    /// Get the audio rendered in offline mode. Block until the render is done
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<AudioObject, QubxError>`: `QubxError::RenderError` if the backend is not a `FileSink`, the process has
+    /// not been started or the render failed (it does not block in these cases)
+    ///
+    pub fn get_rendered_audio(&self) -> Result<AudioObject, QubxError> {
+        let p = self.process.lock().unwrap();
+        if !p.backend.is_offline() { return Err(QubxError::RenderError(String::from("the backend is not a FileSink"))) }
+        let render = Arc::clone(&p.render);
+        let channels = p.params.outchannels as usize;
+        let sr = p.params.sr as f32;
         drop(p);
-        drop(pm);
+
+        Ok(AudioObject::new(render.wait()?, channels, sr))
     }
 }
 
@@ -51,26 +179,186 @@ impl QubxDuplexProcess {
         }
     }
 
-    pub fn start<F>(&self, arg: ProcessArg<F>)
+    /// Start the duplex stream
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<ProcessHandle, QubxError>`: stop, pause, resume or restart the duplex stream (see `ProcessHandle`)
+    ///
+    pub fn start<F>(&self, arg: ProcessArg<F>) -> Result<ProcessHandle, QubxError>
     where
         F: for<'a> FnMut(&'a [f32]) -> Vec<f32> + Send + Sync + 'static,
     {
         let pclone = Arc::clone(&self.process);
         let p = pclone.lock().unwrap();
         println!("[PROCESS INFO] Starting stream-duplex...");
-        let t = p.start(arg);
-        let pmonitor = Arc::clone(&self.processes_monitor);
-        let mut pm = pmonitor.lock().unwrap();
-        pm.add_process(Process::new(
-            t,
-            String::from("DUPLEX STREAM OUT"),
-            ProcessState::On,
-        ));
+        let control = Arc::new(ProcessControl::new(String::from("DUPLEX STREAM OUT"), Arc::clone(&self.processes_monitor)));
+        let handle = p.start(arg, control);
+        drop(p);
+
+        handle
+    }
+
+    /// Send a command to the running duplex stream: set a param, trigger an event or swap the processor (see
+    /// `ProcessCommand`). It never blocks the audio thread
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `command`: `ProcessCommand`
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), QubxError>`: `QubxError::QueueFull` if the duplex stream is not draining its commands
+    ///
+    pub fn send_command(&self, command: ProcessCommand) -> Result<(), QubxError> {
+        let sender = self.process.lock().unwrap().get_command_sender();
+        sender.send(command)
+    }
 
+    /// Get a sender of commands to the duplex stream, that can be cloned and moved to control threads
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `ProcessCommandSender`
+    ///
+    pub fn get_command_sender(&self) -> ProcessCommandSender {
+        self.process.lock().unwrap().get_command_sender()
+    }
+
+    /// Hot-swap the patch of the running duplex stream with a crossfade, without closing the stream. The old patch is
+    /// dropped outside the audio thread at the end of the crossfade
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `patch`: new duplex closure (see `DuplexPatchType`)
+    /// `crossfade`: crossfade time in sec (0 swaps immediately)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<(), QubxError>`
+    ///
+    pub fn swap_patch<F>(&self, patch: F, crossfade: f32) -> Result<(), QubxError>
+    where
+        F: for<'a> FnMut(&'a [f32]) -> Vec<f32> + Send + 'static,
+    {
+        self.process.lock().unwrap().swap_patch(patch, crossfade)
+    }
+
+    /// Get the audio rendered in offline mode. Block until the render is done
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<AudioObject, QubxError>`: `QubxError::RenderError` if the backend is not a `FileSink`, the process has
+    /// not been started or the render failed (it does not block in these cases)
+    ///
+    pub fn get_rendered_audio(&self) -> Result<AudioObject, QubxError> {
+        let p = self.process.lock().unwrap();
+        if !p.backend.is_offline() { return Err(QubxError::RenderError(String::from("the backend is not a FileSink"))) }
+        let render = Arc::clone(&p.render);
+        let channels = p.get_params().outchannels as usize;
+        let sr = p.get_params().sr as f32;
         drop(p);
-        drop(pm);
+
+        Ok(AudioObject::new(render.wait()?, channels, sr))
     }
 }
+
+pub struct QubxInputProcess {
+    processes_monitor: Arc<Mutex<MonitorProcess>>,
+    process: Arc<Mutex<InputProcess>>,
+    target: Mutex<Option<RecordTarget>>,
+}
+
+impl QubxInputProcess {
+    pub fn new(
+        processes_monitor: Arc<Mutex<MonitorProcess>>,
+        process: Arc<Mutex<InputProcess>>,
+    ) -> Self {
+        Self {
+            processes_monitor,
+            process,
+            target: Mutex::new(None),
+        }
+    }
+
+    fn start_process<F>(&self, arg: ProcessArg<F>, target: Option<RecordTarget>) -> Result<ProcessHandle, QubxError>
+    where
+        F: for<'a> FnMut(&'a [f32]) + Send + Sync + 'static,
+    {
+        let pclone = Arc::clone(&self.process);
+        let p = pclone.lock().unwrap();
+        println!("[PROCESS INFO] Starting stream-input...");
+        *self.target.lock().unwrap() = target.clone();
+        let control = Arc::new(ProcessControl::new(String::from("INPUT STREAM"), Arc::clone(&self.processes_monitor)));
+        let handle = p.start(arg, target, control);
+        drop(p);
+
+        handle
+    }
+
+    /// Start the input stream. Each interleaved input block is delivered to the closure
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `arg`: `ProcessArg::PatchSpace::<InputPatchType>(closure)`
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<ProcessHandle, QubxError>`: stop, pause, resume or restart the input stream (see `ProcessHandle`)
+    ///
+    pub fn start<F>(&self, arg: ProcessArg<F>) -> Result<ProcessHandle, QubxError>
+    where
+        F: for<'a> FnMut(&'a [f32]) + Send + Sync + 'static,
+    {
+        self.start_process(arg, None)
+    }
+
+    /// Start the input stream and record it
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `arg`: `ProcessArg::NoArgs` or `ProcessArg::PatchSpace::<InputPatchType>(closure)` (called before recording)
+    /// `target`: `RecordTarget::Memory` (see `get_recording()`) or `RecordTarget::File(name)` (32 bit float wav)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<ProcessHandle, QubxError>`: `QubxError::RecordError` if the file can not be created. Write errors are
+    /// reported by `ProcessHandle::take_error()` when the stream ends
+    ///
+    pub fn record<F>(&self, arg: ProcessArg<F>, target: RecordTarget) -> Result<ProcessHandle, QubxError>
+    where
+        F: for<'a> FnMut(&'a [f32]) + Send + Sync + 'static,
+    {
+        self.start_process(arg, Some(target))
+    }
+
+    /// Get the audio recorded in memory so far. It can be called while recording (the object keeps growing)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Option<AudioObject>`: `None` if the input is not recorded to memory
+    ///
+    pub fn get_recording(&self) -> Option<AudioObject> {
+        if !matches!(*self.target.lock().unwrap(), Some(RecordTarget::Memory)) { return None }
+        let p = self.process.lock().unwrap();
+        let channels = p.get_params().inchannels as usize;
+        let sr = p.get_params().sr as f32;
+        Some(AudioObject::new(p.get_recording(), channels, sr))
+    }
+}
+
 pub struct QubxDspProcess {
     processes_monitor: Arc<Mutex<MonitorProcess>>,
     process: Arc<Mutex<DspProcess>>,
@@ -87,20 +375,95 @@ impl QubxDspProcess {
         }
     }
 
-    // pub fn start<F>(&self, audio_data: Vec<f32>, dsp_function: Option<F>)
-    pub fn start<F1, F2>(&self, args: DspProcessArg<F1, F2>)
+    /// Get the channel strip of the dsp process in the master mix: gain, pan, mute and solo (see `ChannelStrip`)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `ChannelStrip`
+    ///
+    pub fn get_channel_strip(&self) -> ChannelStrip {
+        self.process.lock().unwrap().get_channel_strip()
+    }
+
+    /// Release the output of the dsp process: the running sources fade out and are removed from the master mix without
+    /// clicks (streams stop at the end of the fade-out)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `fade_out`: fade-out time in sec (`None`: fade-out of each source, see `FadeParams`). Sources without fade-out
+    /// are ramped down in 5 ms
+    ///
+    pub fn release(&self, fade_out: Option<f32>) {
+        self.process.lock().unwrap().release(fade_out)
+    }
+
+    /// Start the dsp process
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<ProcessHandle, QubxError>`: stop, pause, resume or restart the dsp process (see `ProcessHandle`)
+    ///
+    pub fn start<F1, F2>(&self, args: DspProcessArg<F1, F2>) -> Result<ProcessHandle, QubxError>
+    where
+        F1: Fn() -> Vec<f32> + Send + Sync + 'static,
+        F2: for<'a> Fn(&'a [f32]) -> Vec<f32> + Send + Sync + 'static,
+    {
+        self.start_with(args, DspOptions::default())
+    }
+
+    /// Start the dsp process with options: start time, frames, source format and fades (see `DspOptions`)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `args`: audio and closure or processor (see `DspProcessArg`)
+    /// `options`: `DspOptions`
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<ProcessHandle, QubxError>`: stop, pause, resume or restart the dsp process (see `ProcessHandle`)
+    ///
+    pub fn start_with<F1, F2>(&self, args: DspProcessArg<F1, F2>, options: DspOptions) -> Result<ProcessHandle, QubxError>
     where
         F1: Fn() -> Vec<f32> + Send + Sync + 'static,
         F2: for<'a> Fn(&'a [f32]) -> Vec<f32> + Send + Sync + 'static,
     {
         let pclone = Arc::clone(&self.process);
         let p = pclone.lock().unwrap();
-        let t = p.start(args);
-        let pmonitor = Arc::clone(&self.processes_monitor);
-        let mut pm = pmonitor.lock().unwrap();
-        pm.add_process(Process::new(t, String::from("DSP"), ProcessState::On));
+        let control = Arc::new(ProcessControl::new(String::from("DSP"), Arc::clone(&self.processes_monitor)));
+        let handle = p.start(args, options, control);
+        drop(p);
 
+        handle
+    }
+    /// Start the dsp process in streaming mode: the closure is called repeatedly to fill the next frame until it
+    /// returns `false` or the process is stopped (see `DspProcess::start_stream()`)
+    ///
+    /// # Args
+    /// ------
+    ///
+    /// `closure`: fill the frame (chunk * outchannels samples, interleaved) and return `true` to keep streaming
+    /// `params`: queue capacity, policy when the queue is full and start time (see `StreamQueueParams`)
+    ///
+    /// # Return
+    /// --------
+    ///
+    /// `Result<ProcessHandle, QubxError>`: stop, pause, resume or restart the stream (see `ProcessHandle`)
+    ///
+    pub fn start_stream<F>(&self, closure: F, params: StreamQueueParams) -> Result<ProcessHandle, QubxError>
+    where
+        F: for<'a> FnMut(&'a mut [f32]) -> bool + Send + 'static,
+    {
+        let pclone = Arc::clone(&self.process);
+        let p = pclone.lock().unwrap();
+        let control = Arc::new(ProcessControl::new(String::from("DSP STREAM"), Arc::clone(&self.processes_monitor)));
+        let handle = p.start_stream(closure, params, control);
         drop(p);
-        drop(pm);
+
+        handle
     }
 }
@@ -1,5 +1,10 @@
+use crate::qprocessor::Processor;
 
 pub type MasterPatchType = Box<dyn FnMut(&mut [f32]) + Send + Sync>;
 pub type DuplexPatchType = Box<dyn FnMut(&[f32]) -> Vec<f32> + Send + Sync>;
+pub type InputPatchType = Box<dyn FnMut(&[f32]) + Send + Sync>;
 pub type DspHybridType = Box<dyn Fn(&[f32]) -> Vec<f32> + Send + Sync>;
-pub type DspPatchType = Box<dyn Fn() -> Vec<f32> + Send + Sync>;
\ No newline at end of file
+pub type DspPatchType = Box<dyn Fn() -> Vec<f32> + Send + Sync>;
+pub type DspStreamType = Box<dyn FnMut(&mut [f32]) -> bool + Send + Sync>;
+pub type BusPatchType = Box<dyn FnMut(&mut [f32]) + Send + Sync>;
+pub type ProcessorType = Box<dyn Processor>;
@@ -0,0 +1,122 @@
+use qubx::*;
+
+const SR: i32 = 1000;
+const CHUNK: u32 = 64;
+
+fn offline_qubx(duration: Option<f32>) -> Qubx {
+    Qubx::new(false, BackendType::FileSink(OfflineParams { target: RenderTarget::Memory, duration }))
+}
+
+fn stream_params(outchannels: u32) -> StreamParameters {
+    StreamParameters { chunk: CHUNK, sr: SR, outchannels, ..Default::default() }
+}
+
+fn ramp(n: usize) -> Vec<f32> {
+    (0..n).map(|i| (i % 100) as f32 / 100.0).collect()
+}
+
+#[test]
+fn render_source() {
+    let mut qubx = offline_qubx(None);
+    let master = qubx.create_master_streamout(String::from("master"), stream_params(1)).unwrap();
+    let dsp = qubx.create_parallel_dsp_process(String::from("master"), false).unwrap();
+
+    let x = ramp(1000);
+    dsp.start(DspProcessArg::Source::<DspPatchType, DspHybridType>(x.clone())).unwrap();
+    master.start(ProcessArg::NoArgs::<MasterPatchType>).unwrap();
+
+    let audio = master.get_rendered_audio().unwrap();
+    assert_eq!(audio.n_channels, 1);
+    assert_eq!(audio.vector_signal.len() % CHUNK as usize, 0);
+    assert_eq!(&audio.vector_signal[..x.len()], &x[..]);
+    assert!(audio.vector_signal[x.len()..].iter().all(|sample| *sample == 0.0));
+}
+
+#[test]
+fn render_scheduled_sources() {
+    let mut qubx = offline_qubx(None);
+    let master = qubx.create_master_streamout(String::from("master"), stream_params(1)).unwrap();
+    let dsp1 = qubx.create_parallel_dsp_process(String::from("master"), false).unwrap();
+    let dsp2 = qubx.create_parallel_dsp_process(String::from("master"), false).unwrap();
+
+    dsp1.start(DspProcessArg::Source::<DspPatchType, DspHybridType>(vec![0.25; 300])).unwrap();
+    let options = DspOptions::default().at(StartTime::AtSample(250));
+    dsp2.start_with(DspProcessArg::Source::<DspPatchType, DspHybridType>(vec![0.5; 100]), options).unwrap();
+    master.start(ProcessArg::NoArgs::<MasterPatchType>).unwrap();
+
+    let y = master.get_rendered_audio().unwrap().vector_signal;
+    assert!(y[..250].iter().all(|sample| *sample == 0.25));
+    assert!(y[250..300].iter().all(|sample| *sample == 0.75));
+    assert!(y[300..350].iter().all(|sample| *sample == 0.5));
+    assert!(y[350..].iter().all(|sample| *sample == 0.0));
+}
+
+#[test]
+fn render_hybrid_space() {
+    let mut qubx = offline_qubx(None);
+    let master = qubx.create_master_streamout(String::from("master"), stream_params(2)).unwrap();
+    let dsp = qubx.create_parallel_dsp_process(String::from("master"), true).unwrap();
+
+    let x = ramp(2000);
+    let gain: DspHybridType = Box::new(|frame| frame.iter().map(|sample| sample * 0.5).collect());
+    dsp.start(DspProcessArg::HybridSpace::<DspPatchType, DspHybridType>(x.clone(), gain)).unwrap();
+    master.start(ProcessArg::NoArgs::<MasterPatchType>).unwrap();
+
+    let y = master.get_rendered_audio().unwrap().vector_signal;
+    assert!(x.iter().zip(y.iter()).all(|(x, y)| *y == x * 0.5));
+}
+
+#[test]
+fn render_hybrid_space_frames() {
+    // frames larger and smaller than the chunk are re-blocked into master chunks
+    for frame_size in [100, 10] {
+        let mut qubx = offline_qubx(None);
+        let master = qubx.create_master_streamout(String::from("master"), stream_params(2)).unwrap();
+        let dsp = qubx.create_parallel_dsp_process(String::from("master"), false).unwrap();
+
+        let x = ramp(2000);
+        let gain: DspHybridType = Box::new(move |frame| {
+            assert_eq!(frame.len(), frame_size * 2);
+            frame.iter().map(|sample| sample * 0.5).collect()
+        });
+        let frames = FrameParams { frame_size: Some(frame_size), ..Default::default() };
+        let options = DspOptions::default().with_frames(frames);
+        dsp.start_with(DspProcessArg::HybridSpace::<DspPatchType, DspHybridType>(x.clone(), gain), options).unwrap();
+        master.start(ProcessArg::NoArgs::<MasterPatchType>).unwrap();
+
+        let y = master.get_rendered_audio().unwrap().vector_signal;
+        assert_eq!(y.len() % (CHUNK as usize * 2), 0);
+        assert!(x.iter().zip(y.iter()).all(|(x, y)| *y == x * 0.5));
+        assert!(y[x.len()..].iter().all(|sample| *sample == 0.0));
+    }
+}
+
+#[test]
+fn render_master_patch_with_duration() {
+    let mut qubx = offline_qubx(Some(0.25));
+    let master = qubx.create_master_streamout(String::from("master"), stream_params(2)).unwrap();
+
+    let patch: MasterPatchType = Box::new(|frame| frame.fill(1.0));
+    master.start(ProcessArg::PatchSpace(patch)).unwrap();
+
+    let y = master.get_rendered_audio().unwrap().vector_signal;
+    assert_eq!(y.len(), 250 * 2);
+    assert!(y.iter().all(|sample| *sample == 1.0));
+}
+
+#[test]
+fn rendered_audio_requires_start() {
+    let mut qubx = offline_qubx(None);
+    let master = qubx.create_master_streamout(String::from("master"), stream_params(1)).unwrap();
+    assert!(matches!(master.get_rendered_audio(), Err(QubxError::RenderError(_))));
+}
+
+#[test]
+fn render_to_unwritable_path() {
+    let target = RenderTarget::File(String::from("/nonexistent/qubx/render"));
+    let mut qubx = Qubx::new(false, BackendType::FileSink(OfflineParams { target, duration: Some(0.25) }));
+    let master = qubx.create_master_streamout(String::from("master"), stream_params(1)).unwrap();
+
+    master.start(ProcessArg::NoArgs::<MasterPatchType>).unwrap();
+    assert!(matches!(master.get_rendered_audio(), Err(QubxError::RenderError(_))));
+}